@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde::Serialize;
+use serde_ub_json::UbjsonRecord;
+
+#[derive(Clone, Serialize, UbjsonRecord)]
+struct Sample {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+fn bench_ser_records(c: &mut Criterion) {
+    let records = (0..100_000)
+        .map(|i| Sample { x: i, y: i * 2, z: i * 3 })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("ser_records");
+
+    group.bench_function(
+        BenchmarkId::new("ser_records_ub_json", "Vec<Sample>"),
+        |b| b.iter(|| {
+            for record in black_box(&records) {
+                serde_ub_json::to_bytes(record).unwrap();
+            }
+        })
+    );
+
+    group.bench_function(
+        BenchmarkId::new("ser_records_ub_json_record", "Vec<Sample>"),
+        |b| b.iter(|| {
+            let mut out = Vec::new();
+            for record in black_box(&records) {
+                out.clear();
+                record.encode(&mut out);
+            }
+        })
+    );
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_ser_records);
+criterion_main!(benches);
@@ -1,6 +1,7 @@
 use criterion::{black_box, criterion_group, Criterion, BenchmarkId};
 use serde::Deserialize;
 
+#[allow(dead_code)]
 #[derive(Clone, Debug, Deserialize)]
 struct SimpleStruct {
     field1: i32,
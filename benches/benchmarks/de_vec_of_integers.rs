@@ -1,4 +1,5 @@
 use criterion::{black_box, criterion_group, Criterion, BenchmarkId};
+#[allow(unused_imports)]
 use serde::Deserialize;
 
 fn bench_de_vec_of_i8(c: &mut Criterion) {
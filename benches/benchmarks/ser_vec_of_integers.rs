@@ -1,6 +1,8 @@
 use criterion::{black_box, criterion_group, Criterion, BenchmarkId};
+#[allow(unused_imports)]
 use serde::Serialize;
 
+#[allow(clippy::needless_range_loop)]
 fn bench_ser_vec_of_i8(c: &mut Criterion) {
     let mut value = vec![0i8; (i8::MAX as usize) + 1];
     for i in 0..value.len() {
@@ -22,6 +24,7 @@ fn bench_ser_vec_of_i8(c: &mut Criterion) {
     group.finish();
 }
 
+#[allow(clippy::needless_range_loop)]
 fn bench_ser_vec_of_i16(c: &mut Criterion) {
     let mut value = vec![0i16; (i8::MAX as usize) + 1];
     for i in 0..value.len() {
@@ -0,0 +1,145 @@
+use proc_macro::TokenStream;
+use proc_macro2::Literal;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Type};
+
+/// Derives `serde_ub_json::UbjsonRecord` for a struct whose named fields all
+/// have the same numeric type.
+///
+/// Keys and the container header are encoded at compile time, so encoding a
+/// record is a series of copies and decoding reads every value at a fixed offset.
+#[proc_macro_derive(UbjsonRecord)]
+pub fn derive_ubjson_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(input, "UbjsonRecord requires named fields")),
+        },
+        _ => return Err(Error::new_spanned(input, "UbjsonRecord can only be derived for structs")),
+    };
+
+    let first = match fields.first() {
+        Some(field) => field,
+        None => return Err(Error::new_spanned(input, "UbjsonRecord requires at least one field")),
+    };
+
+    let (marker, size) = numeric_type(&first.ty)?;
+    for field in fields {
+        if numeric_type(&field.ty)? != (marker, size) {
+            return Err(Error::new_spanned(
+                &field.ty,
+                "UbjsonRecord requires all fields to have the same type",
+            ));
+        }
+    }
+
+    let mut header = vec![b'{', b'$', marker, b'#'];
+    header.extend(len_bytes(fields.len()));
+    let header = Literal::byte_string(&header);
+
+    let mut offset = header_len(fields.len());
+    let mut encode = Vec::new();
+    let mut decode = Vec::new();
+    let mut names = Vec::new();
+
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let name = ident.to_string();
+
+        let mut key = len_bytes(name.len());
+        key.extend_from_slice(name.as_bytes());
+        let key_len = key.len();
+        let key = Literal::byte_string(&key);
+
+        encode.push(quote! {
+            out.extend_from_slice(#key);
+            out.extend_from_slice(&self.#ident.to_be_bytes());
+        });
+
+        let key_offset = offset;
+        let value_offset = offset + key_len;
+        decode.push(quote! {
+            __private::expect(bytes, #key_offset, #key)?;
+            let #ident = <#ty>::from_be_bytes(__private::payload::<#size>(bytes, #value_offset)?);
+        });
+
+        names.push(ident);
+        offset = value_offset + size;
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::serde_ub_json::UbjsonRecord for #ident #ty_generics #where_clause {
+            fn encode(&self, out: &mut ::std::vec::Vec<u8>) {
+                out.reserve(#offset);
+                out.extend_from_slice(#header);
+                #(#encode)*
+            }
+
+            fn decode(bytes: &[u8]) -> ::serde_ub_json::Result<Self> {
+                use ::serde_ub_json::__private;
+                __private::expect(bytes, 0, #header)?;
+                #(#decode)*
+                ::std::result::Result::Ok(#ident { #(#names),* })
+            }
+        }
+    })
+}
+
+fn numeric_type(ty: &Type) -> Result<(u8, usize), Error> {
+    let name = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.get_ident().map(|i| i.to_string()),
+        _ => None,
+    };
+
+    let layout = match name.as_deref() {
+        Some("i8") => (b'i', 1),
+        Some("u8") => (b'U', 1),
+        Some("i16") => (b'I', 2),
+        Some("i32") => (b'l', 4),
+        Some("i64") => (b'L', 8),
+        Some("f32") => (b'd', 4),
+        Some("f64") => (b'D', 8),
+        _ => {
+            return Err(Error::new_spanned(
+                ty,
+                "UbjsonRecord fields must be one of i8, u8, i16, i32, i64, f32, f64",
+            ))
+        }
+    };
+    Ok(layout)
+}
+
+// smallest signed length marker that fits, as written by other UBJSON encoders
+fn len_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if len <= i8::MAX as usize {
+        bytes.push(b'i');
+        bytes.extend_from_slice(&(len as i8).to_be_bytes());
+    } else if len <= i16::MAX as usize {
+        bytes.push(b'I');
+        bytes.extend_from_slice(&(len as i16).to_be_bytes());
+    } else if len <= i32::MAX as usize {
+        bytes.push(b'l');
+        bytes.extend_from_slice(&(len as i32).to_be_bytes());
+    } else {
+        bytes.push(b'L');
+        bytes.extend_from_slice(&(len as i64).to_be_bytes());
+    }
+    bytes
+}
+
+fn header_len(count: usize) -> usize {
+    4 + len_bytes(count).len()
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use serde_ub_json::{from_bytes, smallest_length_marker, to_bytes_with_config, SerializerConfig, UbjsonRecord};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, UbjsonRecord)]
+struct Sample {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, UbjsonRecord)]
+struct Reading {
+    temperature: f64,
+    humidity: f64,
+}
+
+#[test]
+fn encoding_record_produces_strongly_typed_object() {
+    let value = Sample { x: 1, y: -2, z: 3 };
+    let mut out = Vec::new();
+    value.encode(&mut out);
+
+    let mut expected = b"{$l#i".to_vec();
+    expected.extend_from_slice(&3i8.to_be_bytes());
+    for (key, v) in [("x", 1i32), ("y", -2), ("z", 3)] {
+        expected.push(b'i');
+        expected.extend_from_slice(&(key.len() as i8).to_be_bytes());
+        expected.extend_from_slice(key.as_bytes());
+        expected.extend_from_slice(&v.to_be_bytes());
+    }
+
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn encoding_record_produces_same_bytes_as_optimized_serializer() {
+    let config = SerializerConfig {
+        optimize_containers: true,
+        length_policy: Some(smallest_length_marker),
+        ..Default::default()
+    };
+
+    let value = Sample { x: 1, y: -2, z: 3 };
+    let mut out = Vec::new();
+    value.encode(&mut out);
+    assert_eq!(out, to_bytes_with_config(&value, &config).unwrap());
+
+    let value = Reading { temperature: 21.5, humidity: 0.4 };
+    let mut out = Vec::new();
+    value.encode(&mut out);
+    assert_eq!(out, to_bytes_with_config(&value, &config).unwrap());
+}
+
+#[test]
+fn decoding_encoded_record_produces_same_value() {
+    let value = Reading { temperature: 21.5, humidity: 0.4 };
+    let mut out = Vec::new();
+    value.encode(&mut out);
+
+    assert_eq!(Reading::decode(&out).unwrap(), value);
+}
+
+#[test]
+fn deserializing_encoded_record_with_from_bytes_produces_same_value() {
+    let value = Sample { x: 10, y: 20, z: 30 };
+    let mut out = Vec::new();
+    value.encode(&mut out);
+
+    assert_eq!(from_bytes::<'_, Sample>(&out).unwrap(), value);
+}
+
+#[test]
+fn decoding_record_with_other_layout_produces_error() {
+    let value = Sample { x: 10, y: 20, z: 30 };
+    let out = serde_ub_json::to_bytes(&value).unwrap();
+    let error = Sample::decode(&out).unwrap_err();
+    assert_eq!(error.to_string(), "record layout differs at byte 0");
+
+    let mut out = Vec::new();
+    value.encode(&mut out);
+    assert!(Sample::decode(&out[..out.len() - 1]).is_err());
+}
@@ -0,0 +1,217 @@
+//! actix-web support: [`Ubjson<T>`] extracts a UBJSON request body and
+//! responds with a UBJSON body, like `actix_web::web::Json` does for JSON.
+//!
+//! ```ignore
+//! async fn create(item: Ubjson<Item>) -> Ubjson<Created> {
+//!     Ubjson(store(item.into_inner()))
+//! }
+//!
+//! App::new()
+//!     .app_data(UbjsonConfig::default().limit(64 * 1024))
+//!     .route("/items", web::post().to(create))
+//! ```
+use std::future::{poll_fn, Future};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+
+use actix_web::body::BoxBody;
+use actix_web::dev::Payload;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorPayloadTooLarge, ErrorUnsupportedMediaType};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures_core::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::de::from_bytes;
+use crate::ser::to_bytes;
+
+/// Media type of UBJSON bodies.
+pub const CONTENT_TYPE: &str = "application/ubjson";
+
+/// Request body or response of type `T`, encoded as UBJSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ubjson<T>(pub T);
+
+impl<T> Ubjson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Ubjson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Ubjson<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Limits for extracting [`Ubjson`] bodies, registered with `app_data`.
+#[derive(Clone, Debug)]
+pub struct UbjsonConfig {
+    limit: usize,
+    content_type_required: bool,
+}
+
+impl UbjsonConfig {
+    /// Largest accepted body in bytes, 2 MiB by default. Larger bodies are
+    /// rejected with 413 Payload Too Large.
+    pub fn limit(mut self, limit: usize) -> UbjsonConfig {
+        self.limit = limit;
+        self
+    }
+
+    /// Whether requests must be sent as `application/ubjson`, which is the
+    /// default. Others are rejected with 415 Unsupported Media Type.
+    pub fn content_type_required(mut self, required: bool) -> UbjsonConfig {
+        self.content_type_required = required;
+        self
+    }
+}
+
+impl Default for UbjsonConfig {
+    fn default() -> UbjsonConfig {
+        UbjsonConfig {
+            limit: 2 * 1024 * 1024,
+            content_type_required: true,
+        }
+    }
+}
+
+impl<T> FromRequest for Ubjson<T>
+    where
+        T: DeserializeOwned + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Ubjson<T>, actix_web::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let config = req.app_data::<UbjsonConfig>().cloned().unwrap_or_default();
+        let declared = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<usize>().ok());
+
+        let rejection = if config.content_type_required && req.content_type() != CONTENT_TYPE {
+            let message = format!("expected {}, found {:?}", CONTENT_TYPE, req.content_type());
+            Some(ErrorUnsupportedMediaType(message))
+        } else if matches!(declared, Some(len) if len > config.limit) {
+            Some(ErrorPayloadTooLarge("UBJSON payload too large"))
+        } else {
+            None
+        };
+
+        let payload = payload.take();
+        Box::pin(async move {
+            if let Some(error) = rejection {
+                return Err(error);
+            }
+
+            let mut payload = std::pin::pin!(payload);
+            let mut body = Vec::with_capacity(declared.unwrap_or(0));
+            while let Some(chunk) = poll_fn(|cx| payload.as_mut().poll_next(cx)).await {
+                let chunk = chunk?;
+                if body.len() + chunk.len() > config.limit {
+                    return Err(ErrorPayloadTooLarge("UBJSON payload too large"));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            from_bytes(&body).map(Ubjson).map_err(ErrorBadRequest)
+        })
+    }
+}
+
+impl<T> Responder for Ubjson<T>
+    where
+        T: Serialize,
+{
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<BoxBody> {
+        match to_bytes(&self.0) {
+            Ok(body) => HttpResponse::Ok().content_type(CONTENT_TYPE).body(body),
+            Err(e) => HttpResponse::from_error(ErrorInternalServerError(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::block_on::block_on;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: u32,
+        name: String,
+    }
+
+    fn extract(request: TestRequest) -> Result<Ubjson<Item>, actix_web::Error> {
+        let (req, mut payload) = request.to_http_parts();
+        block_on(Ubjson::<Item>::from_request(&req, &mut payload))
+    }
+
+    fn item() -> Item {
+        Item { id: 7, name: "seven".to_string() }
+    }
+
+    #[test]
+    fn extracting_ubjson_body_produces_value() {
+        let request = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE))
+            .set_payload(to_bytes(&item()).unwrap());
+        assert_eq!(extract(request).unwrap().into_inner(), item());
+    }
+
+    #[test]
+    fn extracting_body_with_other_content_type_produces_error() {
+        let request = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(to_bytes(&item()).unwrap());
+        let error = extract(request).unwrap_err();
+        assert_eq!(error.as_response_error().status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn extracting_body_over_limit_produces_error() {
+        let request = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE))
+            .app_data(UbjsonConfig::default().limit(8))
+            .set_payload(to_bytes(&item()).unwrap());
+        let error = extract(request).unwrap_err();
+        assert_eq!(error.as_response_error().status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn extracting_malformed_body_produces_bad_request() {
+        let request = TestRequest::default()
+            .insert_header((header::CONTENT_TYPE, CONTENT_TYPE))
+            .set_payload(&b"{U\x02id"[..]);
+        let error = extract(request).unwrap_err();
+        assert_eq!(error.as_response_error().status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn responding_with_ubjson_produces_encoded_body() {
+        let req = TestRequest::default().to_http_request();
+        let response = Ubjson(item()).respond_to(&req);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), CONTENT_TYPE);
+
+        let body = block_on(actix_web::body::to_bytes(response.into_body())).unwrap();
+        assert_eq!(from_bytes::<Item>(&body).unwrap(), item());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt::{Display, Write};
+
+use crate::de::{is_empty_payload, payload_size};
+use crate::value::Marker;
+use crate::{Error, Result};
+
+/// Renders UBJSON bytes in the block notation of the specification, e.g.
+/// `[{][i][4][name][S][i][3][bob][}]`, for debugging.
+///
+/// Every value, container header and end marker goes on its own line,
+/// prefixed with its byte offset in hex and indented by nesting depth.
+/// Payloads are shown decoded: integers and floats as numbers, strings as
+/// escaped text. Malformed input does not fail the rendering; everything read
+/// so far is kept and an `error:` line marks where decoding stopped.
+pub fn block_notation(bytes: &[u8]) -> String {
+    let mut renderer = Renderer {
+        bytes,
+        pos: 0,
+        depth: 0,
+        line: String::new(),
+        line_start: 0,
+        out: String::new(),
+    };
+    while renderer.pos < bytes.len() {
+        renderer.begin_line();
+        if let Err(e) = renderer.value(None) {
+            renderer.end_line();
+            let _ = writeln!(renderer.out, "{:08x}  error: {}", renderer.pos, e);
+            break;
+        }
+        renderer.end_line();
+    }
+    renderer.out
+}
+
+struct Renderer<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+    // tokens of the line being built and the offset it starts at
+    line: String,
+    line_start: usize,
+    out: String,
+}
+
+impl<'a> Renderer<'a> {
+    fn begin_line(&mut self) {
+        self.line_start = self.pos;
+    }
+
+    fn end_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        let indent = self.depth * 2;
+        let _ = writeln!(self.out, "{:08x}  {:indent$}{}", self.line_start, "", self.line);
+        self.line.clear();
+    }
+
+    fn token(&mut self, token: impl Display) {
+        let _ = write!(self.line, "[{}]", token);
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(Error::InvalidLength)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(Error::Eof)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn peek_marker(&self) -> Result<Marker> {
+        let byte = *self.bytes.get(self.pos).ok_or(Error::Eof)?;
+        Marker::try_from(byte)
+    }
+
+    // reads and shows a marker
+    fn marker(&mut self) -> Result<Marker> {
+        let marker = self.peek_marker()?;
+        self.pos += 1;
+        self.token(marker as u8 as char);
+        Ok(marker)
+    }
+
+    fn int(&mut self, marker: Marker) -> Result<i64> {
+        Ok(match marker {
+            Marker::I8 => i8::from_be_bytes(self.take_array()?).into(),
+            Marker::U8 => u8::from_be_bytes(self.take_array()?).into(),
+            Marker::I16 => i16::from_be_bytes(self.take_array()?).into(),
+            Marker::I32 => i32::from_be_bytes(self.take_array()?).into(),
+            Marker::I64 => i64::from_be_bytes(self.take_array()?),
+            found => return Err(Error::ExpectedLength { found }),
+        })
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        let marker = self.marker()?;
+        let len = self.int(marker)?;
+        self.token(len);
+        usize::try_from(len).map_err(|_| Error::InvalidLength)
+    }
+
+    fn text(&mut self) -> Result<()> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        self.token(String::from_utf8_lossy(bytes).escape_debug());
+        Ok(())
+    }
+
+    // a value, its marker given by a typed container or read here
+    fn value(&mut self, of_type: Option<Marker>) -> Result<()> {
+        let marker = match of_type {
+            Some(marker) => marker,
+            None => self.marker()?,
+        };
+        match marker {
+            Marker::Null | Marker::NoOp | Marker::True | Marker::False => {}
+            Marker::I8 | Marker::U8 | Marker::I16 | Marker::I32 | Marker::I64 => {
+                let value = self.int(marker)?;
+                self.token(value);
+            }
+            Marker::F32 => {
+                let value = f32::from_be_bytes(self.take_array()?);
+                self.token(value);
+            }
+            Marker::F64 => {
+                let value = f64::from_be_bytes(self.take_array()?);
+                self.token(value);
+            }
+            Marker::Char => {
+                let [byte] = self.take_array()?;
+                self.token((byte as char).escape_debug());
+            }
+            Marker::String | Marker::Number => self.text()?,
+            Marker::ArrayStart | Marker::ObjectStart => self.container(marker)?,
+            Marker::ArrayEnd | Marker::ObjectEnd | Marker::Length | Marker::OfType => {
+                return Err(Error::InvalidMarker);
+            }
+        }
+        Ok(())
+    }
+
+    fn container(&mut self, start: Marker) -> Result<()> {
+        let mut of_type = None;
+        let mut count = None;
+        if self.peek_marker()? == Marker::OfType {
+            self.marker()?;
+            of_type = Some(self.marker()?);
+            if self.peek_marker()? != Marker::Length {
+                return Err(Error::Expected(vec![Marker::Length]));
+            }
+        }
+        if self.peek_marker()? == Marker::Length {
+            self.marker()?;
+            count = Some(self.len()?);
+        }
+        self.end_line();
+
+        if let (Some(count), Some(marker)) = (count, of_type) {
+            // elements of this type take no input, so they are summed up
+            if start == Marker::ArrayStart && is_empty_payload(marker) {
+                self.depth += 1;
+                self.begin_line();
+                self.token(marker as u8 as char);
+                let _ = write!(self.line, " x {}", count);
+                self.end_line();
+                self.depth -= 1;
+                return Ok(());
+            }
+            // every element takes at least its payload, keys at least a length
+            let key = if start == Marker::ObjectStart { 2 } else { 0 };
+            let width = payload_size(marker).max(1) + key;
+            if count.saturating_mul(width) > self.bytes.len() - self.pos {
+                return Err(Error::Eof);
+            }
+        }
+
+        let end = match start {
+            Marker::ObjectStart => Marker::ObjectEnd,
+            _ => Marker::ArrayEnd,
+        };
+        self.depth += 1;
+        let mut index = 0;
+        loop {
+            self.begin_line();
+            match count {
+                Some(count) if index == count => break,
+                None if self.peek_marker()? == end => {
+                    self.depth -= 1;
+                    self.marker()?;
+                    return Ok(());
+                }
+                _ => {}
+            }
+            if start == Marker::ObjectStart {
+                self.text()?;
+            }
+            self.value(of_type)?;
+            self.end_line();
+            index += 1;
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering_object_produces_block_notation() {
+        let out = block_notation(b"{i\x04nameSi\x03bob}");
+        assert_eq!(out, "\
+00000000  [{]
+00000001    [i][4][name][S][i][3][bob]
+0000000d  [}]
+");
+    }
+
+    #[test]
+    fn rendering_typed_array_shows_payloads_without_markers() {
+        let out = block_notation(b"[$U#i\x02\x01\xffZ");
+        assert_eq!(out, "\
+00000000  [[][$][U][#][i][2]
+00000006    [1]
+00000007    [255]
+00000008  [Z]
+");
+    }
+
+    #[test]
+    fn rendering_typed_array_without_payloads_sums_up_elements() {
+        let out = block_notation(b"[$Z#L\x00\x00\x01\x00\x00\x00\x00\x00");
+        assert_eq!(out, "\
+00000000  [[][$][Z][#][L][1099511627776]
+0000000d    [Z] x 1099511627776
+");
+    }
+
+    #[test]
+    fn rendering_typed_array_with_count_beyond_input_produces_error() {
+        let out = block_notation(b"[$l#L\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x01");
+        assert_eq!(out, "\
+00000000  [[][$][l][#][L][1099511627776]
+0000000d  error: end of input
+");
+    }
+
+    #[test]
+    fn rendering_nested_containers_indents_them() {
+        let out = block_notation(b"[[Td\x3f\xc0\x00\x00]C\x61]");
+        assert_eq!(out, "\
+00000000  [[]
+00000001    [[]
+00000002      [T]
+00000003      [d][1.5]
+00000008    []]
+00000009    [C][a]
+0000000b  []]
+");
+    }
+
+    #[test]
+    fn rendering_malformed_input_stops_at_error() {
+        let out = block_notation(b"[Si\x05ab");
+        assert_eq!(out, "\
+00000000  [[]
+00000001    [S][i][5]
+00000004  error: end of input
+");
+    }
+}
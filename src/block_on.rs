@@ -0,0 +1,14 @@
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+
+/// Runs `future` to completion on the current thread. Test I/O is in memory
+/// and never pends, so polling until ready does not spin.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Serializing into and deserializing from [`bytes`](::bytes) buffers, for
+//! stacks that pass [`Bytes`] around instead of `Vec<u8>`.
+//!
+//! Binary fields read with [`from_shared`] can share the input instead of
+//! copying it:
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Attachment {
+//!     name: String,
+//!     #[serde(deserialize_with = "serde_ub_json::bytes::shared")]
+//!     data: Bytes,
+//! }
+//!
+//! let attachment: Attachment = serde_ub_json::bytes::from_shared(frame)?;
+//! ```
+use std::cell::RefCell;
+use std::fmt;
+
+use ::bytes::{BufMut, Bytes, BytesMut};
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserializer, Serialize};
+
+use crate::de::from_bytes;
+use crate::read::MAX_PREALLOC;
+use crate::ser::{serialize_into, SerializerConfig};
+use crate::Result;
+
+thread_local! {
+    // input of the innermost `from_shared` call on this thread
+    static SHARED: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+}
+
+/// Serializes a value into a frozen [`Bytes`].
+pub fn to_bytes_mut<T>(value: &T) -> Result<Bytes>
+    where
+        T: ?Sized + Serialize,
+{
+    let mut buf = BytesMut::new();
+    to_buf(value, &mut buf)?;
+    Ok(buf.freeze())
+}
+
+/// Appends the encoding of a value to any [`BufMut`], such as a `BytesMut`
+/// that is reused across values. On error the buffer may hold a partial
+/// encoding.
+pub fn to_buf<T, B>(value: &T, buf: &mut B) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+        B: BufMut,
+{
+    to_buf_with_config(value, buf, &SerializerConfig::default())
+}
+
+pub fn to_buf_with_config<T, B>(value: &T, buf: &mut B, config: &SerializerConfig) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+        B: BufMut,
+{
+    serialize_into(value, buf.writer(), config)
+}
+
+/// Deserializes a value from `bytes`, like `from_bytes`, letting fields read
+/// with [`shared`] hold slices of `bytes` rather than copies.
+pub fn from_shared<T>(bytes: Bytes) -> Result<T>
+    where
+        T: DeserializeOwned,
+{
+    let _guard = SharedGuard {
+        previous: SHARED.with(|shared| shared.replace(Some(bytes.clone()))),
+    };
+    from_bytes(&bytes)
+}
+
+// restores the outer input when `from_shared` returns or unwinds
+struct SharedGuard {
+    previous: Option<Bytes>,
+}
+
+impl Drop for SharedGuard {
+    fn drop(&mut self) {
+        SHARED.with(|shared| *shared.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Deserializes a binary field as [`Bytes`], for use with
+/// `#[serde(deserialize_with = ...)]`.
+///
+/// Under [`from_shared`], a strongly typed `[$U#` array is returned as a slice
+/// of the input without copying; anything else is copied.
+pub fn shared<'de, D>(deserializer: D) -> std::result::Result<Bytes, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    deserializer.deserialize_bytes(SharedVisitor)
+}
+
+struct SharedVisitor;
+
+impl<'de> Visitor<'de> for SharedVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array")
+    }
+
+    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> std::result::Result<Bytes, E> {
+        Ok(slice_shared(v).unwrap_or_else(|| Bytes::copy_from_slice(v)))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Bytes, E> {
+        Ok(Bytes::copy_from_slice(v))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<Bytes, E> {
+        Ok(Bytes::from(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Bytes, A::Error>
+        where
+            A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX_PREALLOC));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Bytes::from(bytes))
+    }
+}
+
+// `v` as a slice of the shared input, if it lies within it
+fn slice_shared(v: &[u8]) -> Option<Bytes> {
+    SHARED.with(|shared| {
+        let shared = shared.borrow();
+        let input = shared.as_ref()?;
+        let start = input.as_ptr() as usize;
+        let offset = (v.as_ptr() as usize).checked_sub(start)?;
+        if offset + v.len() <= input.len() {
+            Some(input.slice_ref(v))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_bytes;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Attachment {
+        name: String,
+        #[serde(deserialize_with = "shared")]
+        data: Bytes,
+    }
+
+    fn attachment() -> Attachment {
+        Attachment {
+            name: "blob".to_string(),
+            data: Bytes::from_static(&[1, 2, 3, 4]),
+        }
+    }
+
+    #[test]
+    fn serializing_to_bytes_mut_produces_same_encoding() {
+        let value = (1u8, "two", vec![3.0f32]);
+        let bytes = to_bytes_mut(&value).unwrap();
+        assert_eq!(&bytes[..], &to_bytes(&value).unwrap()[..]);
+    }
+
+    #[test]
+    fn serializing_to_buf_appends() {
+        let mut buf = BytesMut::from(&b"N"[..]);
+        to_buf(&true, &mut buf).unwrap();
+        to_buf_with_config("hi", &mut buf, &SerializerConfig::canonical()).unwrap();
+        assert_eq!(&buf[..], b"NTSi\x02hi");
+        assert!(from_bytes::<bool>(&buf[1..2]).unwrap());
+    }
+
+    #[test]
+    fn deserializing_shared_input_produces_slice_of_it() {
+        let input = to_bytes_mut(&attachment()).unwrap();
+        let value: Attachment = from_shared(input.clone()).unwrap();
+        assert_eq!(value, attachment());
+
+        let offset = input.len() - value.data.len();
+        assert_eq!(value.data.as_ptr(), input[offset..].as_ptr());
+    }
+
+    #[test]
+    fn deserializing_without_shared_input_copies() {
+        let input = to_bytes(&attachment()).unwrap();
+        let value: Attachment = from_bytes(&input).unwrap();
+        assert_eq!(value, attachment());
+        assert!(!input.as_ptr_range().contains(&value.data.as_ptr()));
+    }
+
+    #[test]
+    fn deserializing_untyped_array_copies() {
+        let input = Bytes::from_static(b"{i\x04nameSi\x04blobi\x04data[U\x01U\x02U\x03U\x04]}");
+        let value: Attachment = from_shared(input).unwrap();
+        assert_eq!(value, attachment());
+    }
+}
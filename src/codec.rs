@@ -0,0 +1,167 @@
+//! [`tokio_util::codec`] support, for using UBJSON values with `Framed`
+//! transports. Values need no extra framing: the decoder finds where each one
+//! ends and waits for more input while a value is incomplete.
+//!
+//! ```ignore
+//! let mut framed = Framed::new(stream, UbjsonCodec::<Message>::new());
+//! framed.send(&reply).await?;
+//! let message = framed.next().await.transpose()?;
+//! ```
+use std::marker::PhantomData;
+
+use ::bytes::{Buf, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::de::from_bytes;
+use crate::incremental::ValueScanner;
+use crate::bytes::to_buf_with_config;
+use crate::ser::SerializerConfig;
+use crate::{Error, Result};
+
+/// Decodes values of type `T` and encodes any serializable value.
+pub struct UbjsonCodec<T> {
+    config: SerializerConfig,
+    max_value_len: Option<usize>,
+    // progress through the value at the front of the read buffer
+    scanner: ValueScanner,
+    output: PhantomData<fn() -> T>,
+}
+
+impl<T> UbjsonCodec<T> {
+    pub fn new() -> UbjsonCodec<T> {
+        UbjsonCodec {
+            config: SerializerConfig::default(),
+            max_value_len: None,
+            scanner: ValueScanner::default(),
+            output: PhantomData,
+        }
+    }
+
+    /// Encodes values under `config`.
+    pub fn with_config(config: SerializerConfig) -> UbjsonCodec<T> {
+        UbjsonCodec {
+            config,
+            ..UbjsonCodec::new()
+        }
+    }
+
+    /// Fails decoding with `Error::LimitExceeded` once a single value would
+    /// need more than `max` bytes, instead of buffering it.
+    pub fn max_value_len(mut self, max: usize) -> UbjsonCodec<T> {
+        self.max_value_len = Some(max);
+        self
+    }
+}
+
+impl<T> Default for UbjsonCodec<T> {
+    fn default() -> UbjsonCodec<T> {
+        UbjsonCodec::new()
+    }
+}
+
+impl<T> Clone for UbjsonCodec<T> {
+    fn clone(&self) -> UbjsonCodec<T> {
+        UbjsonCodec {
+            config: self.config.clone(),
+            max_value_len: self.max_value_len,
+            scanner: self.scanner.clone(),
+            output: PhantomData,
+        }
+    }
+}
+
+impl<T> Decoder for UbjsonCodec<T>
+    where
+        T: DeserializeOwned,
+{
+    type Item = T;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>> {
+        let (noops, len) = self.scanner.split(src, self.max_value_len)?;
+        src.advance(noops);
+        match len {
+            Some(len) => from_bytes(&src.split_to(len)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<T>> {
+        match self.decode(src)? {
+            Some(value) => Ok(Some(value)),
+            None if src.is_empty() => Ok(None),
+            None => Err(Error::UnexpectedEof { context: "value" }),
+        }
+    }
+}
+
+impl<T, I> Encoder<I> for UbjsonCodec<T>
+    where
+        I: Serialize,
+{
+    type Error = Error;
+
+    fn encode(&mut self, item: I, dst: &mut BytesMut) -> Result<()> {
+        to_buf_with_config(&item, dst, &self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::bytes::BufMut;
+
+    use super::*;
+    use crate::to_bytes;
+
+    #[test]
+    fn decoding_partial_buffer_waits_for_more_bytes() {
+        let bytes = to_bytes(&vec!["a".to_string(), "bc".to_string()]).unwrap();
+        let mut codec = UbjsonCodec::<Vec<String>>::new();
+        let mut buf = BytesMut::new();
+        for &b in &bytes[..bytes.len() - 1] {
+            buf.put_u8(b);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+        }
+        buf.put_u8(bytes[bytes.len() - 1]);
+        buf.put_slice(b"NU");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(vec!["a".to_string(), "bc".to_string()]));
+        assert_eq!(&buf[..], b"NU");
+        assert!(matches!(codec.decode_eof(&mut buf), Err(Error::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn encoding_values_round_trips() {
+        let mut codec = UbjsonCodec::<(u8, bool)>::with_config(SerializerConfig::canonical());
+        let mut buf = BytesMut::new();
+        codec.encode((1u8, true), &mut buf).unwrap();
+        codec.encode(&(2u8, false), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some((1, true)));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some((2, false)));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn decoding_large_value_in_chunks_resumes_scanning() {
+        let value: Vec<String> = (0..20_000).map(|i| i.to_string()).collect();
+        let bytes = to_bytes(&value).unwrap();
+        let mut codec = UbjsonCodec::<Vec<String>>::new();
+        let mut buf = BytesMut::new();
+        for chunk in bytes[..bytes.len() - 1].chunks(4 * 1024) {
+            buf.put_slice(chunk);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            assert!(buf.len() - codec.scanner.pos < 16);
+        }
+        buf.put_slice(&bytes[bytes.len() - 1..]);
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some(value));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decoding_value_over_limit_produces_error() {
+        let mut codec = UbjsonCodec::<String>::new().max_value_len(4);
+        let mut buf = BytesMut::from(&b"Si\x05hello"[..]);
+        assert!(matches!(codec.decode(&mut buf), Err(Error::LimitExceeded { .. })));
+    }
+}
@@ -1,11 +1,21 @@
+use std::borrow::Cow;
+use std::io;
+use std::marker::PhantomData;
 use std::mem::size_of;
 use std::str;
+use std::str::FromStr;
 
-use serde::de::{DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::de::value::SeqAccessDeserializer;
+use serde::de::{self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, Unexpected, VariantAccess, Visitor};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
-use crate::{Error, Result};
-use crate::value::Marker;
+use crate::{Error, ErrorKind, Result};
+use crate::error::PathSegment;
+use crate::incremental::ValueScanner;
+use crate::raw::RawValue;
+use crate::read::{ChainedSlices, IoRead, Read, Reference, SliceRead, MAX_EMPTY_ELEMENTS, MAX_PREALLOC};
+use crate::value::{Marker, NUMBER_TOKEN, TYPED_ARRAY_TOKEN};
 
 pub fn from_bytes<'de, T>(bytes: &'de [u8]) -> Result<T>
     where
@@ -16,67 +26,460 @@ pub fn from_bytes<'de, T>(bytes: &'de [u8]) -> Result<T>
     Ok(t)
 }
 
-pub struct Deserializer<'de> {
-    bytes: &'de [u8],
+pub fn from_bytes_with_config<'de, T>(bytes: &'de [u8], config: &DeserializerConfig) -> Result<T>
+    where
+        T: Deserialize<'de>,
+{
+    let mut deserializer = Deserializer::with_config(bytes, config.clone());
+    let t = T::deserialize(&mut deserializer).map_err(|e| e.at_path(&deserializer.path))?;
+    Ok(t)
+}
+
+/// Like [`from_bytes`], but fails with `Error::TrailingData` when anything
+/// other than no-ops follows the value.
+pub fn from_bytes_strict<'de, T>(bytes: &'de [u8]) -> Result<T>
+    where
+        T: Deserialize<'de>,
+{
+    let mut deserializer = Deserializer::new(bytes);
+    let t = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(t)
+}
+
+/// Options controlling which inputs are accepted.
+#[derive(Clone, Debug, Default)]
+pub struct DeserializerConfig {
+    /// Accept common deviations from the specification, such as strongly
+    /// typed containers without a count (`[$i ... ]`), which then run until
+    /// their closing marker, and booleans written as the integers `0` and `1`.
+    ///
+    /// Such an array cannot tell a payload byte `0x5D` (`]`) at an element
+    /// boundary from its closing marker, and ends there. Reading it then fails
+    /// with `Error::InvalidMarker` when the byte after that is not a marker,
+    /// as it cannot follow a real end, instead of returning the shortened
+    /// array.
+    pub lenient: bool,
+    /// Record the keys and indices leading to the value being read, and
+    /// append them to custom errors (`... at .items[3].name`).
+    pub track_path: bool,
+    /// In lenient mode, how many unrecognized marker bytes may be skipped
+    /// before decoding fails. Useful for salvaging damaged input; see
+    /// [`Deserializer::on_skip`] to be told about them.
+    pub skip_budget: usize,
+    /// Also accept unit enum variants written as a bare integer, the index of
+    /// the variant.
+    pub enum_as_index: bool,
+    /// Also accept structs written as arrays of their field values, in
+    /// declaration order.
+    pub struct_from_array: bool,
+    /// Fail with `Error::NonFiniteFloat` when a high-precision number read as
+    /// a float is out of range (e.g. `1e400`), instead of producing infinity.
+    pub reject_non_finite: bool,
+    /// Hand counted, strongly typed arrays (`[$<type>#<count>`) to `Value` as
+    /// `Value::TypedArray`, so that writing the value back reproduces the
+    /// typed form. Other types read such arrays as usual.
+    pub preserve_typed_arrays: bool,
+    /// Read an empty array or object into an `Option` as `None`, whether
+    /// unsized (`[]`, `{}`) or with a count of zero (`[#i\x00`), for
+    /// producers that write absent values that way.
+    pub empty_container_as_none: bool,
+    /// Parse object keys read into integers, bools or floats, e.g. for a
+    /// `BTreeMap<i64, V>`, from their text.
+    pub coerce_int_keys: bool,
+}
+
+/// Deserializes a value from an [`io::Read`] source.
+pub fn from_reader<R, T>(reader: R) -> Result<T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    let mut deserializer = Deserializer::from_read(IoRead::new(reader));
+    let t = T::deserialize(&mut deserializer)?;
+    Ok(t)
+}
+
+/// Decodes the elements of a top-level array one at a time, so that arrays
+/// larger than memory can be processed.
+///
+/// Both counted and unsized arrays are supported. Iteration stops after the
+/// last element, or at the first error.
+pub fn read_array_elements<R, T>(reader: R) -> ArrayElements<R, T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    ArrayElements {
+        de: Deserializer::from_read(IoRead::new(reader)),
+        started: false,
+        remaining: None,
+        of_type: None,
+        done: false,
+        output: PhantomData,
+    }
+}
+
+/// Iterator returned by [`read_array_elements`].
+pub struct ArrayElements<R, T> {
+    de: Deserializer<'static, IoRead<R>>,
+    started: bool,
+    // elements left in a counted array
+    remaining: Option<usize>,
+    of_type: Option<Marker>,
+    done: bool,
+    output: PhantomData<T>,
+}
+
+impl<R, T> ArrayElements<R, T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    fn next_element(&mut self) -> Result<Option<T>> {
+        if !self.started {
+            self.started = true;
+            if self.de.read_marker()? != Marker::ArrayStart {
+                return Err(Error::Expected(vec![Marker::ArrayStart]));
+            }
+            (self.remaining, self.of_type) = self.de.read_container_header()?;
+        }
+
+        match self.remaining {
+            Some(0) => return Ok(None),
+            Some(n) => self.remaining = Some(n - 1),
+            None if self.de.peek_byte()? == Marker::ArrayEnd as u8 => {
+                self.de.read_byte()?;
+                return Ok(None);
+            }
+            None => {}
+        }
+
+        // hint type to the deserializer if set
+        self.de.of_type = self.of_type;
+        T::deserialize(&mut self.de).map(Some)
+    }
+}
+
+impl<R, T> Iterator for ArrayElements<R, T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let element = self.next_element().transpose();
+        if !matches!(element, Some(Ok(_))) {
+            self.done = true;
+        }
+        element
+    }
+}
+
+/// Deserializes the only element of a root array, for producers that wrap the
+/// payload in a single-element array (`[#i\x01<value>`).
+pub fn from_bytes_unwrap_single<'de, T>(bytes: &'de [u8]) -> Result<T>
+    where
+        T: Deserialize<'de>,
+{
+    let mut deserializer = Deserializer::new(bytes);
+    if deserializer.read_marker()? != Marker::ArrayStart {
+        return Err(Error::Expected(vec![Marker::ArrayStart]));
+    }
+
+    let (len, of_type) = deserializer.read_container_header()?;
+    match len {
+        Some(1) => {}
+        None if deserializer.peek_byte()? != Marker::ArrayEnd as u8 => {}
+        _ => return Err(Error::InvalidLength),
+    }
+
+    deserializer.of_type = of_type;
+    let t = T::deserialize(&mut deserializer)?;
+
+    if len.is_none() && deserializer.read_marker()? != Marker::ArrayEnd {
+        return Err(Error::InvalidLength);
+    }
+    Ok(t)
+}
+
+/// Decodes a strongly typed array (`[$<type>#<count>`) of exactly `N`
+/// fixed-width numbers into a stack array, without allocating.
+pub fn from_bytes_array<const N: usize, T>(bytes: &[u8]) -> Result<[T; N]>
+    where
+        T: TypedElement,
+{
+    let mut deserializer = Deserializer::new(bytes);
+    if deserializer.read_marker()? != Marker::ArrayStart {
+        return Err(Error::Expected(vec![Marker::ArrayStart]));
+    }
+
+    match deserializer.read_container_header()? {
+        (Some(len), Some(marker)) if marker == T::MARKER => {
+            if len != N {
+                return Err(Error::InvalidLength);
+            }
+        }
+        _ => return Err(Error::Expected(vec![Marker::OfType])),
+    }
+
+    let mut array = [T::default(); N];
+    let mut data = [0u8; 8];
+    for element in array.iter_mut() {
+        deserializer.read_bytes_mut(&mut data[..T::SIZE])?;
+        *element = T::from_be_slice(&data[..T::SIZE]);
+    }
+    Ok(array)
+}
+
+/// Fixed-width numeric type that can be the element type of a strongly typed
+/// array, see [`from_bytes_array`].
+pub trait TypedElement: Copy + Default + sealed::Sealed {
+    const MARKER: Marker;
+    const SIZE: usize;
+
+    #[doc(hidden)]
+    fn from_be_slice(data: &[u8]) -> Self;
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! typed_element {
+    ($($ty:ty => $marker:expr),*) => {
+        $(
+            impl sealed::Sealed for $ty {}
+
+            impl TypedElement for $ty {
+                const MARKER: Marker = $marker;
+                const SIZE: usize = size_of::<$ty>();
+
+                fn from_be_slice(data: &[u8]) -> Self {
+                    let mut bytes = [0u8; size_of::<$ty>()];
+                    bytes.copy_from_slice(data);
+                    <$ty>::from_be_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+typed_element! {
+    i8 => Marker::I8,
+    u8 => Marker::U8,
+    i16 => Marker::I16,
+    i32 => Marker::I32,
+    i64 => Marker::I64,
+    f32 => Marker::F32,
+    f64 => Marker::F64
+}
+
+/// Decodes a length (a length marker followed by its payload, as found after
+/// `#` or in front of string data) and returns it with the remaining input.
+pub fn parse_len(bytes: &[u8]) -> Result<(usize, &[u8])> {
+    let mut deserializer = Deserializer::new(bytes);
+    let len = deserializer.read_len()?;
+    Ok((len, deserializer.read.remaining()))
+}
+
+/// Counts the complete top-level values in `bytes` without decoding them.
+/// NoOp markers between values are skipped; a value cut off at the end of the
+/// buffer is an error.
+pub fn count_values(bytes: &[u8]) -> Result<usize> {
+    let mut deserializer = Deserializer::new(bytes);
+    let mut raw = Vec::new();
+    let mut count = 0;
+    loop {
+        match deserializer.read.remaining().first() {
+            None => return Ok(count),
+            Some(&b) if b == Marker::NoOp as u8 => {
+                deserializer.read_byte()?;
+            }
+            Some(_) => {
+                raw.clear();
+                deserializer.read_raw(&mut raw)?;
+                count += 1;
+            }
+        }
+    }
+}
+
+/// Deserializes a value from input split into several slices, without
+/// concatenating them first.
+pub fn from_slices<'de, T>(segments: &[&'de [u8]]) -> Result<T>
+    where
+        T: Deserialize<'de>,
+{
+    let mut deserializer = Deserializer::from_read(ChainedSlices::new(segments));
+    let t = T::deserialize(&mut deserializer)?;
+    Ok(t)
+}
+
+type SkipHook<'de> = Box<dyn FnMut(usize, u8) + 'de>;
+type UnknownFieldHook<'de> = Box<dyn FnMut(&str, RawValue) + 'de>;
+
+pub struct Deserializer<'de, R = SliceRead<'de>> {
+    read: R,
+    config: DeserializerConfig,
+    scratch: Vec<u8>,
     of_type: Option<Marker>,
+    path: Vec<PathSegment>,
+    skipped: usize,
+    on_skip: Option<SkipHook<'de>>,
+    on_unknown_field: Option<UnknownFieldHook<'de>>,
+    // key of the object entry being read, kept for `on_unknown_field` when
+    // the path does not hold it
+    field: Option<Cow<'de, str>>,
+    // header of the container whose start marker is handed on in `of_type`,
+    // read ahead by `deserialize_option`
+    header: Option<(Option<usize>, Option<Marker>)>,
+    lifetime: PhantomData<&'de ()>,
 }
 
 impl<'de> Deserializer<'de> {
     pub fn new(bytes: &'de [u8]) -> Deserializer<'de> {
+        Deserializer::from_read(SliceRead::new(bytes))
+    }
+
+    pub fn with_config(bytes: &'de [u8], config: DeserializerConfig) -> Deserializer<'de> {
+        Deserializer::from_read_with_config(SliceRead::new(bytes), config)
+    }
+
+    /// Turns the deserializer into an iterator over concatenated top-level values.
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter<T>(self) -> StreamDeserializer<'de, T>
+        where
+            T: Deserialize<'de>,
+    {
+        StreamDeserializer::new(self)
+    }
+}
+
+impl<'de, R> Deserializer<'de, R>
+    where
+        R: Read<'de>,
+{
+    pub fn from_read(read: R) -> Deserializer<'de, R> {
+        Deserializer::from_read_with_config(read, DeserializerConfig::default())
+    }
+
+    pub fn from_read_with_config(read: R, config: DeserializerConfig) -> Deserializer<'de, R> {
         Deserializer {
-            bytes,
+            read,
+            config,
+            scratch: Vec::new(),
             of_type: None,
+            path: Vec::new(),
+            skipped: 0,
+            on_skip: None,
+            on_unknown_field: None,
+            field: None,
+            header: None,
+            lifetime: PhantomData,
         }
     }
 
-    fn peek_byte(&self) -> Result<u8> {
-        if self.bytes.len() > 0 {
-            let byte = self.bytes[0];
-            Ok(byte)
-        } else {
-            Err(Error::Eof)
+    /// Calls `f` with the offset and value of every byte skipped in lenient
+    /// mode, see `DeserializerConfig::skip_budget`.
+    pub fn on_skip<F>(mut self, f: F) -> Self
+        where
+            F: FnMut(usize, u8) + 'de,
+    {
+        self.on_skip = Some(Box::new(f));
+        self
+    }
+
+    /// Calls `f` with the key and encoded value of every object entry whose
+    /// value is ignored, such as fields missing from the target struct,
+    /// before the value is skipped.
+    pub fn on_unknown_field<F>(mut self, f: F) -> Self
+        where
+            F: FnMut(&str, RawValue) + 'de,
+    {
+        self.on_unknown_field = Some(Box::new(f));
+        self
+    }
+
+    /// Checks that nothing but no-ops follows the values read so far, failing
+    /// with `Error::TrailingData` otherwise. Call it once the input should be
+    /// exhausted to catch concatenated or corrupted data.
+    pub fn end(&mut self) -> Result<()> {
+        loop {
+            match self.peek_byte() {
+                Ok(byte) if byte == Marker::NoOp as u8 => {
+                    self.read_byte()?;
+                }
+                Ok(_) => return Err(Error::TrailingData),
+                Err(Error::Eof) => return Ok(()),
+                Err(e) => return Err(e),
+            }
         }
     }
 
-    fn read_byte(&mut self) -> Result<u8> {
-        if self.bytes.len() > 0 {
-            let byte = self.bytes[0];
-            self.bytes = &self.bytes[1..];
-            Ok(byte)
-        } else {
-            Err(Error::Eof)
+    fn enter(&mut self, segment: PathSegment) {
+        if self.config.track_path {
+            self.path.push(segment);
         }
     }
 
-    fn read_bytes_mut(&mut self, data: &mut [u8]) -> Result<()> {
-        let len = data.len();
-        if self.bytes.len() < len {
-            return Err(Error::Eof);
+    fn leave(&mut self) {
+        if self.config.track_path {
+            self.path.pop();
         }
-        data.copy_from_slice(&self.bytes[..len]);
-        self.bytes = &self.bytes[len..];
-        Ok(())
     }
 
-    fn read_bytes(&mut self, len: usize) -> Result<&'de [u8]> {
-        if self.bytes.len() < len {
-            return Err(Error::Eof);
+    fn peek_byte(&mut self) -> Result<u8> {
+        self.read.peek_byte()
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        self.read.read_byte()
+    }
+
+    fn read_bytes_mut(&mut self, data: &mut [u8]) -> Result<()> {
+        self.read.read_into(data)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Reference<'de, '_>> {
+        self.read.read_slice(len, &mut self.scratch)
+    }
+
+    pub(crate) fn peek_marker(&mut self) -> Result<Marker> {
+        loop {
+            let byte = self.peek_byte()?;
+            match Marker::try_from(byte) {
+                Ok(marker) => return Ok(marker),
+                Err(e) => {
+                    self.skip_junk(byte, e)?;
+                    self.read_byte()?;
+                }
+            }
         }
-        let data = &self.bytes[..len];
-        self.bytes = &self.bytes[len..];
-        Ok(data)
     }
 
-    fn peek_marker(&self) -> Result<Marker> {
-        let byte = self.peek_byte()?;
-        let marker = Marker::try_from(byte)?;
+    pub(crate) fn read_marker(&mut self) -> Result<Marker> {
+        let marker = self.peek_marker()?;
+        self.read_byte()?;
         Ok(marker)
     }
 
-    fn read_marker(&mut self) -> Result<Marker> {
-        let byte = self.read_byte()?;
-        let marker = Marker::try_from(byte)?;
-        Ok(marker)
+    // lets an unrecognized marker byte be skipped while the budget lasts
+    fn skip_junk(&mut self, byte: u8, err: Error) -> Result<()> {
+        if !self.config.lenient || self.skipped >= self.config.skip_budget {
+            return Err(err);
+        }
+        self.skipped += 1;
+        let offset = self.read.offset();
+        if let Some(on_skip) = self.on_skip.as_mut() {
+            on_skip(offset, byte);
+        }
+        Ok(())
     }
 
     fn take_or_read_marker(&mut self) -> Result<Marker> {
@@ -87,14 +490,99 @@ impl<'de> Deserializer<'de> {
     }
 
     fn read_len(&mut self) -> Result<usize> {
-        let size = match self.read_marker()? {
-            Marker::I8 => self.read_i8()? as usize,
-            Marker::I16 => self.read_i16()? as usize,
-            Marker::I32 => self.read_i32()? as usize,
-            Marker::I64 => self.read_i64()? as usize,
-            _ => return Err(Error::ExpectedLength),
+        match self.read_marker()? {
+            Marker::Number if self.config.lenient => {
+                // length given as the digits of a high-precision number
+                let marker = self.read_marker()?;
+                let size = self.read_int_len(marker)?;
+                let digits = self.read_bytes(size)?;
+                parse_len_digits(&digits)
+            }
+            marker => self.read_int_len(marker),
+        }
+    }
+
+    fn read_int_len(&mut self, marker: Marker) -> Result<usize> {
+        let size = match marker {
+            Marker::I8 => self.read_i8()? as i64,
+            Marker::U8 => self.read_u8()? as i64,
+            Marker::I16 => self.read_i16()? as i64,
+            Marker::I32 => self.read_i32()? as i64,
+            Marker::I64 => self.read_i64()?,
+            found => return Err(Error::ExpectedLength { found }),
+        };
+        // negative or too large for this platform
+        usize::try_from(size).map_err(|_| Error::InvalidLength)
+    }
+
+    pub(crate) fn read_container_header(&mut self) -> Result<(Option<usize>, Option<Marker>)> {
+        if let Some(header) = self.header.take() {
+            return Ok(header);
+        }
+        let header = match self.peek_marker()? {
+            Marker::OfType => {
+                // both type and length are specified
+                self.read_marker()?;
+                let marker = self.read_marker()?;
+                match self.peek_marker() {
+                    Ok(Marker::Length) => {
+                        self.read_marker()?;
+                        let len = self.read_len()?;
+                        self.check_count(len, Some(marker))?;
+                        (Some(len), Some(marker))
+                    }
+                    // type without count, runs until the closing marker
+                    _ if self.config.lenient => (None, Some(marker)),
+                    _ => return Err(Error::Expected(vec![Marker::Length])),
+                }
+            }
+            Marker::Length => {
+                // only length is specified
+                self.read_marker()?;
+                let len = self.read_len()?;
+                self.check_count(len, None)?;
+                (Some(len), None)
+            }
+            _ => (None, None), // neither type nor length are specified
+        };
+        Ok(header)
+    }
+
+    // Fails before anything is allocated for a count the rest of the input
+    // cannot hold, when its length is known: every element takes at least its
+    // payload, or its marker in containers without a type. Reported like
+    // truncated input, since the missing bytes may still arrive. Elements
+    // without a payload take no input at all, so their count is capped
+    // instead.
+    fn check_count(&self, count: usize, of_type: Option<Marker>) -> Result<()> {
+        let width = match of_type {
+            Some(marker) if is_empty_payload(marker) => {
+                if count > MAX_EMPTY_ELEMENTS {
+                    return Err(Error::LimitExceeded { limit: "max_empty_elements" });
+                }
+                return Ok(());
+            }
+            Some(Marker::I16) => 2,
+            Some(Marker::I32 | Marker::F32) => 4,
+            Some(Marker::I64 | Marker::F64) => 8,
+            _ => 1,
+        };
+        match self.read.remaining_len() {
+            Some(remaining) if count.saturating_mul(width) > remaining => Err(Error::Eof),
+            _ => Ok(()),
+        }
+    }
+
+    // payload of a signed integer whose marker has already been read
+    fn read_signed(&mut self, marker: Marker) -> Result<i64> {
+        let value = match marker {
+            Marker::I8 => self.read_i8()? as i64,
+            Marker::I16 => self.read_i16()? as i64,
+            Marker::I32 => self.read_i32()? as i64,
+            Marker::I64 => self.read_i64()?,
+            _ => return Err(Error::InvalidMarker),
         };
-        Ok(size)
+        Ok(value)
     }
 
     fn read_u8(&mut self) -> Result<u8> {
@@ -141,8 +629,7 @@ impl<'de> Deserializer<'de> {
 
     fn read_string(&mut self) -> Result<String> {
         let size = self.read_len()?;
-        let mut data = vec![0; size];
-        self.read_bytes_mut(&mut data)?;
+        let data = self.read_bytes(size)?.to_vec();
 
         match String::from_utf8(data) {
             Ok(s) => Ok(s),
@@ -150,97 +637,356 @@ impl<'de> Deserializer<'de> {
         }
     }
 
-    fn read_str(&mut self) -> Result<&'de str> {
+    pub(crate) fn read_str(&mut self) -> Result<Reference<'de, '_, str>> {
         let size = self.read_len()?;
         let data = self.read_bytes(size)?;
+        as_str(data)
+    }
 
-        match str::from_utf8(data) {
-            Ok(s) => Ok(s),
-            Err(_) => Err(Error::InvalidString),
+    // copies the next value into `raw` without interpreting it
+    fn read_raw(&mut self, raw: &mut Vec<u8>) -> Result<()> {
+        let marker = self.take_or_read_marker()?;
+        raw.push(marker as u8);
+        self.read_raw_payload(marker, raw)
+    }
+
+    pub(crate) fn read_raw_payload(&mut self, marker: Marker, raw: &mut Vec<u8>) -> Result<()> {
+        match marker {
+            Marker::Null | Marker::NoOp | Marker::True | Marker::False => Ok(()),
+            Marker::Number | Marker::String => {
+                let len = self.read_raw_len(raw)?;
+                self.copy_raw(len, raw)
+            }
+            Marker::ArrayStart => self.read_raw_container(Marker::ArrayEnd, raw),
+            Marker::ObjectStart => self.read_raw_container(Marker::ObjectEnd, raw),
+            m if payload_size(m) > 0 => self.copy_raw(payload_size(m), raw),
+            _ => Err(Error::InvalidMarker),
         }
     }
-}
 
-impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
-    type Error = Error;
+    fn read_raw_len(&mut self, raw: &mut Vec<u8>) -> Result<usize> {
+        let marker = self.read_marker()?;
+        if marker == Marker::Number && self.config.lenient {
+            raw.push(marker as u8);
+            let marker = self.read_marker()?;
+            let size = self.read_raw_int_len(marker, raw)?;
+            let start = raw.len();
+            self.copy_raw(size, raw)?;
+            return parse_len_digits(&raw[start..]);
+        }
+        self.read_raw_int_len(marker, raw)
+    }
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
-        where
-            V: Visitor<'de>,
-    {
-        match self.peek_marker()? {
-            Marker::Null => self.deserialize_option(visitor),
-            Marker::NoOp => self.deserialize_unit(visitor),
-            Marker::True => self.deserialize_bool(visitor),
-            Marker::False => self.deserialize_bool(visitor),
-            _ => Err(Error::InvalidMarker),
+    fn read_raw_int_len(&mut self, marker: Marker, raw: &mut Vec<u8>) -> Result<usize> {
+        if !marker.is_integer() {
+            return Err(Error::ExpectedLength { found: marker });
         }
+        let start = raw.len();
+        raw.push(marker as u8);
+        self.copy_raw(payload_size(marker), raw)?;
+        parse_len(&raw[start..]).map(|(len, _)| len)
     }
 
-    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
-        where
-            V: Visitor<'de>,
-    {
-        match self.take_or_read_marker()? {
-            Marker::True => visitor.visit_bool(true),
-            Marker::False => visitor.visit_bool(false),
-            _ => Err(Error::Expected(vec![Marker::True, Marker::False])),
+    fn read_raw_container(&mut self, end: Marker, raw: &mut Vec<u8>) -> Result<()> {
+        if let Some((len, of_type)) = self.header.take() {
+            // written back as read, with the count as `L`
+            if let Some(marker) = of_type {
+                raw.extend_from_slice(&[Marker::OfType as u8, marker as u8]);
+            }
+            if let Some(len) = len {
+                raw.extend_from_slice(&[Marker::Length as u8, Marker::I64 as u8]);
+                raw.extend_from_slice(&(len as i64).to_be_bytes());
+            }
+            return self.read_raw_elements(end, len, of_type, raw);
+        }
+
+        let mut of_type = None;
+        if self.peek_byte()? == Marker::OfType as u8 {
+            raw.push(self.read_byte()?);
+            let marker = self.read_marker()?;
+            raw.push(marker as u8);
+            of_type = Some(marker);
+        }
+
+        let mut len = None;
+        if self.peek_byte()? == Marker::Length as u8 {
+            raw.push(self.read_byte()?);
+            let count = self.read_raw_len(raw)?;
+            self.check_count(count, of_type)?;
+            len = Some(count);
+        } else if of_type.is_some() && !self.config.lenient {
+            return Err(Error::Expected(vec![Marker::Length]));
         }
+        self.read_raw_elements(end, len, of_type, raw)
     }
 
-    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
-        where
-            V: Visitor<'de>,
-    {
-        match self.take_or_read_marker()? {
-            Marker::I8 => visitor.visit_i8(self.read_i8()?),
-            _ => Err(Error::Expected(vec![Marker::I8])),
+    fn read_raw_elements(
+        &mut self,
+        end: Marker,
+        len: Option<usize>,
+        of_type: Option<Marker>,
+        raw: &mut Vec<u8>,
+    ) -> Result<()> {
+        let mut remaining = len;
+        loop {
+            match remaining {
+                Some(0) => return Ok(()),
+                Some(n) => remaining = Some(n - 1),
+                None if self.peek_byte()? == end as u8 => {
+                    raw.push(self.read_byte()?);
+                    return Ok(());
+                }
+                None => {}
+            }
+
+            if end == Marker::ObjectEnd {
+                let len = self.read_raw_len(raw)?;
+                self.copy_raw(len, raw)?;
+            }
+
+            match of_type {
+                Some(marker) => self.read_raw_payload(marker, raw)?,
+                None => self.read_raw(raw)?,
+            }
         }
     }
 
-    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
-        where
-            V: Visitor<'de>,
-    {
-        match self.take_or_read_marker()? {
-            Marker::I16 => visitor.visit_i16(self.read_i16()?),
-            Marker::I8 => visitor.visit_i16((self.read_i8()?) as i16),
-            _ => Err(Error::Expected(vec![Marker::I16, Marker::I8])),
+    fn copy_raw(&mut self, len: usize, raw: &mut Vec<u8>) -> Result<()> {
+        let data = self.read_bytes(len)?;
+        raw.extend_from_slice(&data);
+        Ok(())
+    }
+
+    // single `U` element of an untyped byte array
+    fn read_byte_value(&mut self) -> Result<u8> {
+        match self.read_marker()? {
+            Marker::U8 => self.read_u8(),
+            _ => Err(Error::Expected(vec![Marker::U8])),
         }
     }
 
-    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
+    // array start marker has already been consumed
+    fn visit_array<V>(&mut self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        match self.take_or_read_marker()? {
-            Marker::I32 => visitor.visit_i32(self.read_i32()?),
-            Marker::I16 => visitor.visit_i32((self.read_i16()?) as i32),
-            Marker::I8 => visitor.visit_i32((self.read_i8()?) as i32),
-            _ => Err(Error::Expected(vec![Marker::I32, Marker::I16, Marker::I8])),
-        }
+        let (len, of_type) = self.read_container_header()?;
+
+        visitor.visit_seq(ArrayAccess {
+            de: self,
+            len,
+            of_type,
+            trailer: if len.is_some() { None } else { Some(Marker::ArrayEnd) },
+            index: 0,
+        })
     }
 
-    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
+    fn visit_any_array<V>(&mut self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        match self.take_or_read_marker()? {
-            Marker::I64 => visitor.visit_i64(self.read_i64()?),
-            Marker::I32 => visitor.visit_i64((self.read_i32()?) as i64),
-            Marker::I16 => visitor.visit_i64((self.read_i16()?) as i64),
-            Marker::I8 => visitor.visit_i64((self.read_i8()?) as i64),
-            _ => Err(Error::Expected(vec![Marker::I64, Marker::I32, Marker::I16, Marker::I8])),
+        let (len, of_type) = self.read_container_header()?;
+        let elements = ArrayAccess {
+            de: self,
+            len,
+            of_type,
+            trailer: if len.is_some() { None } else { Some(Marker::ArrayEnd) },
+            index: 0,
+        };
+
+        match (len, of_type) {
+            (Some(_), Some(marker)) => visitor.visit_map(TypedArrayAccess {
+                marker: Some(marker),
+                elements: Some(elements),
+            }),
+            _ => visitor.visit_seq(elements),
         }
     }
 
-    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+    // array start marker has already been consumed, elements are the fields in order
+    fn visit_positional_struct<V>(&mut self, fields: usize, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        match self.take_or_read_marker()? {
-            Marker::U8 => visitor.visit_u8(self.read_u8()?),
-            _ => Err(Error::Expected(vec![Marker::U8])),
+        let (len, of_type) = self.read_container_header()?;
+        if matches!(len, Some(len) if len != fields) {
+            return Err(Error::InvalidLength);
+        }
+
+        let mut access = ArrayAccess {
+            de: self,
+            len,
+            of_type,
+            trailer: if len.is_some() { None } else { Some(Marker::ArrayEnd) },
+            index: 0,
+        };
+        let value = visitor.visit_seq(&mut access)?;
+
+        // every element must have been a field
+        match access.len {
+            Some(0) => Ok(value),
+            _ => Err(Error::InvalidLength),
+        }
+    }
+
+    // object start marker has already been consumed
+    fn visit_object<V>(&mut self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        let (len, of_type) = self.read_container_header()?;
+
+        visitor.visit_map(ObjectAccess {
+            de: self,
+            len,
+            of_type,
+            trailer: if len.is_some() { None } else { Some(Marker::ObjectEnd) },
+            key: None,
+        })
+    }
+
+    fn read_number<T>(&mut self) -> Result<T>
+        where
+            T: FromStr,
+    {
+        // high-precision numbers are length-prefixed like strings
+        let s = self.read_str()?;
+        s.parse::<T>().map_err(|_| Error::InvalidNumber)
+    }
+}
+
+impl<'de, R> serde::de::Deserializer<'de> for &mut Deserializer<'de, R>
+    where
+        R: Read<'de>,
+{
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::Null => visitor.visit_unit(),
+            Marker::NoOp => self.deserialize_any(visitor), // no-op carries no value
+            Marker::True => visitor.visit_bool(true),
+            Marker::False => visitor.visit_bool(false),
+            Marker::I8 => visitor.visit_i8(self.read_i8()?),
+            Marker::U8 => visitor.visit_u8(self.read_u8()?),
+            Marker::I16 => visitor.visit_i16(self.read_i16()?),
+            Marker::I32 => visitor.visit_i32(self.read_i32()?),
+            Marker::I64 => visitor.visit_i64(self.read_i64()?),
+            Marker::F32 => visitor.visit_f32(self.read_f32()?),
+            Marker::F64 => visitor.visit_f64(self.read_f64()?),
+            Marker::Number => {
+                // exposed as a single-entry map so that Value can keep the digits
+                let s = self.read_str()?;
+                visitor.visit_map(NumberAccess { number: Some(&s) })
+            }
+            Marker::Char => {
+                let c = self.read_byte()?;
+                visitor.visit_char(c as char)
+            }
+            Marker::String => visit_str(visitor, self.read_str()?),
+            Marker::ArrayStart if self.config.preserve_typed_arrays => self.visit_any_array(visitor),
+            Marker::ArrayStart => self.visit_array(visitor),
+            Marker::ObjectStart => self.visit_object(visitor),
+            _ => Err(Error::InvalidMarker),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        // a `U` element type means the array holds zeros and ones
+        let typed = self.of_type.is_some();
+        match self.take_or_read_marker()? {
+            Marker::True => visitor.visit_bool(true),
+            Marker::False => visitor.visit_bool(false),
+            Marker::U8 if typed || self.config.lenient => match self.read_u8()? {
+                0 => visitor.visit_bool(false),
+                1 => visitor.visit_bool(true),
+                n => Err(de::Error::invalid_value(Unexpected::Unsigned(n as u64), &"0 or 1")),
+            },
+            Marker::I8 if self.config.lenient => match self.read_i8()? {
+                0 => visitor.visit_bool(false),
+                1 => visitor.visit_bool(true),
+                n => Err(de::Error::invalid_value(Unexpected::Signed(n as i64), &"0 or 1")),
+            },
+            _ => Err(Error::Expected(vec![Marker::True, Marker::False])),
+        }
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::I8 => visitor.visit_i8(self.read_i8()?),
+            _ => Err(Error::Expected(vec![Marker::I8])),
+        }
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::I16 => visitor.visit_i16(self.read_i16()?),
+            Marker::I8 => visitor.visit_i16((self.read_i8()?) as i16),
+            _ => Err(Error::Expected(vec![Marker::I16, Marker::I8])),
+        }
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::I32 => visitor.visit_i32(self.read_i32()?),
+            Marker::I16 => visitor.visit_i32((self.read_i16()?) as i32),
+            Marker::I8 => visitor.visit_i32((self.read_i8()?) as i32),
+            _ => Err(Error::Expected(vec![Marker::I32, Marker::I16, Marker::I8])),
+        }
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::I64 => visitor.visit_i64(self.read_i64()?),
+            Marker::I32 => visitor.visit_i64((self.read_i32()?) as i64),
+            Marker::I16 => visitor.visit_i64((self.read_i16()?) as i64),
+            Marker::I8 => visitor.visit_i64((self.read_i8()?) as i64),
+            Marker::Number => visitor.visit_i64(self.read_number()?),
+            _ => Err(Error::Expected(vec![Marker::I64, Marker::I32, Marker::I16, Marker::I8, Marker::Number])),
+        }
+    }
+
+    fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::U8 => visitor.visit_i128(self.read_u8()?.into()),
+            m @ (Marker::I8 | Marker::I16 | Marker::I32 | Marker::I64) => {
+                visitor.visit_i128(self.read_signed(m)?.into())
+            }
+            Marker::Number => visitor.visit_i128(self.read_number()?),
+            _ => Err(Error::Expected(vec![
+                Marker::Number, Marker::U8, Marker::I8, Marker::I16, Marker::I32, Marker::I64,
+            ])),
+        }
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        match self.take_or_read_marker()? {
+            Marker::U8 => visitor.visit_u8(self.read_u8()?),
+            _ => Err(Error::Expected(vec![Marker::U8])),
         }
     }
 
@@ -248,9 +994,11 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
+        // signed values are range checked by the visitor
         match self.take_or_read_marker()? {
             Marker::U8 => visitor.visit_u16((self.read_u8()?) as u16),
-            _ => Err(Error::Expected(vec![Marker::U8])),
+            m @ (Marker::I8 | Marker::I16 | Marker::I32 | Marker::I64) => visitor.visit_i64(self.read_signed(m)?),
+            _ => Err(Error::Expected(vec![Marker::U8, Marker::I8, Marker::I16, Marker::I32, Marker::I64])),
         }
     }
 
@@ -258,9 +1006,11 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
+        // signed values are range checked by the visitor
         match self.take_or_read_marker()? {
             Marker::U8 => visitor.visit_u32((self.read_u8()?) as u32),
-            _ => Err(Error::Expected(vec![Marker::U8])),
+            m @ (Marker::I8 | Marker::I16 | Marker::I32 | Marker::I64) => visitor.visit_i64(self.read_signed(m)?),
+            _ => Err(Error::Expected(vec![Marker::U8, Marker::I8, Marker::I16, Marker::I32, Marker::I64])),
         }
     }
 
@@ -268,9 +1018,29 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
+        // signed values are range checked by the visitor
         match self.take_or_read_marker()? {
             Marker::U8 => visitor.visit_u64((self.read_u8()?) as u64),
-            _ => Err(Error::Expected(vec![Marker::U8])),
+            m @ (Marker::I8 | Marker::I16 | Marker::I32 | Marker::I64) => visitor.visit_i64(self.read_signed(m)?),
+            Marker::Number => visitor.visit_u64(self.read_number()?),
+            _ => Err(Error::Expected(vec![
+                Marker::U8, Marker::I8, Marker::I16, Marker::I32, Marker::I64, Marker::Number,
+            ])),
+        }
+    }
+
+    fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        // signed values are range checked by the visitor
+        match self.take_or_read_marker()? {
+            Marker::U8 => visitor.visit_u128(self.read_u8()?.into()),
+            m @ (Marker::I8 | Marker::I16 | Marker::I32 | Marker::I64) => visitor.visit_i64(self.read_signed(m)?),
+            Marker::Number => visitor.visit_u128(self.read_number()?),
+            _ => Err(Error::Expected(vec![
+                Marker::Number, Marker::U8, Marker::I8, Marker::I16, Marker::I32, Marker::I64,
+            ])),
         }
     }
 
@@ -280,7 +1050,9 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
     {
         match self.take_or_read_marker()? {
             Marker::F32 => visitor.visit_f32(self.read_f32()?),
-            _ => Err(Error::Expected(vec![Marker::F32])),
+            // narrowed, as written by `SerializerConfig::always_f64`
+            Marker::F64 => visitor.visit_f32(self.read_f64()? as f32),
+            _ => Err(Error::Expected(vec![Marker::F32, Marker::F64])),
         }
     }
 
@@ -291,7 +1063,14 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         match self.take_or_read_marker()? {
             Marker::F64 => visitor.visit_f64(self.read_f64()?),
             Marker::F32 => visitor.visit_f64((self.read_f32()?) as f64),
-            _ => Err(Error::Expected(vec![Marker::F64, Marker::F32])),
+            Marker::Number => {
+                let n: f64 = self.read_number()?;
+                if self.config.reject_non_finite && !n.is_finite() {
+                    return Err(Error::NonFiniteFloat);
+                }
+                visitor.visit_f64(n)
+            }
+            _ => Err(Error::Expected(vec![Marker::F64, Marker::F32, Marker::Number])),
         }
     }
 
@@ -306,11 +1085,10 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
             }
             Marker::String => {
                 let s = self.read_str()?;
-                if s.len() == 1 && s.is_ascii() {
-                    let c = s.as_bytes()[0];
-                    visitor.visit_char(c as char)
-                } else {
-                    Err(Error::InvalidString)
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => visitor.visit_char(c),
+                    _ => Err(Error::InvalidString),
                 }
             }
             _ => Err(Error::Expected(vec![Marker::Char, Marker::String])),
@@ -322,13 +1100,10 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
             V: Visitor<'de>,
     {
         match self.take_or_read_marker()? {
-            Marker::String => visitor.visit_borrowed_str(self.read_str()?),
+            Marker::String => visit_str(visitor, self.read_str()?),
             Marker::Char => {
                 let bytes = self.read_bytes(1)?;
-                match str::from_utf8(bytes) {
-                    Ok(s) => visitor.visit_borrowed_str(s),
-                    Err(_) => Err(Error::InvalidString),
-                }
+                visit_str(visitor, as_str(bytes)?)
             }
             _ => Err(Error::Expected(vec![Marker::String, Marker::Char])),
         }
@@ -348,43 +1123,37 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         }
     }
 
+    // Only the strongly typed `[$U#<count>` form stores the bytes contiguously and
+    // can be borrowed, other arrays of `U` values are collected into a buffer.
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        match self.read_marker()? {
+        match self.take_or_read_marker()? {
             Marker::ArrayStart => {
-                let (len, _of_type) = match self.peek_marker()? {
-                    Marker::OfType => {
-                        // both type and length are specified
-                        self.read_marker()?;
-                        let marker = self.read_marker()?;
-                        match self.read_marker()? {
-                            Marker::Length => {
-                                let len = self.read_len()?;
-                                (Some(len), Some(marker))
-                            }
-                            _ => return Err(Error::Expected(vec![Marker::Length])),
+                let value = match self.read_container_header()? {
+                    (Some(len), Some(Marker::U8)) => { // read borrowed bytes
+                        match self.read_bytes(len)? {
+                            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes::<Error>(bytes)?,
+                            Reference::Copied(bytes) => visitor.visit_bytes::<Error>(bytes)?,
                         }
                     }
-                    Marker::Length => {
-                        // only length is specified
-                        self.read_marker()?;
-                        let len = self.read_len()?;
-                        (Some(len), None)
-                    }
-                    _ => (None, None), // neither type nor length are specified
-                };
-
-                let value = match len {
-                    Some(len) => { // read borrowed bytes
-                        let bytes = self.read_bytes(len)?;
-                        visitor.visit_borrowed_bytes::<Error>(bytes)?
+                    (Some(len), None) => {
+                        let mut bytes = Vec::with_capacity(len.min(MAX_PREALLOC));
+                        for _ in 0..len {
+                            bytes.push(self.read_byte_value()?);
+                        }
+                        visitor.visit_byte_buf::<Error>(bytes)?
                     }
-                    None => { // this will fail because it is impossible to read as borrowed bytes
-                        let bytes = vec![0u8];
-                        visitor.visit_bytes::<Error>(&bytes)?
+                    (None, None) => {
+                        let mut bytes = Vec::new();
+                        while self.peek_marker()? != Marker::ArrayEnd {
+                            bytes.push(self.read_byte_value()?);
+                        }
+                        self.read_marker()?;
+                        visitor.visit_byte_buf::<Error>(bytes)?
                     }
+                    _ => return Err(Error::Expected(vec![Marker::U8])),
                 };
 
                 Ok(value)
@@ -404,12 +1173,40 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
-        match self.peek_marker()? {
-            Marker::Null => {
-                self.read_marker()?;
+        match self.of_type {
+            Some(Marker::Null) => {
+                self.of_type = None;
                 visitor.visit_none()
             }
-            _ => visitor.visit_some(self),
+            Some(_) => visitor.visit_some(self),
+            None => match self.peek_marker()? {
+                Marker::Null => {
+                    self.read_marker()?;
+                    visitor.visit_none()
+                }
+                Marker::ArrayStart | Marker::ObjectStart if self.config.empty_container_as_none => {
+                    let start = self.read_marker()?;
+                    let end = match start {
+                        Marker::ArrayStart => Marker::ArrayEnd,
+                        _ => Marker::ObjectEnd,
+                    };
+                    let header = self.read_container_header()?;
+                    match header.0 {
+                        Some(0) => return visitor.visit_none(),
+                        None if self.peek_byte()? == end as u8 => {
+                            self.read_marker()?;
+                            return visitor.visit_none();
+                        }
+                        _ => {}
+                    }
+                    // the start marker is handed on like a container element
+                    // type, together with the header read here
+                    self.of_type = Some(start);
+                    self.header = Some(header);
+                    visitor.visit_some(self)
+                }
+                _ => visitor.visit_some(self),
+            },
         }
     }
 
@@ -417,9 +1214,9 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
-        match self.read_marker()? {
-            Marker::Null => visitor.visit_unit(),
-            _ => Err(Error::Expected(vec![Marker::Null])),
+        match self.take_or_read_marker()? {
+            Marker::Null | Marker::NoOp => visitor.visit_unit(),
+            _ => Err(Error::Expected(vec![Marker::Null, Marker::NoOp])),
         }
     }
 
@@ -437,42 +1234,12 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        match self.read_marker()? {
-            Marker::ArrayStart => {
-                let (len, of_type) = match self.peek_marker()? {
-                    Marker::OfType => {
-                        // both type and length are specified
-                        self.read_marker()?;
-                        let marker = self.read_marker()?;
-                        match self.read_marker()? {
-                            Marker::Length => {
-                                let len = self.read_len()?;
-                                (Some(len), Some(marker))
-                            }
-                            _ => return Err(Error::Expected(vec![Marker::Length])),
-                        }
-                    }
-                    Marker::Length => {
-                        // only length is specified
-                        self.read_marker()?;
-                        let len = self.read_len()?;
-                        (Some(len), None)
-                    }
-                    _ => (None, None), // neither type nor length are specified
-                };
-
-                let value = visitor.visit_seq(ArrayAccess {
-                    de: &mut self,
-                    len,
-                    of_type,
-                    trailer: if len.is_some() { None } else { Some(Marker::ArrayEnd) },
-                })?;
-                Ok(value)
-            }
+        match self.take_or_read_marker()? {
+            Marker::ArrayStart => self.visit_array(visitor),
             _ => Err(Error::Expected(vec![Marker::ArrayStart])),
         }
     }
@@ -481,57 +1248,54 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        let marker = match self.of_type {
+            Some(marker) => marker,
+            None => self.peek_marker()?,
+        };
+
+        match marker {
+            Marker::ObjectStart => {
+                // tuple written as an object with positional keys
+                self.take_or_read_marker()?;
+                let (len, of_type) = self.read_container_header()?;
+
+                let value = visitor.visit_seq(PositionalAccess {
+                    de: &mut *self,
+                    len,
+                    of_type,
+                    index: 0,
+                })?;
+
+                if len.is_none() {
+                    match self.read_marker()? {
+                        Marker::ObjectEnd => {}
+                        _ => return Err(Error::Expected(vec![Marker::ObjectEnd])),
+                    }
+                }
+                Ok(value)
+            }
+            _ => self.deserialize_seq(visitor),
+        }
     }
 
     fn deserialize_tuple_struct<V>(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
         visitor: V,
     ) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        self.deserialize_seq(visitor)
+        self.deserialize_tuple(len, visitor)
     }
 
-    fn deserialize_map<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        match self.read_marker()? {
-            Marker::ObjectStart => {
-                let (len, of_type) = match self.peek_marker()? {
-                    Marker::OfType => {
-                        // both type and length are specified
-                        self.read_marker()?;
-                        let marker = self.read_marker()?;
-                        match self.read_marker()? {
-                            Marker::Length => {
-                                let len = self.read_len()?;
-                                (Some(len), Some(marker))
-                            }
-                            _ => return Err(Error::Expected(vec![Marker::Length])),
-                        }
-                    }
-                    Marker::Length => {
-                        // only length is specified
-                        self.read_marker()?;
-                        let len = self.read_len()?;
-                        (Some(len), None)
-                    }
-                    _ => (None, None), // neither type nor length are specified
-                };
-
-                let value = visitor.visit_map(ObjectAccess {
-                    de: &mut self,
-                    len,
-                    of_type,
-                    trailer: if len.is_some() { None } else { Some(Marker::ObjectEnd) }
-                })?;
-                Ok(value)
-            }
+        match self.take_or_read_marker()? {
+            Marker::ObjectStart => self.visit_object(visitor),
             _ => Err(Error::Expected(vec![Marker::ObjectStart])),
         }
     }
@@ -539,12 +1303,23 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
     fn deserialize_struct<V>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
+        if self.config.struct_from_array {
+            let marker = match self.of_type {
+                Some(marker) => marker,
+                None => self.peek_marker()?,
+            };
+            if marker == Marker::ArrayStart {
+                self.take_or_read_marker()?;
+                return self.visit_positional_struct(fields.len(), visitor);
+            }
+        }
+
         self.deserialize_map(visitor)
     }
 
@@ -557,36 +1332,32 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
-        match self.read_marker()? {
+        match self.take_or_read_marker()? {
             Marker::String => {
                 let s = self.read_str()?;
-                visitor.visit_enum(s.into_deserializer())
+                visitor.visit_enum((*s).into_deserializer())
+            }
+            Marker::U8 if self.config.enum_as_index => {
+                let index = self.read_u8()? as u32;
+                visitor.visit_enum(index.into_deserializer())
+            }
+            m @ (Marker::I8 | Marker::I16 | Marker::I32 | Marker::I64) if self.config.enum_as_index => {
+                let index = self.read_signed(m)?;
+                let index = u32::try_from(index)
+                    .map_err(|_| <Error as de::Error>::invalid_value(Unexpected::Signed(index), &"a variant index"))?;
+                visitor.visit_enum(index.into_deserializer())
             }
             Marker::ObjectStart => {
-                let len = match self.peek_marker()? {
-                    Marker::OfType => {
-                        // both type and length are specified
-                        self.read_marker()?; // of type
-                        self.read_marker()?; // type
-                        match self.read_marker()? { // length
-                            Marker::Length => {
-                                let len = self.read_len()?;
-                                Some(len)
-                            }
-                            _ => return Err(Error::Expected(vec![Marker::Length])),
-                        }
-                    }
-                    Marker::Length => {
-                        // only length is specified
-                        self.read_marker()?;
-                        let len = self.read_len()?;
-                        Some(len)
-                    }
-                    _ => None
-                };
+                let (len, of_type) = self.read_container_header()?;
+
+                // an enum object holds exactly the variant entry
+                if matches!(len, Some(len) if len != 1) {
+                    return Err(Error::InvalidLength);
+                }
 
                 let value = visitor.visit_enum(ItemAccess {
-                    de: self
+                    de: self,
+                    of_type,
                 })?;
 
                 match len {
@@ -614,18 +1385,73 @@ impl<'a, 'de: 'a> serde::de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
+        let key = match self.path.last() {
+            _ if self.on_unknown_field.is_none() => None,
+            Some(PathSegment::Key(key)) => Some(Cow::Owned(key.clone())),
+            _ => self.field.take(),
+        };
+        if let Some(key) = key {
+            let mut raw = Vec::new();
+            self.read_raw(&mut raw)?;
+            if let Some(on_unknown_field) = self.on_unknown_field.as_mut() {
+                on_unknown_field(&key, RawValue::new(&raw));
+            }
+            return visitor.visit_unit();
+        }
+
         self.deserialize_any(visitor)
     }
 }
 
-struct ArrayAccess<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
+struct ArrayAccess<'a, 'de: 'a, R> {
+    de: &'a mut Deserializer<'de, R>,
     len: Option<usize>,
     of_type: Option<Marker>,
     trailer: Option<Marker>,
+    index: usize,
+}
+
+impl<'de, 'a, R> ArrayAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
+    fn element<T>(&mut self, seed: T) -> Result<T::Value>
+        where
+            T: DeserializeSeed<'de>,
+    {
+        // hint type to the deserializer if set; a container type (`$[`, `${`)
+        // stands in for the start marker and the element's header follows
+        self.de.of_type = self.of_type;
+        self.de.field = None;
+        self.de.enter(PathSegment::Index(self.index));
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.leave();
+        self.index += 1;
+        Ok(value)
+    }
+
+    // Consumes the closing marker of an array without count. With a type, a
+    // payload byte `0x5D` at an element boundary reads the same; a real end
+    // is followed by a marker or the end of input, so the array is known to
+    // have been cut short here otherwise.
+    fn close(&mut self) -> Result<()> {
+        self.de.read_marker()?;
+        self.len = Some(0);
+        if self.of_type.is_some_and(|marker| payload_size(marker) > 0) {
+            match self.de.peek_byte() {
+                Ok(byte) if Marker::try_from(byte).is_err() => return Err(Error::InvalidMarker),
+                Ok(_) | Err(Error::Eof) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<'de, 'a> SeqAccess<'de> for ArrayAccess<'a, 'de> {
+impl<'de, 'a, R> SeqAccess<'de> for ArrayAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
@@ -637,9 +1463,7 @@ impl<'de, 'a> SeqAccess<'de> for ArrayAccess<'a, 'de> {
                 if len == 0 {
                     Ok(None)
                 } else {
-                    // hint type to the deserializer if set
-                    self.de.of_type = self.of_type;
-                    let value = seed.deserialize(&mut *self.de)?;
+                    let value = self.element(seed)?;
                     self.len = Some(len - 1);
 
                     // consume trailing marker
@@ -657,26 +1481,27 @@ impl<'de, 'a> SeqAccess<'de> for ArrayAccess<'a, 'de> {
                 }
             }
             None => {
-                // consume trailing marker
+                // skip no-ops between elements, e.g. heartbeats of a slow writer
+                if self.of_type.is_none() {
+                    while self.de.peek_byte()? == Marker::NoOp as u8 {
+                        self.de.read_byte()?;
+                    }
+                }
+
+                // consume trailing marker, payloads of typed elements are not markers
                 if let Some(m) = self.trailer {
-                    let marker = self.de.peek_marker()?;
-                    if marker == m {
-                        self.de.read_marker()?;
-                        self.len = Some(0);
+                    if self.de.peek_byte()? == m as u8 {
+                        self.close()?;
                         return Ok(None)
                     }
                 }
 
-                let value = seed.deserialize(&mut *self.de)?;
+                let value = self.element(seed)?;
 
                 // try consume trailing marker
                 if let Some(m) = self.trailer {
-                    let byte = self.de.peek_byte()?;
-                    if let Ok(marker) = Marker::try_from(byte) {
-                        if marker == m {
-                            self.de.read_marker()?;
-                            self.len = Some(0);
-                        }
+                    if self.de.peek_byte()? == m as u8 {
+                        self.close()?;
                     }
                 }
 
@@ -686,14 +1511,50 @@ impl<'de, 'a> SeqAccess<'de> for ArrayAccess<'a, 'de> {
     }
 }
 
-struct ObjectAccess<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
+struct ObjectAccess<'a, 'de: 'a, R> {
+    de: &'a mut Deserializer<'de, R>,
     len: Option<usize>,
     of_type: Option<Marker>,
     trailer: Option<Marker>,
+    key: Option<Cow<'de, str>>,
+}
+
+impl<'de, 'a, R> ObjectAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
+    fn read_key<K>(&mut self, seed: K) -> Result<K::Value>
+        where
+            K: DeserializeSeed<'de>,
+    {
+        let config = &self.de.config;
+        // objects always have string keys
+        if !config.track_path && self.de.on_unknown_field.is_none() && !config.coerce_int_keys {
+            self.de.of_type = Some(Marker::String);
+            return seed.deserialize(&mut *self.de);
+        }
+
+        // read the key here to remember it for the value, owned only where
+        // the path or a copied key needs it
+        let (track_path, coerce) = (config.track_path, config.coerce_int_keys);
+        let on_unknown_field = self.de.on_unknown_field.is_some();
+        let key = self.de.read_str()?;
+        let name = match key {
+            _ if track_path => Some(Cow::Owned(key.to_string())),
+            _ if !on_unknown_field => None,
+            Reference::Borrowed(s) => Some(Cow::Borrowed(s)),
+            Reference::Copied(s) => Some(Cow::Owned(s.to_string())),
+        };
+        let value = seed.deserialize(KeyDeserializer { key, coerce })?;
+        self.key = name;
+        Ok(value)
+    }
 }
 
-impl<'de, 'a> MapAccess<'de> for ObjectAccess<'a, 'de> {
+impl<'de, 'a, R> MapAccess<'de> for ObjectAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
@@ -705,9 +1566,8 @@ impl<'de, 'a> MapAccess<'de> for ObjectAccess<'a, 'de> {
                 if len == 0 {
                     Ok(None)
                 } else {
-                    // objects always have string keys
-                    self.de.of_type = Some(Marker::String);
-                    let value = seed.deserialize(&mut *self.de)?;
+                    let value = self.read_key(seed)
+                        .map_err(|e| e.in_context("object entry"))?;
                     self.len = Some(len - 1);
 
                     // consume trailing marker
@@ -735,112 +1595,1284 @@ impl<'de, 'a> MapAccess<'de> for ObjectAccess<'a, 'de> {
                     }
                 }
 
-                // objects always have string keys
-                self.de.of_type = Some(Marker::String);
-                let value = seed.deserialize(&mut *self.de)?;
+                let value = self.read_key(seed)?;
+
+                // try consume trailing marker
+                if let Some(m) = self.trailer {
+                    let byte = self.de.peek_byte()?;
+                    if let Ok(marker) = Marker::try_from(byte) {
+                        if marker == m {
+                            self.de.read_marker()?;
+                            self.len = Some(0);
+                        }
+                    }
+                }
+
+                Ok(Some(value))
+            }
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where
+            V: DeserializeSeed<'de>,
+    {
+        // hint type to the deserializer if set
+        self.de.of_type = self.of_type;
+        if let Some(key) = self.key.take() {
+            match self.de.config.track_path {
+                true => self.de.enter(PathSegment::Key(key.into_owned())),
+                false => self.de.field = Some(key),
+            }
+        }
+        let value = seed.deserialize(&mut *self.de);
+        self.de.field = None;
+        if value.is_ok() {
+            self.de.leave();
+        }
+
+        match self.len {
+            Some(_) => value.map_err(|e| e.in_context("object entry")),
+            None => value,
+        }
+    }
+}
+
+struct PositionalAccess<'a, 'de: 'a, R> {
+    de: &'a mut Deserializer<'de, R>,
+    len: Option<usize>,
+    of_type: Option<Marker>,
+    index: usize,
+}
+
+impl<'de, 'a, R> SeqAccess<'de> for PositionalAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+        where
+            T: DeserializeSeed<'de>,
+    {
+        match self.len {
+            Some(len) if len == self.index => return Ok(None),
+            None if self.de.peek_marker()? == Marker::ObjectEnd => return Ok(None),
+            _ => {}
+        }
+
+        // keys must be the element indices in order
+        let key = self.de.read_str()?;
+        if key.parse::<usize>().ok() != Some(self.index) {
+            let msg = format!("expected positional key \"{}\", found \"{}\"", self.index, &*key);
+            return Err(de::Error::custom(msg));
+        }
+
+        // hint type to the deserializer if set
+        self.de.of_type = self.of_type;
+        self.de.enter(PathSegment::Index(self.index));
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.leave();
+        self.index += 1;
+
+        Ok(Some(value))
+    }
+}
+
+// typed array exposed as a map of the element type and the elements, so that
+// Value can keep the element type
+struct TypedArrayAccess<'a, 'de: 'a, R> {
+    marker: Option<Marker>,
+    elements: Option<ArrayAccess<'a, 'de, R>>,
+}
+
+impl<'de, 'a, R> MapAccess<'de> for TypedArrayAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where
+            K: DeserializeSeed<'de>,
+    {
+        if self.marker.is_some() {
+            seed.deserialize(TYPED_ARRAY_TOKEN.into_deserializer()).map(Some)
+        } else if self.elements.is_some() {
+            seed.deserialize("values".into_deserializer()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where
+            V: DeserializeSeed<'de>,
+    {
+        if let Some(marker) = self.marker.take() {
+            seed.deserialize((marker as u8 as char).into_deserializer())
+        } else if let Some(elements) = self.elements.take() {
+            seed.deserialize(SeqAccessDeserializer::new(elements))
+        } else {
+            Err(Error::InvalidMarker)
+        }
+    }
+}
+
+// object key read ahead of its value, which reads like a string key read by
+// `Deserializer` or, with `coerce`, also as an integer, bool or float
+struct KeyDeserializer<'de, 's> {
+    key: Reference<'de, 's, str>,
+    coerce: bool,
+}
+
+macro_rules! deserialize_primitive_key {
+    ($($method:ident => $visit:ident($ty:ty),)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+                where
+                    V: Visitor<'de>,
+            {
+                if !self.coerce {
+                    return self.deserialize_any(visitor);
+                }
+                match self.key.parse::<$ty>() {
+                    Ok(n) => visitor.$visit(n),
+                    Err(_) => Err(de::Error::invalid_value(Unexpected::Str(&self.key), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 's> de::Deserializer<'de> for KeyDeserializer<'de, 's> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        visit_str(visitor, self.key)
+    }
+
+    deserialize_primitive_key! {
+        deserialize_i8 => visit_i8(i8),
+        deserialize_i16 => visit_i16(i16),
+        deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64),
+        deserialize_i128 => visit_i128(i128),
+        deserialize_u8 => visit_u8(u8),
+        deserialize_u16 => visit_u16(u16),
+        deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64),
+        deserialize_u128 => visit_u128(u128),
+        deserialize_bool => visit_bool(bool),
+        deserialize_f32 => visit_f32(f32),
+        deserialize_f64 => visit_f64(f64),
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // unit variant named by the key
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        visitor.visit_enum((&*self.key).into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct NumberAccess<'s> {
+    number: Option<&'s str>,
+}
+
+impl<'de, 's> MapAccess<'de> for NumberAccess<'s> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where
+            K: DeserializeSeed<'de>,
+    {
+        match self.number {
+            Some(_) => seed.deserialize(NUMBER_TOKEN.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where
+            V: DeserializeSeed<'de>,
+    {
+        match self.number.take() {
+            Some(number) => seed.deserialize(number.into_deserializer()),
+            None => Err(Error::InvalidNumber),
+        }
+    }
+}
+
+struct ItemAccess<'a, 'de: 'a, R> {
+    de: &'a mut Deserializer<'de, R>,
+    of_type: Option<Marker>,
+}
+
+impl<'de, 'a, R> EnumAccess<'de> for ItemAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+        where
+            V: DeserializeSeed<'de>,
+    {
+        // objects always have string keys
+        self.de.of_type = Some(Marker::String);
+        let val = seed.deserialize(&mut *self.de)?;
+        Ok((val, self))
+    }
+}
+
+impl<'de, 'a, R> VariantAccess<'de> for ItemAccess<'a, 'de, R>
+    where
+        R: Read<'de>,
+{
+    type Error = Error;
+
+    // unit variant written as an entry with a null value
+    fn unit_variant(self) -> Result<()> {
+        self.de.of_type = self.of_type;
+        match self.de.take_or_read_marker()? {
+            Marker::Null => Ok(()),
+            _ => Err(Error::Expected(vec![Marker::Null])),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+        where
+            T: DeserializeSeed<'de>,
+    {
+        // hint type to the deserializer if set
+        self.de.of_type = self.of_type;
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.of_type = self.of_type;
+        serde::de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.of_type = self.of_type;
+        serde::de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
+/// Iterator over a sequence of top-level values, such as a log of concatenated documents.
+///
+/// NoOp markers between values are skipped.
+pub struct StreamDeserializer<'de, T> {
+    de: Deserializer<'de>,
+    input: &'de [u8],
+    offset: usize,
+    // where the value yielded last starts
+    value_offset: usize,
+    recover: bool,
+    done: bool,
+    // value found while resynchronizing, with the offset it ends at
+    pending: Option<(T, usize)>,
+    max_values: Option<usize>,
+    resync_window: usize,
+    // values yielded so far
+    count: usize,
+    output: PhantomData<T>,
+}
+
+const DEFAULT_RESYNC_WINDOW: usize = 4 * 1024;
+
+impl<'de, T> StreamDeserializer<'de, T>
+    where
+        T: Deserialize<'de>,
+{
+    pub fn new(de: Deserializer<'de>) -> StreamDeserializer<'de, T> {
+        StreamDeserializer {
+            input: de.read.remaining(),
+            de,
+            offset: 0,
+            value_offset: 0,
+            recover: false,
+            done: false,
+            pending: None,
+            max_values: None,
+            resync_window: DEFAULT_RESYNC_WINDOW,
+            count: 0,
+            output: PhantomData,
+        }
+    }
+
+    /// Keeps iterating past malformed documents.
+    ///
+    /// A document that is well-formed but fails to decode, e.g. one of the
+    /// wrong type, is skipped as a whole. Otherwise the input is scanned
+    /// forward for a value that decodes and is followed by the end of input, a
+    /// no-op or another well-formed value. The scan is a heuristic: concatenated
+    /// documents carry no frame boundaries, so it may still land inside a
+    /// payload. The yielded error is [`Error::Skipped`] with the byte range
+    /// that was passed over. Truncated input and I/O failures still end the
+    /// iteration.
+    pub fn recover(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    /// Bytes scanned past a malformed document when recovering, 4 KiB by
+    /// default. Only values that start and end within the window are found;
+    /// if there is none, the rest of the input is skipped.
+    pub fn resync_window(mut self, bytes: usize) -> Self {
+        self.resync_window = bytes;
+        self
+    }
+
+    /// Stops after `max` values. If the input holds more, the iterator yields
+    /// `Error::LimitExceeded` once and then ends.
+    pub fn max_values(mut self, max: usize) -> Self {
+        self.max_values = Some(max);
+        self
+    }
+
+    fn limit_reached(&mut self) -> bool {
+        match self.max_values {
+            Some(max) if self.count >= max => {
+                self.pending = None;
+                self.done = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Offset of the next value relative to the start of the input.
+    pub fn byte_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Offset of the value yielded last relative to the start of the input,
+    /// past any no-ops before it, e.g. for reporting where a document came
+    /// from.
+    pub fn value_offset(&self) -> usize {
+        self.value_offset
+    }
+
+    // decodes the value at `offset` from the input up to `until`
+    fn decode_at(&mut self, offset: usize, until: usize) -> Result<(T, usize)> {
+        self.de.read = SliceRead::at(&self.input[..until], offset);
+        self.de.of_type = None;
+        let value = T::deserialize(&mut self.de)?;
+        Ok((value, self.de.read.offset()))
+    }
+
+    // end of the well-formed value at `offset`, if there is one
+    fn scan_at(&self, offset: usize, until: usize) -> Option<usize> {
+        match ValueScanner::default().split(&self.input[offset..until], None) {
+            Ok((0, Some(len))) => Some(offset + len),
+            _ => None,
+        }
+    }
+
+    // whether a value ending at `end` is followed by something a writer could
+    // have put after a document
+    fn at_boundary(&self, end: usize) -> bool {
+        match self.input.get(end) {
+            None => true,
+            Some(&b) if b == Marker::NoOp as u8 => true,
+            Some(_) => ValueScanner::default().split(&self.input[end..], None).is_ok(),
+        }
+    }
+
+    fn resync(&mut self, from: usize) -> Option<(T, usize, usize)> {
+        let until = from.saturating_add(self.resync_window).min(self.input.len());
+        for offset in from..until {
+            let plausible = match Marker::try_from(self.input[offset]) {
+                Ok(marker) => is_value_start(marker) && marker != Marker::NoOp,
+                Err(_) => false,
+            };
+            if !plausible {
+                continue;
+            }
+            let end = match self.scan_at(offset, until) {
+                Some(end) => end,
+                None => continue,
+            };
+            if let Ok((value, decoded)) = self.decode_at(offset, end) {
+                if decoded == end && self.at_boundary(end) {
+                    return Some((value, offset, end));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<'de, T> Iterator for StreamDeserializer<'de, T>
+    where
+        T: Deserialize<'de>,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_some() && self.limit_reached() {
+            return Some(Err(Error::LimitExceeded { limit: "max_values" }));
+        }
+
+        if let Some((value, end)) = self.pending.take() {
+            self.value_offset = self.offset;
+            self.offset = end;
+            self.count += 1;
+            return Some(Ok(value));
+        }
+
+        if self.done {
+            return None;
+        }
+
+        while self.input.get(self.offset) == Some(&(Marker::NoOp as u8)) {
+            self.offset += 1;
+        }
+
+        if self.offset == self.input.len() {
+            self.done = true;
+            return None;
+        }
+
+        if self.limit_reached() {
+            return Some(Err(Error::LimitExceeded { limit: "max_values" }));
+        }
+
+        let start = self.offset;
+        match self.decode_at(start, self.input.len()) {
+            Ok((value, end)) => {
+                self.value_offset = start;
+                self.offset = end;
+                self.count += 1;
+                Some(Ok(value))
+            }
+            Err(e) => {
+                let recoverable = matches!(e.kind(), ErrorKind::Malformed | ErrorKind::Data);
+                if !self.recover || !recoverable {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+
+                if let Some(end) = self.scan_at(start, self.input.len()) {
+                    self.offset = end;
+                    return Some(Err(Error::Skipped {
+                        start,
+                        end,
+                        cause: Box::new(e),
+                    }));
+                }
+
+                match self.resync(start + 1) {
+                    Some((value, at, end)) => {
+                        self.offset = at;
+                        self.pending = Some((value, end));
+                    }
+                    None => {
+                        self.offset = self.input.len();
+                        self.done = true;
+                    }
+                }
+
+                Some(Err(Error::Skipped {
+                    start,
+                    end: self.offset,
+                    cause: Box::new(e),
+                }))
+            }
+        }
+    }
+}
+
+fn as_str<'de, 's>(bytes: Reference<'de, 's>) -> Result<Reference<'de, 's, str>> {
+    let s = match bytes {
+        Reference::Borrowed(b) => str::from_utf8(b).map(Reference::Borrowed),
+        Reference::Copied(c) => str::from_utf8(c).map(Reference::Copied),
+    };
+    s.map_err(|_| Error::InvalidString)
+}
+
+fn visit_str<'de, V>(visitor: V, s: Reference<'de, '_, str>) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+{
+    match s {
+        Reference::Borrowed(s) => visitor.visit_borrowed_str(s),
+        Reference::Copied(s) => visitor.visit_str(s),
+    }
+}
+
+// length written as decimal digits, with the same guards as integer lengths
+fn parse_len_digits(digits: &[u8]) -> Result<usize> {
+    str::from_utf8(digits)
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .ok_or(Error::InvalidLength)
+}
+
+// size of the payload following a fixed-width marker
+pub(crate) fn payload_size(marker: Marker) -> usize {
+    match marker {
+        Marker::I8 | Marker::U8 | Marker::Char => 1,
+        Marker::I16 => 2,
+        Marker::I32 | Marker::F32 => 4,
+        Marker::I64 | Marker::F64 => 8,
+        _ => 0,
+    }
+}
+
+// markers that are the whole value, as elements of typed containers take no
+// input at all
+pub(crate) fn is_empty_payload(marker: Marker) -> bool {
+    matches!(marker, Marker::Null | Marker::NoOp | Marker::True | Marker::False)
+}
+
+fn is_value_start(marker: Marker) -> bool {
+    marker.is_scalar() || marker.is_container_start() || marker == Marker::NoOp
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::num::{NonZeroI64, NonZeroU32};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct SimpleStruct {
+        field1: i32,
+        field2: String,
+    }
+
+    #[derive(Deserialize)]
+    struct UniformStruct {
+        field1: i32,
+        field2: i32,
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, Deserialize)]
+    enum SimpleEnum {
+        Unit,
+        NewType(i32),
+        Tuple(i32, i32),
+        Struct { field1: i32, field2: i32 },
+    }
+
+    // owned bytes, decoded through deserialize_byte_buf
+    #[derive(Debug, PartialEq)]
+    struct ByteBuf(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for ByteBuf {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+        {
+            struct ByteBufVisitor;
+
+            impl<'de> Visitor<'de> for ByteBufVisitor {
+                type Value = ByteBuf;
+
+                fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    formatter.write_str("bytes")
+                }
+
+                fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<ByteBuf, E> {
+                    Ok(ByteBuf(v.to_vec()))
+                }
+
+                fn visit_byte_buf<E>(self, v: Vec<u8>) -> std::result::Result<ByteBuf, E> {
+                    Ok(ByteBuf(v))
+                }
+            }
+
+            deserializer.deserialize_byte_buf(ByteBufVisitor)
+        }
+    }
+
+    #[test]
+    fn parsing_len_of_each_width_produces_len_and_rest() {
+        assert_eq!(parse_len(b"i\x05rest").unwrap(), (5, &b"rest"[..]));
+
+        let mut data = vec![b'I'];
+        data.extend_from_slice(&300i16.to_be_bytes());
+        assert_eq!(parse_len(&data).unwrap(), (300, &b""[..]));
+
+        let mut data = vec![b'l'];
+        data.extend_from_slice(&70_000i32.to_be_bytes());
+        data.push(b'S');
+        assert_eq!(parse_len(&data).unwrap(), (70_000, &b"S"[..]));
+
+        let mut data = vec![b'L'];
+        data.extend_from_slice(&(1i64 << 33).to_be_bytes());
+        assert_eq!(parse_len(&data).unwrap(), (1 << 33, &b""[..]));
+    }
+
+    #[test]
+    fn counting_values_produces_number_of_top_level_values() {
+        assert_eq!(count_values(b"").unwrap(), 0);
+        assert_eq!(count_values(b"i\x01").unwrap(), 1);
+        assert_eq!(count_values(b"[i\x01[T]]").unwrap(), 1);
+
+        let mut data = crate::to_bytes(&vec!["a", "b"]).unwrap();
+        data.extend_from_slice(b"NZ{i\x01ai\x02}N");
+        data.extend_from_slice(&crate::to_bytes(&1.5f64).unwrap());
+        assert_eq!(count_values(&data).unwrap(), 4);
+    }
+
+    #[test]
+    fn counting_values_with_truncated_tail_produces_error() {
+        assert!(matches!(count_values(b"TSi\x05hel"), Err(Error::Eof)));
+        assert!(matches!(count_values(b"T[i\x01"), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn deserializing_string_with_number_length_in_lenient_mode_can_produce_string() {
+        let config = DeserializerConfig {
+            lenient: true,
+            ..Default::default()
+        };
+        let data = b"SHi\x015hello";
+
+        let value: String = from_bytes_with_config(data, &config).unwrap();
+        assert_eq!(value, "hello");
+        let value: Vec<i8> = from_bytes_with_config(b"[#HU\x012i\x01i\x02", &config).unwrap();
+        assert_eq!(value, vec![1, 2]);
+
+        // skipped unknown fields are copied raw
+        #[derive(Deserialize)]
+        struct Only {
+            a: i8,
+        }
+        let mut skipped = 0;
+        let value = Only::deserialize(
+            &mut Deserializer::with_config(b"{i\x01sSHi\x015helloi\x01ai\x07}", config.clone())
+                .on_unknown_field(|_, raw| skipped = raw.as_bytes().len()),
+        ).unwrap();
+        assert_eq!(value.a, 7);
+        assert_eq!(skipped, data.len());
+
+        assert!(matches!(from_bytes::<'_, String>(data), Err(Error::ExpectedLength { found: Marker::Number })));
+        assert!(matches!(from_bytes_with_config::<String>(b"SHi\x02-1x", &config), Err(Error::InvalidLength)));
+        assert!(matches!(from_bytes_with_config::<String>(b"SHi\x031.5x", &config), Err(Error::InvalidLength)));
+    }
+
+    #[test]
+    fn parsing_invalid_len_produces_error() {
+        assert!(matches!(parse_len(b"i\xff"), Err(Error::InvalidLength)));
+
+        let mut data = vec![b'L'];
+        data.extend_from_slice(&i64::MIN.to_be_bytes());
+        assert!(matches!(parse_len(&data), Err(Error::InvalidLength)));
+
+        assert!(matches!(parse_len(b"S"), Err(Error::ExpectedLength { found: Marker::String })));
+        assert!(matches!(parse_len(b"I\x01"), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn deserializing_string_with_negative_len_produces_error() {
+        let data = b"Si\xfdabc";
+        assert!(matches!(from_bytes::<'_, String>(data), Err(Error::InvalidLength)));
+    }
+
+    #[test]
+    fn deserializing_serialized_non_zero_integers_can_produce_non_zero_integers() {
+        let value = NonZeroU32::new(70_000).unwrap();
+        let out = crate::to_bytes(&value).unwrap();
+        assert_eq!(from_bytes::<'_, NonZeroU32>(&out).unwrap(), value);
+
+        let value = NonZeroI64::new(-5).unwrap();
+        let out = crate::to_bytes(&value).unwrap();
+        assert_eq!(from_bytes::<'_, NonZeroI64>(&out).unwrap(), value);
+
+        let value = 65_535u16;
+        let out = crate::to_bytes(&value).unwrap();
+        assert_eq!(from_bytes::<'_, u16>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn deserializing_zero_or_negative_into_non_zero_unsigned_produces_error() {
+        let mut data = vec![b'L'];
+        data.extend_from_slice(&0i64.to_be_bytes());
+        assert!(matches!(from_bytes::<'_, NonZeroU32>(&data), Err(Error::Custom(_))));
+
+        let data = b"i\xff";
+        assert!(matches!(from_bytes::<'_, NonZeroU32>(data), Err(Error::Custom(_))));
+        assert!(matches!(from_bytes::<'_, u16>(data), Err(Error::Custom(_))));
+    }
+
+    #[test]
+    fn deserializing_typed_array_without_count_in_lenient_mode_can_produce_vec() {
+        let data = b"[$i\x01\x02\x03]";
+
+        let config = DeserializerConfig { lenient: true, ..Default::default() };
+        let value = from_bytes_with_config::<Vec<i8>>(data, &config).unwrap();
+        assert_eq!(value, vec![1, 2, 3]);
+
+        let value = from_bytes_with_config::<Vec<i8>>(b"[$i]", &config).unwrap();
+        assert!(value.is_empty());
+
+        let result = from_bytes::<'_, Vec<i8>>(data);
+        assert!(matches!(result, Err(Error::Expected(_))));
+    }
+
+    #[test]
+    fn deserializing_typed_array_without_count_ended_by_payload_byte_produces_error() {
+        let config = DeserializerConfig { lenient: true, ..Default::default() };
+
+        let result = from_bytes_with_config::<Vec<u8>>(b"[$U\x01\x5d\x02]", &config);
+        assert!(matches!(result, Err(Error::InvalidMarker)));
+
+        let result = from_bytes_with_config::<Vec<i16>>(b"[$I\x5d\x00\x01\x00]", &config);
+        assert!(matches!(result, Err(Error::InvalidMarker)));
+
+        let result = from_bytes_with_config::<Vec<Vec<u8>>>(b"[[$U\x01\x5d\x02]]", &config);
+        assert!(matches!(result, Err(Error::InvalidMarker)));
+
+        // trailing bytes after a complete value are left alone, as for any value
+        let result = from_bytes_with_config::<Vec<u8>>(b"[$U\x01\x02]U\x02", &config);
+        assert_eq!(result.unwrap(), vec![1, 2]);
+        assert_eq!(from_bytes_with_config::<u8>(b"U\x01U\x02", &config).unwrap(), 1);
+    }
+
+    #[test]
+    fn deserializing_failing_nested_field_with_track_path_produces_error_with_path() {
+        #[derive(Debug, Deserialize)]
+        struct Item {
+            #[allow(dead_code)]
+            name: NonZeroU32,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Outer {
+            #[allow(dead_code)]
+            items: Vec<Item>,
+        }
+
+        // {items: [{name: 1}, {name: 0}]}
+        let data = b"{i\x05items[{i\x04nameU\x01}{i\x04nameU\x00}]}";
+        let config = DeserializerConfig { track_path: true, ..Default::default() };
+
+        match from_bytes_with_config::<Outer>(data, &config) {
+            Err(Error::Custom(msg)) => assert!(msg.ends_with(" at .items[1].name"), "{}", msg),
+            other => panic!("Expected custom error, got {:?}", other),
+        }
+
+        match from_bytes::<'_, Outer>(data) {
+            Err(Error::Custom(msg)) => assert!(!msg.contains(" at ."), "{}", msg),
+            other => panic!("Expected custom error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deserializing_boolean_arrays_in_every_form_can_produce_vec_of_bool() {
+        let expected = vec![true, true, true];
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[TTT]").unwrap(), expected);
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[$T#i\x03").unwrap(), expected);
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[$U#i\x03\x01\x01\x01").unwrap(), expected);
+
+        let expected = vec![false, false];
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[FF]").unwrap(), expected);
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[$F#i\x02").unwrap(), expected);
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[$U#i\x02\x00\x00").unwrap(), expected);
+
+        let expected = vec![true, false, true];
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[TFT]").unwrap(), expected);
+        assert_eq!(from_bytes::<'_, Vec<bool>>(b"[$U#i\x03\x01\x00\x01").unwrap(), expected);
+    }
+
+    #[test]
+    fn deserializing_typed_u8_array_with_non_boolean_byte_produces_error() {
+        let result = from_bytes::<'_, Vec<bool>>(b"[$U#i\x02\x01\x02");
+        assert!(matches!(result, Err(Error::Custom(_))));
+
+        // untyped `U` values are numbers, not booleans
+        let result = from_bytes::<'_, Vec<bool>>(b"[U\x01]");
+        assert!(matches!(result, Err(Error::Expected(_))));
+    }
+
+    #[test]
+    fn deserializing_stream_with_junk_byte_in_lenient_mode_can_produce_both_values() {
+        let mut skipped = Vec::new();
+
+        let data = b"i\x01\xffi\x02";
+        let config = DeserializerConfig {
+            lenient: true,
+            skip_budget: 1,
+            ..Default::default()
+        };
+
+        let values = Deserializer::with_config(data, config.clone())
+            .on_skip(|offset, byte| skipped.push((offset, byte)))
+            .into_iter::<i8>()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(values, vec![1, 2]);
+        assert_eq!(skipped, vec![(2, 0xff)]);
+
+        // the budget is exhausted by the second junk byte
+        let data = b"i\x01\xff\xffi\x02";
+        let mut values = Deserializer::with_config(data, config).into_iter::<i8>();
+        assert_eq!(values.next().unwrap().unwrap(), 1);
+        assert!(matches!(values.next(), Some(Err(Error::InvalidMarker))));
+
+        // strict mode keeps the hard error
+        let mut values = Deserializer::new(b"i\x01\xffi\x02").into_iter::<i8>();
+        assert_eq!(values.next().unwrap().unwrap(), 1);
+        assert!(matches!(values.next(), Some(Err(Error::InvalidMarker))));
+    }
+
+    #[test]
+    fn deserializing_typed_array_into_fixed_array_can_produce_array() {
+        let data = b"[$i#i\x04\x01\x02\x03\xfc";
+        let value = from_bytes_array::<4, i8>(data).unwrap();
+        assert_eq!(value, [1, 2, 3, -4]);
+
+        let mut data = b"[$l#U\x02".to_vec();
+        data.extend_from_slice(&70_000i32.to_be_bytes());
+        data.extend_from_slice(&(-1i32).to_be_bytes());
+        assert_eq!(from_bytes_array::<2, i32>(&data).unwrap(), [70_000, -1]);
+    }
+
+    #[test]
+    fn deserializing_typed_array_into_fixed_array_of_other_size_or_type_produces_error() {
+        let data = b"[$i#i\x04\x01\x02\x03\x04";
+        assert!(matches!(from_bytes_array::<3, i8>(data), Err(Error::InvalidLength)));
+        assert!(matches!(from_bytes_array::<4, u8>(data), Err(Error::Expected(_))));
+        assert!(matches!(from_bytes_array::<2, i8>(b"[i\x01i\x02]"), Err(Error::Expected(_))));
+        assert!(matches!(from_bytes_array::<4, i8>(&data[..7]), Err(Error::Eof)));
+    }
+
+    #[test]
+    fn deserializing_single_element_array_with_unwrap_single_can_produce_struct() {
+        let data = b"[#i\x01{i\x06field1l\x00\x00\x00\x07i\x06field2Si\x02ok}";
+        let value = from_bytes_unwrap_single::<SimpleStruct>(data).unwrap();
+        assert_eq!(value, SimpleStruct { field1: 7, field2: "ok".to_string() });
+
+        assert_eq!(from_bytes_unwrap_single::<i8>(b"[i\x05]").unwrap(), 5);
+        assert_eq!(from_bytes_unwrap_single::<i8>(b"[$i#i\x01\x05").unwrap(), 5);
+    }
+
+    #[test]
+    fn deserializing_other_root_with_unwrap_single_produces_error() {
+        assert!(matches!(from_bytes_unwrap_single::<i8>(b"i\x05"), Err(Error::Expected(_))));
+        assert!(matches!(from_bytes_unwrap_single::<i8>(b"[]"), Err(Error::InvalidLength)));
+        assert!(matches!(from_bytes_unwrap_single::<i8>(b"[i\x05i\x06]"), Err(Error::InvalidLength)));
+        assert!(matches!(from_bytes_unwrap_single::<i8>(b"[#i\x02i\x05i\x06"), Err(Error::InvalidLength)));
+    }
+
+    #[test]
+    fn deserializing_integer_with_enum_as_index_can_produce_unit_variant() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Color {
+            Red,
+            Green,
+        }
+
+        let config = DeserializerConfig { enum_as_index: true, ..Default::default() };
+        assert_eq!(from_bytes_with_config::<Color>(b"i\x00", &config).unwrap(), Color::Red);
+        assert_eq!(from_bytes_with_config::<Color>(b"U\x01", &config).unwrap(), Color::Green);
+        assert_eq!(from_bytes_with_config::<Color>(b"Si\x03Red", &config).unwrap(), Color::Red);
+
+        match from_bytes_with_config::<Color>(b"i\x02", &config) {
+            Err(Error::Custom(msg)) => assert!(msg.contains("variant index"), "{}", msg),
+            other => panic!("Expected custom error, got {:?}", other),
+        }
+        assert!(matches!(from_bytes_with_config::<Color>(b"i\xff", &config), Err(Error::Custom(_))));
+
+        assert!(matches!(from_bytes::<'_, Color>(b"i\x00"), Err(Error::Expected(_))));
+    }
+
+    #[test]
+    fn deserializing_high_precision_number_with_noop_length_produces_expected_length() {
+        let data = b"HNi\x0212";
+        assert!(matches!(from_bytes::<'_, i64>(data), Err(Error::ExpectedLength { found: Marker::NoOp })));
+        assert!(matches!(
+            from_bytes::<'_, crate::Value>(data),
+            Err(Error::ExpectedLength { found: Marker::NoOp })
+        ));
+    }
+
+    #[test]
+    fn deserializing_container_header_distinguishes_missing_and_wrong_length() {
+        // the count marker is cut off
+        assert!(matches!(from_bytes::<'_, Vec<i8>>(b"[#"), Err(Error::Eof)));
+        assert!(matches!(from_bytes::<'_, Vec<i8>>(b"[$i#"), Err(Error::Eof)));
+
+        // a marker is there, but it cannot be a length
+        match from_bytes::<'_, Vec<i8>>(b"[#Si\x01a") {
+            Err(Error::ExpectedLength { found }) => assert_eq!(found, Marker::String),
+            other => panic!("Expected length error, got {:?}", other),
+        }
+        assert!(matches!(
+            from_bytes::<'_, Vec<i8>>(b"[$i#d\0\0\0\0"),
+            Err(Error::ExpectedLength { found: Marker::F32 })
+        ));
+    }
+
+    #[test]
+    fn deserializing_array_of_typed_arrays_can_produce_nested_vec() {
+        // `[$[#i2`: two inner arrays without their `[`, the first one typed itself
+        let data = b"[$[#i\x02$i#i\x02\x01\x02#i\x01i\x03";
+
+        let value = from_bytes::<'_, Vec<Vec<i8>>>(data).unwrap();
+        assert_eq!(value, vec![vec![1, 2], vec![3]]);
+
+        let value = from_bytes::<'_, crate::Value>(data).unwrap();
+        assert_eq!(value, crate::Value::Array(vec![
+            crate::Value::Array(vec![crate::Value::I8(1), crate::Value::I8(2)]),
+            crate::Value::Array(vec![crate::Value::I8(3)]),
+        ]));
+
+        let value = from_bytes::<'_, Vec<(i8, Option<i8>)>>(b"[$[#i\x01#i\x02i\x01Z").unwrap();
+        assert_eq!(value, vec![(1, None)]);
+    }
+
+    #[test]
+    fn deserializing_empty_container_with_empty_container_as_none_can_produce_none() {
+        let config = DeserializerConfig {
+            empty_container_as_none: true,
+            ..Default::default()
+        };
+
+        let value: Option<Vec<i32>> = from_bytes_with_config(b"[]", &config).unwrap();
+        assert_eq!(value, None);
+        let value: Option<HashMap<String, i32>> = from_bytes_with_config(b"{}", &config).unwrap();
+        assert_eq!(value, None);
+
+        // a count of zero is empty as well
+        let value: Option<Vec<i32>> = from_bytes_with_config(b"[#i\x00", &config).unwrap();
+        assert_eq!(value, None);
+        let value: Option<Vec<u8>> = from_bytes_with_config(b"[$U#i\x00", &config).unwrap();
+        assert_eq!(value, None);
+        let value: Option<HashMap<String, i32>> = from_bytes_with_config(b"{#i\x00", &config).unwrap();
+        assert_eq!(value, None);
+
+        // non-empty containers are still read
+        let value: Option<Vec<i32>> = from_bytes_with_config(b"[l\0\0\0\x07]", &config).unwrap();
+        assert_eq!(value, Some(vec![7]));
+        let value: Option<Vec<i8>> = from_bytes_with_config(b"[#i\x01i\x02", &config).unwrap();
+        assert_eq!(value, Some(vec![2]));
+        let value: Option<HashMap<String, i8>> = from_bytes_with_config(b"{i\x01ai\x01}", &config).unwrap();
+        assert_eq!(value, Some(HashMap::from([("a".to_string(), 1)])));
+        let value: Option<HashMap<String, i8>> = from_bytes_with_config(b"{#i\x01i\x01ai\x01", &config).unwrap();
+        assert_eq!(value, Some(HashMap::from([("a".to_string(), 1)])));
+        let value: Option<Vec<u8>> = from_bytes_with_config(b"[$U#i\x02\x01\x02", &config).unwrap();
+        assert_eq!(value, Some(vec![1, 2]));
+
+        // off by default
+        assert_eq!(from_bytes::<'_, Option<Vec<i32>>>(b"[]").unwrap(), Some(vec![]));
+    }
+
+    // what `serde_bytes` does for `Vec<u8>` fields
+    mod byte_buf {
+        use std::fmt;
+
+        use serde::de::{self, Visitor};
+        use serde::{Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+            struct ByteBufVisitor;
+
+            impl<'de> Visitor<'de> for ByteBufVisitor {
+                type Value = Vec<u8>;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("byte array")
+                }
+
+                fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                    Ok(v.to_vec())
+                }
+
+                fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                    Ok(v)
+                }
+            }
+
+            deserializer.deserialize_byte_buf(ByteBufVisitor)
+        }
+    }
+
+    #[test]
+    fn deserializing_byte_buf_fields_round_trips_typed_arrays() {
+        #[derive(Debug, PartialEq, serde::Serialize, Deserialize)]
+        struct Blob<'a> {
+            #[serde(with = "byte_buf")]
+            owned: Vec<u8>,
+            #[serde(serialize_with = "byte_buf::serialize")]
+            borrowed: &'a [u8],
+        }
+
+        let value = Blob {
+            owned: vec![0, 1, 255],
+            borrowed: b"raw",
+        };
+        let out = crate::to_bytes(&value).unwrap();
+        assert_eq!(out.windows(4).filter(|w| w == b"[$U#").count(), 2);
+        assert_eq!(from_bytes::<'_, Blob>(&out).unwrap(), value);
+
+        // per-byte arrays from other writers are read as well
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Owned {
+            #[serde(with = "byte_buf")]
+            data: Vec<u8>,
+        }
+        let value = from_bytes::<'_, Owned>(b"{i\x04data[U\x01U\x02]}").unwrap();
+        assert_eq!(value.data, vec![1, 2]);
+
+        // as elements of an array whose element type is itself `[`
+        let value = from_bytes::<'_, Vec<&[u8]>>(b"[$[#i\x02$U#i\x01\x07$U#i\x00").unwrap();
+        assert_eq!(value, vec![&[7u8][..], &[][..]]);
+    }
+
+    #[test]
+    fn deserializing_128_bit_ints_can_produce_values() {
+        for v in [i128::MIN, -1, 0, i128::MAX] {
+            let out = crate::to_bytes(&v).unwrap();
+            assert_eq!(out[0], b'H');
+            assert_eq!(from_bytes::<'_, i128>(&out).unwrap(), v);
+        }
+        let out = crate::to_bytes(&u128::MAX).unwrap();
+        assert_eq!(from_bytes::<'_, u128>(&out).unwrap(), u128::MAX);
+
+        // smaller integer markers widen
+        assert_eq!(from_bytes::<'_, i128>(b"i\xfe").unwrap(), -2);
+        assert_eq!(from_bytes::<'_, i128>(b"U\xc8").unwrap(), 200);
+        assert_eq!(from_bytes::<'_, u128>(b"I\x01\x00").unwrap(), 256);
+        assert!(from_bytes::<'_, u128>(b"i\xff").is_err());
+        assert!(matches!(from_bytes::<'_, i128>(b"Hi\x031.5"), Err(Error::InvalidNumber)));
+    }
+
+    #[test]
+    fn deserializing_struct_with_extra_field_calls_on_unknown_field() {
+        let mut fields = Vec::new();
+        let mut on_unknown_field = |key: &str, raw: RawValue| {
+            fields.push((key.to_string(), raw.as_bytes().to_vec()));
+        };
+
+        // {field1: 7, extra: [1, "x"], field2: "ok"}
+        let data = b"{i\x06field1l\x00\x00\x00\x07i\x05extra[i\x01Si\x01x]i\x06field2Si\x02ok}";
+        let mut de = Deserializer::new(data).on_unknown_field(&mut on_unknown_field);
+        let value = SimpleStruct::deserialize(&mut de).unwrap();
+        assert_eq!(value, SimpleStruct { field1: 7, field2: "ok".to_string() });
+        drop(de);
+
+        // values of typed objects get their marker back, and the key is taken
+        // from the path when it is tracked
+        let data = b"{$l#i\x03i\x06field1\x00\x00\x00\x07i\x05extra\x00\x00\x00\x09i\x06field2\x00\x00\x00\x08";
+        let config = DeserializerConfig { track_path: true, ..Default::default() };
+        let mut de = Deserializer::with_config(data, config).on_unknown_field(&mut on_unknown_field);
+        let value = UniformStruct::deserialize(&mut de).unwrap();
+        assert_eq!((value.field1, value.field2), (7, 8));
+        drop(de);
+
+        assert_eq!(fields, vec![
+            ("extra".to_string(), b"[i\x01Si\x01x]".to_vec()),
+            ("extra".to_string(), b"l\x00\x00\x00\x09".to_vec()),
+        ]);
+        let raw = RawValue::new(&fields[0].1);
+        assert_eq!(raw.deserialize::<(i8, String)>().unwrap(), (1, "x".to_string()));
+    }
+
+    #[test]
+    fn deserializing_newtype_keys_with_track_path_or_on_unknown_field_produces_same_map() {
+        use std::collections::BTreeMap;
+
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+        struct Name(String);
+
+        let data = b"{i\x01aU\x07i\x01bU\x08}";
+        let expected = BTreeMap::from([(Name("a".to_string()), 7u8), (Name("b".to_string()), 8)]);
+        assert_eq!(from_bytes::<BTreeMap<Name, u8>>(data).unwrap(), expected);
 
-                // try consume trailing marker
-                if let Some(m) = self.trailer {
-                    let byte = self.de.peek_byte()?;
-                    if let Ok(marker) = Marker::try_from(byte) {
-                        if marker == m {
-                            self.de.read_marker()?;
-                            self.len = Some(0);
-                        }
-                    }
-                }
+        let config = DeserializerConfig { track_path: true, ..Default::default() };
+        assert_eq!(from_bytes_with_config::<BTreeMap<Name, u8>>(data, &config).unwrap(), expected);
 
-                Ok(Some(value))
-            }
-        }
+        let mut de = Deserializer::new(data).on_unknown_field(|_, _| {});
+        assert_eq!(BTreeMap::<Name, u8>::deserialize(&mut de).unwrap(), expected);
+
+        let mut de = Deserializer::from_read(IoRead::new(&data[..])).on_unknown_field(|_, _| {});
+        assert_eq!(BTreeMap::<Name, u8>::deserialize(&mut de).unwrap(), expected);
     }
 
-    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
-        where
-            V: DeserializeSeed<'de>,
-    {
-        // hint type to the deserializer if set
-        self.de.of_type = self.of_type;
-        let value = seed.deserialize(&mut *self.de)?;
-        Ok(value)
+    #[test]
+    fn deserializing_array_with_struct_from_array_can_produce_struct() {
+        let config = DeserializerConfig { struct_from_array: true, ..Default::default() };
+        let expected = SimpleStruct { field1: 7, field2: "ok".to_string() };
+
+        let data = b"[#i\x02l\x00\x00\x00\x07Si\x02ok";
+        assert_eq!(from_bytes_with_config::<SimpleStruct>(data, &config).unwrap(), expected);
+
+        let data = b"[l\x00\x00\x00\x07Si\x02ok]";
+        assert_eq!(from_bytes_with_config::<SimpleStruct>(data, &config).unwrap(), expected);
+
+        // objects are still accepted
+        let data = b"{i\x06field1l\x00\x00\x00\x07i\x06field2Si\x02ok}";
+        assert_eq!(from_bytes_with_config::<SimpleStruct>(data, &config).unwrap(), expected);
+
+        let data = b"[l\x00\x00\x00\x07Si\x02ok]";
+        assert!(from_bytes::<'_, SimpleStruct>(data).is_err());
     }
-}
 
-struct ItemAccess<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
-}
+    #[test]
+    fn deserializing_array_of_other_length_with_struct_from_array_produces_error() {
+        let config = DeserializerConfig { struct_from_array: true, ..Default::default() };
 
-impl<'de, 'a> EnumAccess<'de> for ItemAccess<'a, 'de> {
-    type Error = Error;
-    type Variant = Self;
+        let data = b"[#i\x03l\x00\x00\x00\x07Si\x02okZ";
+        let result = from_bytes_with_config::<SimpleStruct>(data, &config);
+        assert!(matches!(result, Err(Error::InvalidLength)));
 
-    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
-        where
-            V: DeserializeSeed<'de>,
-    {
-        // objects always have string keys
-        self.de.of_type = Some(Marker::String);
-        let val = seed.deserialize(&mut *self.de)?;
-        Ok((val, self))
+        let data = b"[l\x00\x00\x00\x07Si\x02okZ]";
+        let result = from_bytes_with_config::<SimpleStruct>(data, &config);
+        assert!(matches!(result, Err(Error::InvalidLength)));
+
+        let data = b"[l\x00\x00\x00\x07]";
+        let result = from_bytes_with_config::<SimpleStruct>(data, &config);
+        assert!(matches!(result, Err(Error::Custom(_))));
     }
-}
 
-impl<'de, 'a> VariantAccess<'de> for ItemAccess<'a, 'de> {
-    type Error = Error;
+    #[test]
+    fn deserializing_truncated_char_produces_eof() {
+        assert!(matches!(from_bytes::<'_, char>(b"C"), Err(Error::Eof)));
+        assert!(matches!(from_bytes::<'_, &str>(b"C"), Err(Error::Eof)));
+        assert!(matches!(from_bytes::<'_, String>(b"C"), Err(Error::Eof)));
+        assert!(matches!(from_bytes::<'_, crate::Value>(b"C"), Err(Error::Eof)));
+        assert!(matches!(from_slices::<char>(&[b"C", b""]), Err(Error::Eof)));
+    }
 
-    fn unit_variant(self) -> Result<()> {
-        Err(Error::InvalidMarker)
+    #[test]
+    fn deserializing_reader_can_produce_value() {
+        let data = b"{i\x06field1l\x00\x00\x00\x07i\x06field2Si\x02ok}";
+        let value = from_reader::<_, SimpleStruct>(Cursor::new(&data[..])).unwrap();
+        assert_eq!(value, SimpleStruct { field1: 7, field2: "ok".to_string() });
+
+        assert!(matches!(from_reader::<_, SimpleStruct>(Cursor::new(&data[..10])), Err(Error::Eof)));
     }
 
-    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
-        where
-            T: DeserializeSeed<'de>,
-    {
-        seed.deserialize(self.de)
+    #[test]
+    fn reading_array_elements_from_reader_produces_each_element() {
+        let values: Vec<i32> = (0..1000).collect();
+        let data = crate::to_bytes(&values).unwrap();
+
+        let elements = read_array_elements::<_, i32>(Cursor::new(&data));
+        assert_eq!(elements.collect::<Result<Vec<_>>>().unwrap(), values);
+
+        // unsized and typed arrays
+        let elements = read_array_elements::<_, String>(Cursor::new(b"[Si\x01aSi\x01b]"));
+        assert_eq!(elements.collect::<Result<Vec<_>>>().unwrap(), vec!["a", "b"]);
+
+        let elements = read_array_elements::<_, i8>(Cursor::new(b"[$i#i\x03\x01\x02\x03"));
+        assert_eq!(elements.collect::<Result<Vec<_>>>().unwrap(), vec![1, 2, 3]);
     }
 
-    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
-        where
-            V: Visitor<'de>,
-    {
-        serde::de::Deserializer::deserialize_seq(self.de, visitor)
+    #[test]
+    fn reading_array_elements_from_truncated_reader_stops_at_error() {
+        let mut elements = read_array_elements::<_, i8>(Cursor::new(b"[i\x01i"));
+        assert_eq!(elements.next().unwrap().unwrap(), 1);
+        assert!(matches!(elements.next(), Some(Err(Error::Eof))));
+        assert!(elements.next().is_none());
+
+        let mut elements = read_array_elements::<_, i8>(Cursor::new(b"i\x01"));
+        assert!(matches!(elements.next(), Some(Err(Error::Expected(_)))));
+        assert!(elements.next().is_none());
     }
 
-    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
-        where
-            V: Visitor<'de>,
-    {
-        serde::de::Deserializer::deserialize_map(self.de, visitor)
+    #[test]
+    fn deserializing_out_of_range_high_precision_number_into_f64_produces_infinity() {
+        let data = b"Hi\x051e400";
+        assert_eq!(from_bytes::<'_, f64>(data).unwrap(), f64::INFINITY);
+        assert_eq!(from_bytes::<'_, f64>(b"Hi\x06-1e400").unwrap(), f64::NEG_INFINITY);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+    #[test]
+    fn deserializing_out_of_range_high_precision_number_with_reject_non_finite_produces_error() {
+        let config = DeserializerConfig { reject_non_finite: true, ..Default::default() };
 
-    use super::*;
+        let result = from_bytes_with_config::<f64>(b"Hi\x051e400", &config);
+        assert!(matches!(result, Err(Error::NonFiniteFloat)));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Data);
 
-    #[derive(Deserialize)]
-    struct SimpleStruct {
-        field1: i32,
-        field2: String,
+        assert_eq!(from_bytes_with_config::<f64>(b"Hi\x041e30", &config).unwrap(), 1e30);
     }
 
-    #[derive(Deserialize)]
-    struct UniformStruct {
-        field1: i32,
-        field2: i32,
+    #[test]
+    fn deserializing_integer_bool_in_lenient_mode_can_produce_bool() {
+        let config = DeserializerConfig { lenient: true, ..Default::default() };
+        assert!(!from_bytes_with_config::<bool>(b"i\x00", &config).unwrap());
+        assert!(from_bytes_with_config::<bool>(b"i\x01", &config).unwrap());
+        assert!(from_bytes_with_config::<bool>(b"U\x01", &config).unwrap());
+
+        let value = from_bytes_with_config::<Vec<bool>>(b"[i\x01U\x00T]", &config).unwrap();
+        assert_eq!(value, vec![true, false, true]);
+
+        let result = from_bytes_with_config::<bool>(b"i\x02", &config);
+        assert!(matches!(result, Err(Error::Custom(_))));
+
+        assert!(matches!(from_bytes::<'_, bool>(b"i\x01"), Err(Error::Expected(_))));
     }
 
-    #[derive(Deserialize)]
-    enum SimpleEnum {
-        Unit,
-        NewType(i32),
-        Tuple(i32, i32),
-        Struct { field1: i32, field2: i32 },
+    #[test]
+    fn deserializing_stream_with_max_values_stops_after_limit() {
+        let data = b"i\x01i\x02i\x03";
+
+        let mut values = Deserializer::new(data).into_iter::<i8>().max_values(2);
+        assert_eq!(values.next().unwrap().unwrap(), 1);
+        assert_eq!(values.next().unwrap().unwrap(), 2);
+        match values.next() {
+            Some(Err(Error::LimitExceeded { limit })) => assert_eq!(limit, "max_values"),
+            other => panic!("Expected limit error, got {:?}", other),
+        }
+        assert!(values.next().is_none());
+
+        // input holding exactly the limit ends normally
+        let values = Deserializer::new(data).into_iter::<i8>().max_values(3);
+        assert_eq!(values.collect::<Result<Vec<_>>>().unwrap(), vec![1, 2, 3]);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn deserializing_big_t_value_can_produce_true() {
         let data = b"T";
         let value = from_bytes::<'_, bool>(data).unwrap();
@@ -848,6 +2880,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn deserializing_big_f_value_can_produce_false() {
         let data = b"F";
         let value = from_bytes::<'_, bool>(data).unwrap();
@@ -972,6 +3005,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::byte_char_slices)]
     fn deserializing_big_c_value_can_produce_char() {
         let data = &[b'C', b'A'];
 
@@ -1086,6 +3120,27 @@ mod tests {
     }
 
     #[test]
+    fn deserializing_big_h_value_of_big_i_len_can_produce_u64() {
+        let mut data = vec![b'H', b'I'];
+        data.extend_from_slice(&20i16.to_be_bytes());
+        data.extend_from_slice(u64::MAX.to_string().as_bytes());
+
+        let value = from_bytes::<'_, u64>(&data).unwrap();
+        assert_eq!(value, u64::MAX);
+    }
+
+    #[test]
+    fn deserializing_big_h_value_of_small_i_len_can_produce_f64() {
+        let mut data = vec![b'H', b'i'];
+        data.extend_from_slice(&4i8.to_be_bytes());
+        data.extend_from_slice(b"1.25");
+
+        let value = from_bytes::<'_, f64>(&data).unwrap();
+        assert_eq!(value, 1.25);
+    }
+
+    #[test]
+    #[allow(clippy::byte_char_slices, clippy::redundant_pattern_matching)]
     fn deserializing_big_z_value_can_produce_none() {
         let data = &[b'Z'];
 
@@ -1105,6 +3160,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::byte_char_slices, clippy::let_unit_value)]
     fn deserializing_big_z_value_can_produce_unit() {
         let data = &[b'Z'];
 
@@ -1117,7 +3173,7 @@ mod tests {
         let data = b"[]";
 
         let value = from_bytes::<'_, Vec<i8>>(data).unwrap();
-        assert_eq!(value, vec![]);
+        assert_eq!(value, Vec::<i8>::new());
     }
 
     #[test]
@@ -1128,7 +3184,7 @@ mod tests {
         data.extend_from_slice(&0i8.to_be_bytes());
 
         let value = from_bytes::<'_, Vec<i8>>(&data).unwrap();
-        assert_eq!(value, vec![]);
+        assert_eq!(value, Vec::<i8>::new());
     }
 
     #[test]
@@ -1300,6 +3356,27 @@ mod tests {
 
         let value = from_bytes::<'_, &[u8]>(&data).unwrap();
         assert_eq!(value, &[1u8, 2u8, 3u8, 4u8]);
+
+        let value = from_bytes::<'_, ByteBuf>(&data).unwrap();
+        assert_eq!(value, ByteBuf(vec![1u8, 2u8, 3u8, 4u8]));
+    }
+
+    #[test]
+    fn deserializing_open_bracket_with_len_and_big_u_values_can_produce_owned_bytes() {
+        let data = b"[#i\x03U\x01U\x02U\x03";
+
+        let value = from_bytes::<'_, ByteBuf>(data).unwrap();
+        assert_eq!(value, ByteBuf(vec![1u8, 2u8, 3u8]));
+
+        let value = from_bytes::<'_, ByteBuf>(b"[U\x01U\x02]").unwrap();
+        assert_eq!(value, ByteBuf(vec![1u8, 2u8]));
+
+        // interleaved markers can not be borrowed
+        assert!(from_bytes::<'_, &[u8]>(data).is_err());
+        assert!(matches!(
+            from_bytes::<'_, ByteBuf>(b"[#i\x02U\x01i\x02"),
+            Err(Error::Expected(_))
+        ));
     }
 
     #[test]
@@ -1711,4 +3788,391 @@ mod tests {
             _ => panic!("Expected struct"),
         }
     }
+
+    #[test]
+    fn deserializing_typed_objects_can_produce_each_variant_of_enum() {
+        let data = b"{$Z#i\x01i\x04Unit";
+        assert_eq!(from_bytes::<'_, SimpleEnum>(data).unwrap(), SimpleEnum::Unit);
+
+        let data = b"{#i\x01i\x04UnitZ";
+        assert_eq!(from_bytes::<'_, SimpleEnum>(data).unwrap(), SimpleEnum::Unit);
+
+        let mut data = b"{$l#i\x01i\x07NewType".to_vec();
+        data.extend_from_slice(&7i32.to_be_bytes());
+        assert_eq!(from_bytes::<'_, SimpleEnum>(&data).unwrap(), SimpleEnum::NewType(7));
+
+        let mut data = b"{$[#i\x01i\x05Tuple#i\x02".to_vec();
+        data.push(b'l');
+        data.extend_from_slice(&1i32.to_be_bytes());
+        data.push(b'l');
+        data.extend_from_slice(&2i32.to_be_bytes());
+        assert_eq!(from_bytes::<'_, SimpleEnum>(&data).unwrap(), SimpleEnum::Tuple(1, 2));
+
+        let mut data = b"{$[#i\x01i\x05Tuple$l#i\x02".to_vec();
+        data.extend_from_slice(&1i32.to_be_bytes());
+        data.extend_from_slice(&2i32.to_be_bytes());
+        assert_eq!(from_bytes::<'_, SimpleEnum>(&data).unwrap(), SimpleEnum::Tuple(1, 2));
+
+        let mut data = b"{${#i\x01i\x06Struct$l#i\x02".to_vec();
+        data.extend_from_slice(b"i\x06field1");
+        data.extend_from_slice(&1i32.to_be_bytes());
+        data.extend_from_slice(b"i\x06field2");
+        data.extend_from_slice(&2i32.to_be_bytes());
+        assert_eq!(
+            from_bytes::<'_, SimpleEnum>(&data).unwrap(),
+            SimpleEnum::Struct { field1: 1, field2: 2 }
+        );
+    }
+
+    #[test]
+    fn deserializing_enum_object_with_more_than_1_entry_produces_error() {
+        let mut data = b"{#i\x02i\x07NewTypel".to_vec();
+        data.extend_from_slice(&7i32.to_be_bytes());
+        assert!(matches!(from_bytes::<'_, SimpleEnum>(&data), Err(Error::InvalidLength)));
+    }
+
+    #[test]
+    fn deserializing_serialized_enums_can_produce_same_variants() {
+        let values = vec![
+            SimpleEnum::Unit,
+            SimpleEnum::NewType(7),
+            SimpleEnum::Tuple(1, 2),
+            SimpleEnum::Struct { field1: 1, field2: 2 },
+        ];
+
+        let config = crate::SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+        for out in [crate::to_bytes(&values).unwrap(), crate::to_bytes_with_config(&values, &config).unwrap()] {
+            assert_eq!(from_bytes::<'_, Vec<SimpleEnum>>(&out).unwrap(), values);
+        }
+    }
+
+    #[test]
+    fn deserializing_typed_object_can_produce_newtype_and_tuple_structs() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Meters(i32);
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Point(i8, i8);
+
+        let mut data = b"{$l#i\x01i\x01a".to_vec();
+        data.extend_from_slice(&5i32.to_be_bytes());
+        let value = from_bytes::<'_, HashMap<String, Meters>>(&data).unwrap();
+        assert_eq!(value, HashMap::from([("a".to_string(), Meters(5))]));
+
+        let data = b"{$[#i\x01i\x01p$i#i\x02\x01\x02";
+        let value = from_bytes::<'_, HashMap<String, Point>>(data).unwrap();
+        assert_eq!(value, HashMap::from([("p".to_string(), Point(1, 2))]));
+    }
+
+    fn simple_struct_bytes(field1: i32, field2: &str) -> Vec<u8> {
+        let mut data = vec![b'{', b'#', b'i'];
+        data.extend_from_slice(&2i8.to_be_bytes());
+
+        data.extend_from_slice(b"i");
+        data.extend_from_slice(&6i8.to_be_bytes());
+        data.extend_from_slice(b"field1");
+        data.extend_from_slice(b"l");
+        data.extend_from_slice(&field1.to_be_bytes());
+
+        data.extend_from_slice(b"i");
+        data.extend_from_slice(&6i8.to_be_bytes());
+        data.extend_from_slice(b"field2");
+        data.extend_from_slice(b"S");
+        data.extend_from_slice(b"i");
+        data.extend_from_slice(&(field2.len() as i8).to_be_bytes());
+        data.extend_from_slice(field2.as_bytes());
+
+        data
+    }
+
+    #[test]
+    fn deserializing_stream_of_concatenated_values_produces_each_value() {
+        let mut data = simple_struct_bytes(1, "one");
+        data.extend_from_slice(b"N");
+        data.extend_from_slice(&simple_struct_bytes(2, "two"));
+
+        let values = Deserializer::new(&data)
+            .into_iter::<SimpleStruct>()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].field2, "one");
+        assert_eq!(values[1].field2, "two");
+    }
+
+    #[test]
+    fn deserializing_count_beyond_input_fails_before_allocating() {
+        let result = from_bytes::<Vec<u8>>(b"[#l\x7f\xff\xff\xffU\x01");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Truncated);
+
+        let result = from_bytes::<Vec<i64>>(b"[$L#U\x02\0\0\0\0\0\0\0\x01");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Truncated);
+
+        let result = from_slices::<Vec<u8>>(&[b"[#l\x7f\xff", b"\xff\xffU\x01"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Truncated);
+
+        // elements without a payload take no space
+        let units = from_bytes::<Vec<()>>(b"[$Z#l\x00\x0f\x42\x40").unwrap();
+        assert_eq!(units.len(), 1_000_000);
+    }
+
+    #[test]
+    fn deserializing_huge_count_of_empty_elements_produces_error() {
+        let input = b"[$Z#L\x00\x00\x01\x00\x00\x00\x00\x00";
+        let limit = |result: Result<_>| matches!(result, Err(Error::LimitExceeded { limit: "max_empty_elements" }));
+
+        assert!(limit(from_bytes::<Vec<Option<u8>>>(input).map(drop)));
+        assert!(limit(from_bytes::<de::IgnoredAny>(input).map(drop)));
+        assert!(limit(from_reader::<_, Vec<()>>(&input[..]).map(drop)));
+
+        // copied whole for an unknown field
+        let data = b"{i\x05extra[$Z#L\x00\x00\x01\x00\x00\x00\x00\x00}";
+        let mut de = Deserializer::new(data).on_unknown_field(|_, _| {});
+        assert!(limit(SimpleStruct::deserialize(&mut de).map(drop)));
+    }
+
+    #[test]
+    fn deserializing_long_string_from_short_reader_produces_error() {
+        let result = from_reader::<_, String>(&b"Sl\x7f\xff\xff\xffabc"[..]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Truncated);
+
+        let result = from_slices::<String>(&[b"Sl\x7f\xff\xff\xffab", b"c"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Truncated);
+
+        let value = from_reader::<_, String>(&b"Si\x03abc"[..]).unwrap();
+        assert_eq!(value, "abc");
+    }
+
+    #[test]
+    fn deserializing_strictly_with_trailing_data_produces_error() {
+        assert_eq!(from_bytes_strict::<u8>(b"U\x05NN").unwrap(), 5);
+        assert!(matches!(from_bytes_strict::<u8>(b"U\x05U\x06"), Err(Error::TrailingData)));
+        assert!(matches!(from_bytes_strict::<u8>(b"U\x05NZ"), Err(Error::TrailingData)));
+        assert_eq!(from_bytes::<u8>(b"U\x05U\x06").unwrap(), 5);
+    }
+
+    #[test]
+    fn ending_reader_deserializer_checks_remaining_input() {
+        let mut de = Deserializer::from_read(IoRead::new(&b"TN"[..]));
+        assert!(bool::deserialize(&mut de).unwrap());
+        de.end().unwrap();
+
+        let mut de = Deserializer::from_read(IoRead::new(&b"TF"[..]));
+        assert!(bool::deserialize(&mut de).unwrap());
+        assert!(matches!(de.end(), Err(Error::TrailingData)));
+    }
+
+    #[test]
+    fn deserializing_stream_reports_offset_of_each_value() {
+        let first = simple_struct_bytes(1, "one");
+        let mut data = first.clone();
+        data.extend_from_slice(b"NN");
+        data.extend_from_slice(&simple_struct_bytes(2, "two"));
+        data.extend_from_slice(b"N");
+
+        let mut stream = Deserializer::new(&data).into_iter::<SimpleStruct>();
+        let mut offsets = Vec::new();
+        while let Some(value) = stream.next() {
+            offsets.push((value.unwrap().field1, stream.value_offset()));
+        }
+
+        assert_eq!(offsets, vec![(1, 0), (2, first.len() + 2)]);
+        assert_eq!(stream.byte_offset(), data.len());
+    }
+
+    #[test]
+    fn deserializing_stream_in_recovery_mode_skips_corrupted_value() {
+        let first = simple_struct_bytes(1, "one");
+        let mut second = simple_struct_bytes(2, "two");
+        second[0] = b'x';
+        let third = simple_struct_bytes(3, "three");
+
+        let mut data = first.clone();
+        data.extend_from_slice(&second);
+        data.extend_from_slice(&third);
+
+        let mut stream = Deserializer::new(&data)
+            .into_iter::<SimpleStruct>()
+            .recover(true);
+
+        assert_eq!(stream.next().unwrap().unwrap().field1, 1);
+
+        match stream.next().unwrap() {
+            Err(Error::Skipped { start, end, cause }) => {
+                assert_eq!(start, first.len());
+                assert_eq!(end, first.len() + second.len());
+                assert_eq!(cause.kind(), ErrorKind::Malformed);
+            }
+            _ => panic!("Expected skipped error"),
+        }
+
+        assert_eq!(stream.next().unwrap().unwrap().field1, 3);
+        assert_eq!(stream.value_offset(), first.len() + second.len());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn deserializing_stream_in_recovery_mode_skips_whole_value_of_wrong_type() {
+        let data = b"SU\x02i\x05i\x07";
+
+        let mut stream = Deserializer::new(data).into_iter::<i8>().recover(true);
+
+        match stream.next().unwrap() {
+            Err(Error::Skipped { start, end, .. }) => assert_eq!((start, end), (0, 5)),
+            _ => panic!("Expected skipped error"),
+        }
+        assert_eq!(stream.next().unwrap().unwrap(), 7);
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn deserializing_stream_in_recovery_mode_does_not_decode_payload_bytes() {
+        let data = b"xU\x09zzU\x07";
+
+        let mut stream = Deserializer::new(data).into_iter::<u8>().recover(true);
+
+        match stream.next().unwrap() {
+            Err(Error::Skipped { start, end, .. }) => assert_eq!((start, end), (0, 5)),
+            _ => panic!("Expected skipped error"),
+        }
+        assert_eq!(stream.next().unwrap().unwrap(), 7);
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn deserializing_stream_in_recovery_mode_gives_up_past_resync_window() {
+        let data = b"xU\x09zzU\x07";
+
+        let mut stream = Deserializer::new(data)
+            .into_iter::<u8>()
+            .recover(true)
+            .resync_window(2);
+
+        match stream.next().unwrap() {
+            Err(Error::Skipped { start, end, .. }) => assert_eq!((start, end), (0, data.len())),
+            _ => panic!("Expected skipped error"),
+        }
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn deserializing_stream_in_recovery_mode_stops_at_truncated_value() {
+        let mut data = simple_struct_bytes(1, "one");
+        let second = simple_struct_bytes(2, "two");
+        data.extend_from_slice(&second[..second.len() - 2]);
+
+        let mut stream = Deserializer::new(&data)
+            .into_iter::<SimpleStruct>()
+            .recover(true);
+
+        assert_eq!(stream.next().unwrap().unwrap().field1, 1);
+        match stream.next().unwrap() {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::Truncated),
+            _ => panic!("Expected error"),
+        }
+        assert!(stream.next().is_none());
+    }
+
+    fn assert_same_when_split<'a, T>(data: &'a [u8])
+        where
+            T: Deserialize<'a> + PartialEq + std::fmt::Debug,
+    {
+        let expected = from_bytes::<'_, T>(data).unwrap();
+
+        for i in 0..=data.len() {
+            let segments = [&data[..i], &data[i..]];
+            assert_eq!(from_slices::<T>(&segments).unwrap(), expected, "split at {}", i);
+        }
+
+        let segments = data.chunks(1).collect::<Vec<_>>();
+        assert_eq!(from_slices::<T>(&segments).unwrap(), expected);
+    }
+
+    #[test]
+    fn deserializing_segmented_input_produces_same_value_as_contiguous_input() {
+        assert_same_when_split::<SimpleStruct>(&simple_struct_bytes(1024, "value"));
+
+        let mut data = vec![b'[', b'$', b'S', b'#', b'i', 2];
+        data.extend_from_slice(b"i\x03one");
+        data.extend_from_slice(b"i\x03two");
+        assert_same_when_split::<Vec<String>>(&data);
+
+        let mut data = vec![b'[', b'$', b'U', b'#', b'i', 4];
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        assert_same_when_split::<Vec<u8>>(&data);
+
+        let mut data = vec![b'H', b'I'];
+        data.extend_from_slice(&3i16.to_be_bytes());
+        data.extend_from_slice(b"1.5");
+        assert_same_when_split::<f64>(&data);
+    }
+
+    #[test]
+    fn deserializing_segmented_input_borrows_strings_within_one_segment() {
+        let data = b"Si\x05hello";
+
+        let value = from_slices::<&str>(&[&data[..2], &data[2..]]).unwrap();
+        assert_eq!(value, "hello");
+
+        // a straddling string can only be copied
+        assert!(from_slices::<&str>(&[&data[..4], &data[4..]]).is_err());
+        assert_eq!(from_slices::<String>(&[&data[..4], &data[4..]]).unwrap(), "hello");
+    }
+
+    #[test]
+    fn deserializing_open_brace_with_length_exceeding_entries_produces_unexpected_eof() {
+        let mut data = vec![b'{', b'#', b'i'];
+        data.extend_from_slice(&3i8.to_be_bytes());
+
+        data.extend_from_slice(b"i");
+        data.extend_from_slice(&1i8.to_be_bytes());
+        data.extend_from_slice(b"a");
+        data.extend_from_slice(b"i");
+        data.extend_from_slice(&1i8.to_be_bytes());
+
+        match from_bytes::<'_, HashMap<String, i8>>(&data) {
+            Err(Error::UnexpectedEof { context }) => assert_eq!(context, "object entry"),
+            _ => panic!("Expected unexpected eof error"),
+        }
+    }
+
+    #[test]
+    fn deserializing_float_key_text_can_produce_floats() {
+        let key = |text| KeyDeserializer { key: Reference::Borrowed(text), coerce: true };
+        assert_eq!(f64::deserialize(key("-0.25")).unwrap(), -0.25);
+        assert_eq!(f32::deserialize(key("1.5")).unwrap(), 1.5);
+        assert!(f64::deserialize(key("one")).is_err());
+    }
+
+    #[test]
+    fn deserializing_newtype_and_enum_keys_with_coerce_int_keys_produces_map() {
+        use std::collections::BTreeMap;
+
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+        struct Id(u32);
+
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+        struct Name(String);
+
+        #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+        enum Kind {
+            A,
+            B,
+        }
+
+        let config = DeserializerConfig { coerce_int_keys: true, ..Default::default() };
+
+        let value: BTreeMap<Id, u8> = from_bytes_with_config(b"{i\x011U\x07i\x0212U\x08}", &config).unwrap();
+        assert_eq!(value, BTreeMap::from([(Id(1), 7), (Id(12), 8)]));
+
+        let value: BTreeMap<Name, u8> = from_bytes_with_config(b"{i\x01aU\x07}", &config).unwrap();
+        assert_eq!(value, BTreeMap::from([(Name("a".to_string()), 7)]));
+
+        let value: BTreeMap<Kind, u8> = from_bytes_with_config(b"{i\x01BU\x07i\x01AU\x08}", &config).unwrap();
+        assert_eq!(value, BTreeMap::from([(Kind::A, 8), (Kind::B, 7)]));
+    }
 }
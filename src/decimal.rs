@@ -0,0 +1,121 @@
+//! Encodes [`rust_decimal::Decimal`] as a high-precision number (`H`), using
+//! its canonical string form, so no precision is lost to floating point.
+//! Requires the `decimal` feature.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Payment {
+//!     #[serde(with = "serde_ub_json::decimal")]
+//!     amount: Decimal,
+//! }
+//! ```
+use std::fmt;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::de::{Error, MapAccess, Visitor};
+use serde::{Deserializer, Serializer};
+
+use crate::value::NUMBER_TOKEN;
+
+pub fn serialize<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+{
+    serializer.serialize_newtype_struct(NUMBER_TOKEN, &value.to_string())
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(DecimalVisitor)
+}
+
+struct DecimalVisitor;
+
+impl DecimalVisitor {
+    fn parse<E>(digits: &str) -> Result<Decimal, E>
+        where
+            E: Error,
+    {
+        Decimal::from_str(digits)
+            .or_else(|_| Decimal::from_scientific(digits))
+            .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(digits), &DecimalVisitor))
+    }
+}
+
+impl<'de> Visitor<'de> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal number")
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Decimal, E> {
+        Ok(Decimal::from(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Decimal, E>
+        where
+            E: Error,
+    {
+        DecimalVisitor::parse(v)
+    }
+
+    // high-precision numbers arrive as a single entry map, see `Value`
+    fn visit_map<A>(self, mut map: A) -> Result<Decimal, A::Error>
+        where
+            A: MapAccess<'de>,
+    {
+        match map.next_key::<String>()? {
+            Some(key) if key == NUMBER_TOKEN => {
+                let digits = map.next_value::<String>()?;
+                DecimalVisitor::parse(&digits)
+            }
+            _ => Err(A::Error::custom("expected a high-precision number")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{from_bytes, to_bytes};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Payment {
+        #[serde(with = "crate::decimal")]
+        amount: Decimal,
+    }
+
+    #[test]
+    fn serializing_decimals_produces_big_h_values_that_round_trip() {
+        for digits in ["0", "1.50", "-12.345", "79228162514264337593543950335", "-0.0000001"] {
+            let value = Payment { amount: Decimal::from_str(digits).unwrap() };
+            let out = to_bytes(&value).unwrap();
+
+            let mut expected = b"HL".to_vec();
+            expected.extend_from_slice(&(digits.len() as i64).to_be_bytes());
+            expected.extend_from_slice(digits.as_bytes());
+            assert!(out.windows(expected.len()).any(|w| w == &expected[..]));
+
+            let decoded = from_bytes::<Payment>(&out).unwrap();
+            assert_eq!(decoded, value);
+            assert_eq!(decoded.amount.to_string(), digits);
+        }
+    }
+
+    #[test]
+    fn deserializing_big_h_value_with_invalid_digits_produces_error() {
+        let mut data = b"{#i\x01i\x06amountHi\x03".to_vec();
+        data.extend_from_slice(b"1.x");
+        assert!(from_bytes::<Payment>(&data).is_err());
+    }
+}
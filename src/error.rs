@@ -5,14 +5,97 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 pub enum Error {
     Io(std::io::Error),
-    InvalidKey,
+    InvalidKey {
+        found: &'static str,
+    },
     InvalidMarker,
     InvalidString,
+    InvalidNumber,
+    NonFiniteFloat,
     TrailingData,
     Custom(String),
     Eof,
-    ExpectedLength,
+    UnexpectedEof {
+        context: &'static str,
+    },
+    ExpectedLength {
+        found: Marker,
+    },
+    InvalidLength,
+    LimitExceeded {
+        limit: &'static str,
+    },
+    DepthLimitExceeded {
+        max_depth: usize,
+    },
     Expected(Vec<Marker>),
+    Skipped {
+        start: usize,
+        end: usize,
+        cause: Box<Error>,
+    },
+}
+
+/// Broad classification of an [`Error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The underlying reader or writer failed.
+    Io,
+    /// The input ended in the middle of a value.
+    Truncated,
+    /// The input is not valid UBJSON.
+    Malformed,
+    /// The input is valid UBJSON but does not fit the target type.
+    Data,
+}
+
+impl Error {
+    /// Names what was being read when the input ended.
+    pub(crate) fn in_context(self, context: &'static str) -> Error {
+        match self {
+            Error::Eof => Error::UnexpectedEof { context },
+            e => e,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(_) => ErrorKind::Io,
+            Error::Eof | Error::UnexpectedEof { .. } => ErrorKind::Truncated,
+            Error::Custom(_)
+            | Error::NonFiniteFloat
+            | Error::LimitExceeded { .. }
+            | Error::DepthLimitExceeded { .. } => ErrorKind::Data,
+            Error::Skipped { cause, .. } => cause.kind(),
+            _ => ErrorKind::Malformed,
+        }
+    }
+}
+
+/// Step on the way from the root value to the value being processed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+impl Error {
+    /// Appends the location of the failing value to custom errors.
+    pub(crate) fn at_path(self, path: &[PathSegment]) -> Error {
+        match self {
+            Error::Custom(msg) if !path.is_empty() => {
+                let mut location = String::new();
+                for segment in path {
+                    match segment {
+                        PathSegment::Key(key) => location.push_str(&format!(".{}", key)),
+                        PathSegment::Index(index) => location.push_str(&format!("[{}]", index)),
+                    }
+                }
+                Error::Custom(format!("{} at {}", msg, location))
+            }
+            e => e,
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -33,13 +116,23 @@ impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Io(e) => write!(f, "{}", e),
-            Error::InvalidKey => write!(f, "invalid key"),
+            Error::InvalidKey { found } => write!(f, "invalid key: expected string, found {}", found),
             Error::InvalidMarker => write!(f, "invalid marker"),
             Error::InvalidString => write!(f, "invalid string"),
+            Error::InvalidNumber => write!(f, "invalid number"),
+            Error::NonFiniteFloat => write!(f, "non-finite float"),
             Error::TrailingData => write!(f, "trailing data"),
             Error::Custom(s) => write!(f, "{}", s),
             Error::Eof => write!(f, "end of input"),
-            Error::ExpectedLength => write!(f, "expected length"),
+            Error::UnexpectedEof { context } => write!(f, "unexpected end of input in {}", context),
+            Error::ExpectedLength { found } => {
+                write!(f, "expected length, found marker {}", *found as u8 as char)
+            }
+            Error::InvalidLength => write!(f, "invalid length"),
+            Error::LimitExceeded { limit } => write!(f, "limit exceeded: {}", limit),
+            Error::DepthLimitExceeded { max_depth } => {
+                write!(f, "nesting deeper than {} levels", max_depth)
+            }
             Error::Expected(markers) => {
                 write!(f, "expected markers:")?;
                 for c in markers.iter().map(|m| *m as u8 as char) {
@@ -47,6 +140,9 @@ impl Display for Error {
                 }
                 Ok(())
             }
+            Error::Skipped { start, end, cause } => {
+                write!(f, "skipped bytes {}..{}: {}", start, end, cause)
+            }
         }
     }
 }
@@ -0,0 +1,192 @@
+use std::io::{self, Read};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use crate::de::from_bytes;
+use crate::read::MAX_PREALLOC;
+use crate::{Error, Result};
+
+// frames are prefixed with their payload length as a big-endian u32
+const PREFIX_LEN: usize = 4;
+
+/// Reads one length-prefixed frame and decodes its payload.
+///
+/// The prefix is the payload length as a big-endian `u32`. Input ending
+/// anywhere inside the frame is reported as `Error::UnexpectedEof`.
+pub fn read_framed<R, T>(reader: &mut R) -> Result<T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    let mut payload = Vec::new();
+    match read_frame(reader, &mut payload)? {
+        true => from_bytes(&payload),
+        false => Err(Error::UnexpectedEof { context: "frame length" }),
+    }
+}
+
+/// Iterator over the values of a length-prefixed frame stream, see
+/// [`read_framed`].
+///
+/// Iteration ends when the input ends cleanly between two frames; input
+/// ending inside a frame yields an error instead.
+pub struct FrameReader<R, T> {
+    reader: R,
+    payload: Vec<u8>,
+    done: bool,
+    output: PhantomData<T>,
+}
+
+impl<R, T> FrameReader<R, T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    pub fn new(reader: R) -> FrameReader<R, T> {
+        FrameReader {
+            reader,
+            payload: Vec::new(),
+            done: false,
+            output: PhantomData,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, T> Iterator for FrameReader<R, T>
+    where
+        R: io::Read,
+        T: DeserializeOwned,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = match read_frame(&mut self.reader, &mut self.payload) {
+            Ok(true) => from_bytes(&self.payload),
+            Ok(false) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => Err(e),
+        };
+
+        // framing is lost after a read error, a bad payload only spoils its own frame
+        if matches!(result, Err(Error::Io(_)) | Err(Error::UnexpectedEof { .. })) {
+            self.done = true;
+        }
+        Some(result)
+    }
+}
+
+// reads the next frame into `payload`, returns false on a clean end of input
+fn read_frame<R>(reader: &mut R, payload: &mut Vec<u8>) -> Result<bool>
+    where
+        R: io::Read,
+{
+    let mut prefix = [0u8; PREFIX_LEN];
+    let read = fill(reader, &mut prefix)?;
+    if read == 0 {
+        return Ok(false);
+    }
+    if read < PREFIX_LEN {
+        return Err(Error::UnexpectedEof { context: "frame length" });
+    }
+
+    // the buffer grows as the payload arrives, a bogus prefix does not
+    // allocate gigabytes up front
+    let len = u32::from_be_bytes(prefix) as usize;
+    payload.clear();
+    payload.reserve(len.min(MAX_PREALLOC));
+    reader.take(len as u64).read_to_end(payload).map_err(Error::Io)?;
+    if payload.len() < len {
+        return Err(Error::UnexpectedEof { context: "frame payload" });
+    }
+    Ok(true)
+}
+
+// like read_exact, but reports how much was read before the input ended
+fn fill<R>(reader: &mut R, buf: &mut [u8]) -> Result<usize>
+    where
+        R: io::Read,
+{
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::to_bytes;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn reading_three_frames_produces_three_values() {
+        let mut input = Vec::new();
+        input.extend(frame(&to_bytes(&"one").unwrap()));
+        input.extend(frame(&to_bytes(&"two").unwrap()));
+        input.extend(frame(&to_bytes(&"three").unwrap()));
+
+        let values = FrameReader::<_, String>::new(Cursor::new(input))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(values, vec!["one", "two", "three"]);
+    }
+
+    #[test]
+    fn reading_truncated_frame_produces_unexpected_eof() {
+        let mut input = frame(&to_bytes(&1i32).unwrap());
+        let second = frame(&to_bytes(&2i32).unwrap());
+        input.extend_from_slice(&second[..second.len() - 1]);
+
+        let mut frames = FrameReader::<_, i32>::new(Cursor::new(input));
+        assert_eq!(frames.next().unwrap().unwrap(), 1);
+        assert!(matches!(
+            frames.next(),
+            Some(Err(Error::UnexpectedEof { context: "frame payload" }))
+        ));
+        assert!(frames.next().is_none());
+
+        let input = vec![0u8, 0];
+        let mut frames = FrameReader::<_, i32>::new(Cursor::new(input));
+        assert!(matches!(
+            frames.next(),
+            Some(Err(Error::UnexpectedEof { context: "frame length" }))
+        ));
+    }
+
+    #[test]
+    fn reading_frame_with_bogus_length_produces_unexpected_eof() {
+        let input = b"\xff\xff\xff\xffU\x01".to_vec();
+        let result = read_framed::<_, u8>(&mut Cursor::new(input));
+        assert!(matches!(result, Err(Error::UnexpectedEof { context: "frame payload" })));
+    }
+
+    #[test]
+    fn reading_framed_value_from_empty_input_produces_unexpected_eof() {
+        let result = read_framed::<_, i32>(&mut Cursor::new(Vec::new()));
+        assert!(matches!(result, Err(Error::UnexpectedEof { .. })));
+    }
+}
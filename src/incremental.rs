@@ -0,0 +1,422 @@
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+use std::future::poll_fn;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+use std::task::{Context, Poll};
+
+use serde::de::DeserializeOwned;
+
+use crate::de::{from_bytes, is_empty_payload, parse_len, payload_size};
+use crate::value::Marker;
+use crate::{Error, ErrorKind, Result};
+
+/// Collects input that arrives in chunks of any size, e.g. from a socket, and
+/// decodes top-level values as soon as they are complete.
+///
+/// The decoder does no I/O itself: feed it with [`extend`] and call
+/// [`decode`] until it asks for more bytes. NoOp markers between values are
+/// skipped. A value is scanned only once however many chunks it arrives in.
+///
+/// [`extend`]: IncrementalDecoder::extend
+/// [`decode`]: IncrementalDecoder::decode
+#[derive(Clone, Debug, Default)]
+pub struct IncrementalDecoder {
+    buffer: Vec<u8>,
+    // bytes at the front of `buffer` that were decoded or skipped already
+    consumed: usize,
+    scanner: ValueScanner,
+    max_value_len: Option<usize>,
+}
+
+impl IncrementalDecoder {
+    pub fn new() -> IncrementalDecoder {
+        IncrementalDecoder::default()
+    }
+
+    /// Fails with `Error::LimitExceeded` once a single value would need more
+    /// than `max` bytes, instead of buffering a huge or bogus value forever.
+    /// The buffer is unusable after that, as after malformed input.
+    pub fn with_max_value_len(max: usize) -> IncrementalDecoder {
+        IncrementalDecoder {
+            max_value_len: Some(max),
+            ..IncrementalDecoder::default()
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        // move the undecoded tail to the front once it is the smaller part
+        if self.consumed > self.buffer.len() / 2 {
+            self.buffer.drain(..self.consumed);
+            self.consumed = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes received but not decoded yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.consumed..]
+    }
+
+    /// Whether nothing but NoOp markers is left in the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.buffered().iter().all(|&b| b == Marker::NoOp as u8)
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.consumed = 0;
+        self.scanner.reset();
+    }
+
+    /// Decodes the next value if it is complete, or returns `Ok(None)` when
+    /// more bytes are needed. A complete value that fails to decode into `T`
+    /// is consumed, so decoding can go on with the value after it; malformed
+    /// input leaves the buffer unusable and should be dropped with [`clear`].
+    ///
+    /// [`clear`]: IncrementalDecoder::clear
+    pub fn decode<T>(&mut self) -> Result<Option<T>>
+        where
+            T: DeserializeOwned,
+    {
+        let (noops, len) = self.scanner.split(&self.buffer[self.consumed..], self.max_value_len)?;
+        self.consumed += noops;
+        let len = match len {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let start = self.consumed;
+        self.consumed += len;
+        let result = from_bytes(&self.buffer[start..self.consumed]);
+        if self.consumed == self.buffer.len() {
+            self.buffer.clear();
+            self.consumed = 0;
+        }
+        result.map(Some)
+    }
+}
+
+/// Finds where a value ends without decoding it. The scan stops where the
+/// input runs out and picks up from there when called again with more input,
+/// so every byte is looked at once however the value is split.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ValueScanner {
+    // bytes of the current value scanned so far
+    pub(crate) pos: usize,
+    // containers open at `pos`, innermost last
+    open: Vec<OpenContainer>,
+}
+
+#[derive(Clone, Debug)]
+struct OpenContainer {
+    end: Marker,
+    of_type: Option<Marker>,
+    remaining: Option<usize>,
+    // the key of the current object entry has been scanned
+    in_entry: bool,
+}
+
+impl ValueScanner {
+    /// Number of leading no-ops in `bytes` and, once complete, the length of
+    /// the value after them. The caller drops what is reported; the next call
+    /// must pass the same input from there, possibly grown.
+    pub(crate) fn split(&mut self, bytes: &[u8], max_value_len: Option<usize>) -> Result<(usize, Option<usize>)> {
+        let noops = match self.pos {
+            0 => bytes.iter().take_while(|&&b| b == Marker::NoOp as u8).count(),
+            _ => 0,
+        };
+        let value = &bytes[noops..];
+        if value.is_empty() {
+            return Ok((noops, None));
+        }
+
+        let len = self.scan(value)?;
+        let needed = len.unwrap_or(value.len());
+        if matches!(max_value_len, Some(max) if needed > max) {
+            return Err(Error::LimitExceeded { limit: "max_value_len" });
+        }
+        if len.is_some() {
+            self.reset();
+        }
+        Ok((noops, len))
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.pos = 0;
+        self.open.clear();
+    }
+
+    fn scan(&mut self, bytes: &[u8]) -> Result<Option<usize>> {
+        loop {
+            let of_type = match self.open.last_mut() {
+                None if self.pos > 0 => return Ok(Some(self.pos)),
+                None => None,
+                Some(container) => {
+                    if !container.in_entry {
+                        // elements without a payload need no scanning
+                        if container.end == Marker::ArrayEnd && container.of_type.is_some_and(is_empty_payload) {
+                            container.remaining = container.remaining.map(|_| 0);
+                        }
+                        match container.remaining {
+                            Some(0) => {
+                                self.close();
+                                continue;
+                            }
+                            None => match bytes.get(self.pos) {
+                                None => return Ok(None),
+                                Some(&b) if b == container.end as u8 => {
+                                    self.pos += 1;
+                                    self.close();
+                                    continue;
+                                }
+                                Some(_) => {}
+                            },
+                            Some(_) => {}
+                        }
+                        if container.end == Marker::ObjectEnd {
+                            match text_len(&bytes[self.pos..])? {
+                                Some(len) => self.pos += len,
+                                None => return Ok(None),
+                            }
+                            container.in_entry = true;
+                        }
+                    }
+                    container.of_type
+                }
+            };
+
+            let scanned = match of_type {
+                Some(marker) => self.value(marker, self.pos, bytes)?,
+                None => match bytes.get(self.pos) {
+                    Some(&byte) => self.value(Marker::try_from(byte)?, self.pos + 1, bytes)?,
+                    None => false,
+                },
+            };
+            if !scanned {
+                return Ok(None);
+            }
+        }
+    }
+
+    // scans a value whose payload starts at `start`, or returns false without
+    // moving when it is cut off
+    fn value(&mut self, marker: Marker, start: usize, bytes: &[u8]) -> Result<bool> {
+        let end = match marker {
+            Marker::Null | Marker::NoOp | Marker::True | Marker::False => start,
+            Marker::String | Marker::Number => match text_len(&bytes[start..])? {
+                Some(len) => start + len,
+                None => return Ok(false),
+            },
+            Marker::ArrayStart | Marker::ObjectStart => return self.open_container(marker, start, bytes),
+            m if payload_size(m) > 0 => start + payload_size(m),
+            _ => return Err(Error::InvalidMarker),
+        };
+        if end > bytes.len() {
+            return Ok(false);
+        }
+        self.pos = end;
+        self.element_done();
+        Ok(true)
+    }
+
+    fn open_container(&mut self, start: Marker, mut pos: usize, bytes: &[u8]) -> Result<bool> {
+        let mut of_type = None;
+        if bytes.get(pos) == Some(&(Marker::OfType as u8)) {
+            match bytes.get(pos + 1) {
+                Some(&byte) => of_type = Some(Marker::try_from(byte)?),
+                None => return Ok(false),
+            }
+            pos += 2;
+            match bytes.get(pos) {
+                Some(&b) if b == Marker::Length as u8 => {}
+                Some(_) => return Err(Error::Expected(vec![Marker::Length])),
+                None => return Ok(false),
+            }
+        }
+
+        let mut remaining = None;
+        match bytes.get(pos) {
+            Some(&b) if b == Marker::Length as u8 => match parse_len(&bytes[pos + 1..]) {
+                Ok((len, rest)) => {
+                    remaining = Some(len);
+                    pos = bytes.len() - rest.len();
+                }
+                Err(e) if e.kind() == ErrorKind::Truncated => return Ok(false),
+                Err(e) => return Err(e),
+            },
+            Some(_) => {}
+            None => return Ok(false),
+        }
+
+        self.pos = pos;
+        self.open.push(OpenContainer {
+            end: match start {
+                Marker::ObjectStart => Marker::ObjectEnd,
+                _ => Marker::ArrayEnd,
+            },
+            of_type,
+            remaining,
+            in_entry: false,
+        });
+        Ok(true)
+    }
+
+    fn close(&mut self) {
+        self.open.pop();
+        self.element_done();
+    }
+
+    fn element_done(&mut self) {
+        if let Some(container) = self.open.last_mut() {
+            container.in_entry = false;
+            if let Some(remaining) = &mut container.remaining {
+                *remaining -= 1;
+            }
+        }
+    }
+}
+
+// size of a length followed by that many bytes, as in strings and keys, or
+// None while it is cut off
+fn text_len(bytes: &[u8]) -> Result<Option<usize>> {
+    match parse_len(bytes) {
+        Ok((len, rest)) => {
+            let size = (bytes.len() - rest.len()).checked_add(len).ok_or(Error::InvalidLength)?;
+            Ok(Some(size).filter(|&size| size <= bytes.len()))
+        }
+        Err(e) if e.kind() == ErrorKind::Truncated => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read loop of the async readers: decodes the next value, polling
+/// `poll_read` for another chunk whenever the buffered input ends inside one.
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub(crate) async fn read_value<T, P>(
+    decoder: &mut IncrementalDecoder,
+    chunk: &mut [u8],
+    mut poll_read: P,
+) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+        P: FnMut(&mut Context<'_>, &mut [u8]) -> Poll<std::io::Result<usize>>,
+{
+    loop {
+        if let Some(value) = decoder.decode()? {
+            return Ok(Some(value));
+        }
+        let n = poll_fn(|cx| poll_read(cx, chunk)).await?;
+        if n == 0 {
+            return match decoder.is_empty() {
+                true => Ok(None),
+                false => Err(Error::UnexpectedEof { context: "value" }),
+            };
+        }
+        decoder.extend(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_bytes;
+
+    #[test]
+    fn decoding_value_split_across_chunks_waits_for_all_bytes() {
+        let bytes = to_bytes(&("hello".to_string(), vec![1u16, 2, 3])).unwrap();
+        let mut decoder = IncrementalDecoder::new();
+        for chunk in bytes[..bytes.len() - 1].chunks(3) {
+            decoder.extend(chunk);
+            assert!(decoder.decode::<(String, Vec<u16>)>().unwrap().is_none());
+        }
+        decoder.extend(&bytes[bytes.len() - 1..]);
+        let value: (String, Vec<u16>) = decoder.decode().unwrap().unwrap();
+        assert_eq!(value, ("hello".to_string(), vec![1, 2, 3]));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn decoding_several_values_in_one_chunk_produces_them_in_order() {
+        let mut decoder = IncrementalDecoder::new();
+        decoder.extend(b"U\x01NNSi\x02abU");
+        assert_eq!(decoder.decode::<u8>().unwrap(), Some(1));
+        assert_eq!(decoder.decode::<String>().unwrap(), Some("ab".to_string()));
+        assert_eq!(decoder.decode::<u8>().unwrap(), None);
+        assert_eq!(decoder.buffered(), b"U");
+        decoder.extend(b"\x02");
+        assert_eq!(decoder.decode::<u8>().unwrap(), Some(2));
+        assert_eq!(decoder.decode::<u8>().unwrap(), None);
+    }
+
+    #[test]
+    fn decoding_value_of_wrong_type_skips_it() {
+        let mut decoder = IncrementalDecoder::new();
+        decoder.extend(b"TU\x05");
+        assert!(decoder.decode::<u8>().is_err());
+        assert_eq!(decoder.decode::<u8>().unwrap(), Some(5));
+    }
+
+    #[test]
+    fn scanning_byte_by_byte_finds_end_of_each_value() {
+        use std::collections::BTreeMap;
+
+        use crate::{to_bytes_with_config, SerializerConfig};
+
+        let map = BTreeMap::from([("a".to_string(), vec![1i32, -2]), ("b".to_string(), vec![])]);
+        let optimized = SerializerConfig { optimize_containers: true, ..Default::default() };
+        let values = vec![
+            to_bytes(&map).unwrap(),
+            to_bytes_with_config(&map, &optimized).unwrap(),
+            to_bytes(&(1.5f64, 'x', None::<u8>, "text")).unwrap(),
+            b"[[]{}[$Z#i\x03{$T#i\x01i\x01k[#i\x01S\x69\x01s]".to_vec(),
+        ];
+
+        for value in values {
+            let mut scanner = ValueScanner::default();
+            for end in 1..value.len() {
+                assert_eq!(scanner.split(&value[..end], None).unwrap(), (0, None));
+            }
+            assert_eq!(scanner.split(&value, None).unwrap(), (0, Some(value.len())));
+        }
+    }
+
+    #[test]
+    fn decoding_large_value_in_chunks_scans_each_chunk_once() {
+        let value: Vec<u32> = (0..100_000).collect();
+        let bytes = to_bytes(&value).unwrap();
+        let mut decoder = IncrementalDecoder::new();
+        for chunk in bytes[..bytes.len() - 1].chunks(8 * 1024) {
+            decoder.extend(chunk);
+            assert!(decoder.decode::<Vec<u32>>().unwrap().is_none());
+            // everything but the element cut off at the end was scanned already
+            assert!(decoder.buffered().len() - decoder.scanner.pos < 5);
+        }
+        decoder.extend(&bytes[bytes.len() - 1..]);
+        assert_eq!(decoder.decode::<Vec<u32>>().unwrap(), Some(value));
+        assert!(decoder.buffered().is_empty());
+    }
+
+    #[test]
+    fn decoding_many_values_keeps_buffer_small() {
+        let mut decoder = IncrementalDecoder::new();
+        for i in 0..1000u16 {
+            decoder.extend(&to_bytes(&i).unwrap());
+            decoder.extend(b"I");
+            assert_eq!(decoder.decode::<u16>().unwrap(), Some(i));
+            assert_eq!(decoder.buffered(), b"I");
+            decoder.clear();
+        }
+
+        for i in 0..1000u16 {
+            decoder.extend(&to_bytes(&i).unwrap());
+            assert_eq!(decoder.decode::<u16>().unwrap(), Some(i));
+        }
+        assert!(decoder.buffer.len() < 16);
+    }
+
+    #[test]
+    fn decoding_value_over_limit_produces_error() {
+        let mut decoder = IncrementalDecoder::with_max_value_len(8);
+        decoder.extend(b"SL\0\0\0\0\0\0\x10\0abc");
+        assert!(matches!(decoder.decode::<String>(), Err(Error::LimitExceeded { limit: "max_value_len" })));
+    }
+}
@@ -1,9 +1,56 @@
-pub use error::{Error, Result};
-pub use value::Value;
-pub use ser::to_bytes;
-pub use de::from_bytes;
+pub use error::{Error, ErrorKind, Result};
+pub use value::{Marker, Value};
+pub use ser::{
+    byte_length_marker, content_id, serialized_size, serialized_size_with_config,
+    smallest_length_marker, to_bytes, to_bytes_canonical, to_bytes_in, to_bytes_in_with_config,
+    to_bytes_with_config, to_writer, to_writer_with_config, write_smallest_len, CountingFormatter,
+    Formatter, FormatterMode, FormatterStats, HashingFormatter, Serializer, SerializerBuilder,
+    SerializerConfig, SimpleFormatter, StatsFormatter, TeeFormatter, U64Policy, Uniform,
+};
+pub use de::{
+    count_values, from_bytes, from_bytes_array, from_bytes_strict, from_bytes_unwrap_single,
+    from_bytes_with_config, from_reader, from_slices, parse_len, read_array_elements,
+    ArrayElements, Deserializer, DeserializerConfig, StreamDeserializer, TypedElement,
+};
+pub use block::block_notation;
+pub use frame::{read_framed, FrameReader};
+pub use incremental::IncrementalDecoder;
+pub use raw::RawValue;
+pub use read::{ChainedSlices, IoRead, Read, Reference, SliceRead};
+pub use record::UbjsonRecord;
+pub use shape::{matches_shape, Shape};
+pub use transcode::transcode;
+pub use writer::{ArrayWriter, ObjectWriter};
+#[doc(hidden)]
+pub use record::__private;
 
+#[cfg(feature = "derive")]
+pub use serde_ub_json_derive::UbjsonRecord;
+
+#[cfg(feature = "actix-web")]
+pub mod actix;
+mod block;
+#[cfg(all(test, any(feature = "tokio", feature = "futures-io", feature = "actix-web")))]
+mod block_on;
+#[cfg(feature = "bytes")]
+pub mod bytes;
+#[cfg(feature = "tokio-util")]
+pub mod codec;
 mod de;
+#[cfg(feature = "decimal")]
+pub mod decimal;
 mod error;
+mod frame;
+#[cfg(feature = "futures-io")]
+pub mod futures;
+mod incremental;
+mod raw;
+mod read;
+mod record;
 mod ser;
+mod shape;
+mod transcode;
+#[cfg(feature = "tokio")]
+pub mod tokio;
 mod value;
+mod writer;
@@ -0,0 +1,63 @@
+use serde::Deserialize;
+
+use crate::ser::SerializerConfig;
+use crate::Result;
+
+/// Encoded bytes of a single value, exactly as found in the input.
+///
+/// Values of strongly typed containers, which are stored without their
+/// marker, get it back so that the bytes always form a complete value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawValue<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawValue<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> RawValue<'a> {
+        RawValue { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Deserializes the value.
+    pub fn deserialize<T>(&self) -> Result<T>
+        where
+            T: Deserialize<'a>,
+    {
+        crate::from_bytes(self.bytes)
+    }
+
+    /// Decodes the value and encodes it again under `config`, so that it
+    /// matches the encoding of the document it is spliced into.
+    pub fn reencode(&self, config: &SerializerConfig) -> Result<Vec<u8>> {
+        crate::transcode(self.bytes, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smallest_length_marker;
+
+    #[test]
+    fn reencoding_verbose_raw_value_with_compact_config_produces_compact_value() {
+        let mut verbose = b"[#L".to_vec();
+        verbose.extend_from_slice(&2i64.to_be_bytes());
+        verbose.extend_from_slice(b"SL");
+        verbose.extend_from_slice(&1i64.to_be_bytes());
+        verbose.extend_from_slice(b"aSL");
+        verbose.extend_from_slice(&1i64.to_be_bytes());
+        verbose.extend_from_slice(b"b");
+
+        let config = SerializerConfig {
+            optimize_containers: true,
+            length_policy: Some(smallest_length_marker),
+            ..Default::default()
+        };
+        let out = RawValue::new(&verbose).reencode(&config).unwrap();
+        assert_eq!(out, b"[$S#i\x02i\x01ai\x01b");
+        assert_eq!(RawValue::new(&out).deserialize::<Vec<String>>().unwrap(), vec!["a", "b"]);
+    }
+}
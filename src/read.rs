@@ -0,0 +1,307 @@
+use std::io;
+use std::ops::Deref;
+
+use crate::{Error, Result};
+
+/// Source of bytes for the [`Deserializer`](crate::Deserializer).
+pub trait Read<'de> {
+    fn peek_byte(&mut self) -> Result<u8>;
+
+    fn read_byte(&mut self) -> Result<u8>;
+
+    /// Fills `data` completely or fails with `Error::Eof`.
+    fn read_into(&mut self, data: &mut [u8]) -> Result<()>;
+
+    /// Reads `len` bytes, borrowing them from the input when they are contiguous
+    /// and copying them into `scratch` otherwise.
+    fn read_slice<'s>(&'s mut self, len: usize, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's>>;
+
+    /// Number of bytes consumed so far.
+    fn offset(&self) -> usize;
+
+    /// Number of bytes left, if the length of the input is known. Declared
+    /// lengths and counts beyond it are rejected before anything is
+    /// allocated for them.
+    fn remaining_len(&self) -> Option<usize> {
+        None
+    }
+}
+
+// most bytes reserved up front for a length declared by the input; longer
+// values grow their buffer as the bytes actually arrive
+pub(crate) const MAX_PREALLOC: usize = 64 * 1024;
+
+// most elements a typed count may declare when the type has no payload, such
+// as `[$Z#`, since no input length bounds them
+pub(crate) const MAX_EMPTY_ELEMENTS: usize = 1 << 20;
+
+/// Data read from the input, either borrowed for the whole `'de` lifetime or
+/// copied into a scratch buffer.
+pub enum Reference<'b, 'c, T: ?Sized + 'static = [u8]> {
+    Borrowed(&'b T),
+    Copied(&'c T),
+}
+
+impl<'b, 'c, T: ?Sized + 'static> Deref for Reference<'b, 'c, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Reference::Borrowed(b) => b,
+            Reference::Copied(c) => c,
+        }
+    }
+}
+
+/// Reads from a single contiguous slice.
+pub struct SliceRead<'de> {
+    slice: &'de [u8],
+    index: usize,
+}
+
+impl<'de> SliceRead<'de> {
+    pub fn new(slice: &'de [u8]) -> SliceRead<'de> {
+        SliceRead { slice, index: 0 }
+    }
+
+    // starts reading at `index`, so offsets stay relative to the whole slice
+    pub(crate) fn at(slice: &'de [u8], index: usize) -> SliceRead<'de> {
+        SliceRead { slice, index }
+    }
+
+    pub(crate) fn remaining(&self) -> &'de [u8] {
+        &self.slice[self.index..]
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        let rest = self.remaining();
+        if rest.len() < len {
+            return Err(Error::Eof);
+        }
+        self.index += len;
+        Ok(&rest[..len])
+    }
+}
+
+impl<'de> Read<'de> for SliceRead<'de> {
+    fn peek_byte(&mut self) -> Result<u8> {
+        match self.slice.get(self.index) {
+            Some(byte) => Ok(*byte),
+            None => Err(Error::Eof),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let byte = self.peek_byte()?;
+        self.index += 1;
+        Ok(byte)
+    }
+
+    fn read_into(&mut self, data: &mut [u8]) -> Result<()> {
+        let bytes = self.take(data.len())?;
+        data.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn read_slice<'s>(&'s mut self, len: usize, _scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's>> {
+        self.take(len).map(Reference::Borrowed)
+    }
+
+    fn offset(&self) -> usize {
+        self.index
+    }
+
+    fn remaining_len(&self) -> Option<usize> {
+        Some(self.slice.len() - self.index)
+    }
+}
+
+/// Reads from a sequence of slices as if they were concatenated.
+///
+/// Strings and byte arrays that lie within one segment are still borrowed;
+/// only values straddling a boundary are copied.
+pub struct ChainedSlices<'a, 'de> {
+    segments: &'a [&'de [u8]],
+    segment: usize,
+    index: usize,
+    offset: usize,
+}
+
+impl<'a, 'de> ChainedSlices<'a, 'de> {
+    pub fn new(segments: &'a [&'de [u8]]) -> ChainedSlices<'a, 'de> {
+        ChainedSlices {
+            segments,
+            segment: 0,
+            index: 0,
+            offset: 0,
+        }
+    }
+
+    // current segment with at least one unread byte
+    fn current(&mut self) -> Option<&'de [u8]> {
+        while let Some(segment) = self.segments.get(self.segment) {
+            if self.index < segment.len() {
+                return Some(segment);
+            }
+            self.segment += 1;
+            self.index = 0;
+        }
+        None
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.index += len;
+        self.offset += len;
+    }
+}
+
+impl<'a, 'de> Read<'de> for ChainedSlices<'a, 'de> {
+    fn peek_byte(&mut self) -> Result<u8> {
+        match self.current() {
+            Some(segment) => Ok(segment[self.index]),
+            None => Err(Error::Eof),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let byte = self.peek_byte()?;
+        self.advance(1);
+        Ok(byte)
+    }
+
+    fn read_into(&mut self, data: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+        while filled < data.len() {
+            let segment = self.current().ok_or(Error::Eof)?;
+            let available = &segment[self.index..];
+            let n = available.len().min(data.len() - filled);
+            data[filled..filled + n].copy_from_slice(&available[..n]);
+            self.advance(n);
+            filled += n;
+        }
+        Ok(())
+    }
+
+    fn read_slice<'s>(&'s mut self, len: usize, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's>> {
+        if len == 0 {
+            return Ok(Reference::Borrowed(&[]));
+        }
+
+        let segment = self.current().ok_or(Error::Eof)?;
+        let available = &segment[self.index..];
+        if available.len() >= len {
+            self.advance(len);
+            return Ok(Reference::Borrowed(&available[..len]));
+        }
+
+        // the value straddles a boundary, stitch it together
+        if self.remaining_len() < Some(len) {
+            return Err(Error::Eof);
+        }
+        scratch.clear();
+        scratch.resize(len, 0);
+        self.read_into(scratch)?;
+        Ok(Reference::Copied(scratch))
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn remaining_len(&self) -> Option<usize> {
+        let rest: usize = self.segments.iter().skip(self.segment).map(|segment| segment.len()).sum();
+        Some(rest - self.index)
+    }
+}
+
+/// Reads from an [`io::Read`] source. Strings and byte arrays are always
+/// copied.
+///
+/// Bytes are requested as they are needed, often one at a time, so slow
+/// sources should be wrapped in a [`BufReader`](std::io::BufReader).
+pub struct IoRead<R> {
+    reader: R,
+    peeked: Option<u8>,
+    offset: usize,
+}
+
+impl<R> IoRead<R>
+    where
+        R: io::Read,
+{
+    pub fn new(reader: R) -> IoRead<R> {
+        IoRead {
+            reader,
+            peeked: None,
+            offset: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn fill(&mut self, data: &mut [u8]) -> Result<()> {
+        match self.reader.read_exact(data) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::Eof),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+}
+
+impl<'de, R> Read<'de> for IoRead<R>
+    where
+        R: io::Read,
+{
+    fn peek_byte(&mut self) -> Result<u8> {
+        if let Some(byte) = self.peeked {
+            return Ok(byte);
+        }
+        let mut byte = [0u8];
+        self.fill(&mut byte)?;
+        self.peeked = Some(byte[0]);
+        Ok(byte[0])
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let byte = self.peek_byte()?;
+        self.peeked = None;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_into(&mut self, data: &mut [u8]) -> Result<()> {
+        let len = data.len();
+        let rest = match self.peeked {
+            Some(byte) if len > 0 => {
+                self.peeked = None;
+                data[0] = byte;
+                &mut data[1..]
+            }
+            _ => data,
+        };
+        self.fill(rest)?;
+        self.offset += len;
+        Ok(())
+    }
+
+    fn read_slice<'s>(&'s mut self, len: usize, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's>> {
+        scratch.clear();
+        scratch.reserve(len.min(MAX_PREALLOC));
+        if len > 0 {
+            scratch.extend(self.peeked.take());
+        }
+        let mut rest = io::Read::take(&mut self.reader, (len - scratch.len()) as u64);
+        io::Read::read_to_end(&mut rest, scratch).map_err(Error::Io)?;
+        if scratch.len() < len {
+            return Err(Error::Eof);
+        }
+        self.offset += len;
+        Ok(Reference::Copied(scratch))
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+}
@@ -0,0 +1,41 @@
+use crate::Result;
+
+/// Fixed-schema struct encoded directly as a strongly typed object
+/// (`{$<type>#<count>`), bypassing serde.
+///
+/// Implemented with `#[derive(UbjsonRecord)]` (feature `derive`) for structs
+/// whose fields all share one numeric type. Encoded records can also be read
+/// with [`from_bytes`](crate::from_bytes).
+pub trait UbjsonRecord: Sized {
+    /// Appends the encoded record to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decodes a record written by [`encode`](UbjsonRecord::encode).
+    fn decode(bytes: &[u8]) -> Result<Self>;
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use serde::de::Error as _;
+
+    use crate::{Error, Result};
+
+    pub fn expect(bytes: &[u8], offset: usize, expected: &[u8]) -> Result<()> {
+        match bytes.get(offset..offset + expected.len()) {
+            Some(actual) if actual == expected => Ok(()),
+            Some(_) => Err(Error::custom(format_args!("record layout differs at byte {}", offset))),
+            None => Err(Error::Eof),
+        }
+    }
+
+    pub fn payload<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N]> {
+        let mut data = [0u8; N];
+        match bytes.get(offset..offset + N) {
+            Some(actual) => {
+                data.copy_from_slice(actual);
+                Ok(data)
+            }
+            None => Err(Error::Eof),
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::io::Write;
+use std::fmt::{Display, Write as _};
+use std::hash::Hasher;
+use std::io::{IoSlice, Write};
+use std::ops::Range;
 
 use serde::ser::{
     SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
@@ -7,21 +10,420 @@ use serde::ser::{
 use serde::Serialize;
 
 use crate::{Error, Result};
-use crate::value::Marker;
+use crate::error::PathSegment;
+use crate::value::{Marker, NOOP_TOKEN, NUMBER_TOKEN, TYPED_ARRAY_TOKEN, UNIFORM_TOKEN};
 
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>>
     where
         T: Serialize,
+{
+    to_bytes_with_config(value, &SerializerConfig::default())
+}
+
+pub fn to_bytes_with_config<T>(value: &T, config: &SerializerConfig) -> Result<Vec<u8>>
+    where
+        T: Serialize,
 {
     let mut bytes = Vec::new();
-    let policy = SimpleFormatter::new(&mut bytes);
-    let mut serializer = Serializer::new(policy);
-    value.serialize(&mut serializer)?;
+    to_bytes_in_with_config(value, &mut bytes, config)?;
     Ok(bytes)
 }
 
+/// Appends the encoding of a value to `bytes`, so one buffer can be reused
+/// across many values. On error the buffer may hold a partial encoding.
+pub fn to_bytes_in<T>(value: &T, bytes: &mut Vec<u8>) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+{
+    to_bytes_in_with_config(value, bytes, &SerializerConfig::default())
+}
+
+pub fn to_bytes_in_with_config<T>(value: &T, bytes: &mut Vec<u8>, config: &SerializerConfig) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+{
+    serialize_into(value, bytes, config)
+}
+
+/// Serializes a value to `writer`, such as a file or socket.
+///
+/// The many small writes of markers and scalars are collected in a buffer,
+/// and large payloads such as byte blobs and long strings are handed to the
+/// writer together with the buffered bytes in one vectored write, so no
+/// `BufWriter` is needed. The writer is not flushed.
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+    where
+        W: Write,
+        T: ?Sized + Serialize,
+{
+    to_writer_with_config(writer, value, &SerializerConfig::default())
+}
+
+pub fn to_writer_with_config<W, T>(writer: W, value: &T, config: &SerializerConfig) -> Result<()>
+    where
+        W: Write,
+        T: ?Sized + Serialize,
+{
+    let mut writer = BatchWriter::new(writer);
+    serialize_into(value, &mut writer, config)?;
+    writer.write_batch()?;
+    Ok(())
+}
+
+pub(crate) fn serialize_into<T, W>(value: &T, writer: W, config: &SerializerConfig) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+        W: Write,
+{
+    let policy = SimpleFormatter::new(writer);
+    let mut serializer = Serializer::with_config(policy, config.clone());
+    value.serialize(&mut serializer).map_err(|e| e.at_path(&serializer.path))
+}
+
+/// Serializes a value to a deterministic, compact encoding, suitable for
+/// hashing and diffing, using [`SerializerConfig::canonical`].
+pub fn to_bytes_canonical<T>(value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+{
+    to_bytes_with_config(value, &SerializerConfig::canonical())
+}
+
+/// Hashes the canonical encoding of a value (see [`to_bytes_canonical`])
+/// with a fresh `H`, feeding the bytes to the hasher as they are produced.
+/// Equal values get equal IDs regardless of map iteration order.
+pub fn content_id<T, H>(value: &T) -> Result<u64>
+    where
+        T: ?Sized + Serialize,
+        H: Hasher + Default,
+{
+    let mut formatter = HashingFormatter::new(H::default());
+    let mut serializer = Serializer::with_config(&mut formatter, SerializerConfig::canonical());
+    value.serialize(&mut serializer)?;
+    Ok(formatter.finish())
+}
+
+/// Number of bytes `to_bytes` would produce for a value, computed without
+/// allocating the output.
+pub fn serialized_size<T>(value: &T) -> Result<usize>
+    where
+        T: ?Sized + Serialize,
+{
+    serialized_size_with_config(value, &SerializerConfig::default())
+}
+
+pub fn serialized_size_with_config<T>(value: &T, config: &SerializerConfig) -> Result<usize>
+    where
+        T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::with_config(CountingFormatter::new(), config.clone());
+    value.serialize(&mut serializer).map_err(|e| e.at_path(&serializer.path))?;
+    Ok(serializer.into_formatter().count())
+}
+
+/// Writes the wrapped array, struct or map in the strongly typed form
+/// (`[$<type>#<count>`, `{$<type>#<count>`) when all its values are scalars
+/// written with the same marker, as `optimize_containers` does for every
+/// container. Other values are written as usual.
+///
+/// Deserializes like the wrapped type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uniform<T>(pub T);
+
+impl<T> Serialize for Uniform<T>
+    where
+        T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct(UNIFORM_TOKEN, &self.0)
+    }
+}
+
+impl<'de, T> serde::Deserialize<'de> for Uniform<T>
+    where
+        T: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Uniform)
+    }
+}
+
+/// Options controlling how values are laid out on the wire.
+#[derive(Clone, Debug, Default)]
+pub struct SerializerConfig {
+    /// Write tuples and tuple structs as objects keyed by the element index
+    /// (`"0"`, `"1"`, ...) instead of arrays.
+    pub tuples_as_objects: bool,
+    /// Buffer array elements and object entries, and write containers whose
+    /// values all share one scalar type as strongly typed containers
+    /// (`[$<type>#<count>`, `{$<type>#<count>`).
+    ///
+    /// Since every container is buffered, its count is always written, also
+    /// for sequences and maps of unknown length.
+    pub optimize_containers: bool,
+    /// Always write the count of arrays and objects, never the unsized form
+    /// closed by `]` or `}`. Sequences and maps of unknown length are buffered.
+    pub always_count_containers: bool,
+    /// Picks the marker for every length and count that is written, e.g.
+    /// [`smallest_length_marker`]. Must return one of `i`, `U`, `I`, `l` or `L`
+    /// wide enough for the length. Without a policy, the default, lengths are
+    /// left to the formatter: `SimpleFormatter` writes them as `L`, and as the
+    /// smallest marker when created with [`SimpleFormatter::compact`].
+    pub length_policy: Option<fn(usize) -> Marker>,
+    /// Keep track of the field names and element indices leading to the value
+    /// being written, and append them to custom errors (`... at .items[3].name`).
+    pub track_path: bool,
+    /// Write `i16`, `i32` and `i64` values with the narrowest signed marker
+    /// that holds them, e.g. `5i16` as `i` + `0x05`, and `u16`, `u32` and `u64`
+    /// values up to 255 as `U`, larger ones like signed values.
+    pub compact_ints: bool,
+    /// Buffer object entries and write them ordered by key. Maps whose
+    /// iteration order varies, such as `HashMap`, then always produce the same
+    /// output.
+    pub sort_keys: bool,
+    /// Write structs as arrays of their field values in declaration order,
+    /// without keys. Read them back with `struct_from_array`. Fields left out
+    /// with `skip_serializing_if` shift the positions of the fields after them.
+    pub struct_as_array: bool,
+    /// Write `()` and unit structs as no-op (`N`) instead of null (`Z`), so
+    /// unit and `None` stay distinct on the wire.
+    pub unit_as_noop: bool,
+    /// Write every float as `D` (f64), widening `f32` values, for consumers
+    /// that do not understand `d`. Such values still read back into `f32`.
+    pub always_f64: bool,
+    /// Write `f64` values that `f32` holds exactly, such as whole numbers
+    /// below 2^24 or `0.5`, as `d` (f32), saving four bytes each. Ignored
+    /// with `always_f64`.
+    pub compact_floats: bool,
+    /// Write integer, bool and float map keys, e.g. of a `HashMap<u64, V>`, as
+    /// their text (`"42"`, `"true"`, `"1.5"`) instead of failing with
+    /// `Error::InvalidKey`. Read them back with the deserializer's
+    /// `coerce_int_keys`.
+    pub coerce_int_keys: bool,
+    /// How `u64` values are written, see [`U64Policy`].
+    pub u64_policy: U64Policy,
+    /// Leave out struct fields whose value is written as null, such as `None`,
+    /// instead of writing them with `Z`. Not applied with `struct_as_array`,
+    /// where every position must be present.
+    pub skip_none_fields: bool,
+    /// Asked before every element of an array written without a count, except
+    /// the first; a no-op (`N`) is written ahead of the element whenever it
+    /// returns true. Keeps a long-running stream alive while a large unsized
+    /// array is produced. See also [`Serializer::write_noop`].
+    pub noop_heartbeat: Option<fn() -> bool>,
+    /// Fail with `Error::DepthLimitExceeded` instead of nesting containers
+    /// deeper than this, guarding the stack against deeply recursive values.
+    pub max_depth: Option<usize>,
+}
+
+impl SerializerConfig {
+    /// Profile for a deterministic encoding, as written by [`to_bytes_canonical`].
+    ///
+    /// Turns on `compact_ints`, `optimize_containers`, `always_count_containers`
+    /// and `sort_keys`, and picks lengths with [`smallest_length_marker`]:
+    ///
+    /// - integers use the narrowest marker that holds them,
+    /// - lengths and counts use the narrowest signed marker,
+    /// - every container is counted, and typed when its values share a scalar
+    ///   type,
+    /// - object entries are ordered by key, so equal maps produce identical
+    ///   bytes regardless of their iteration order.
+    ///
+    /// Sequences, including sets, keep their iteration order, and struct
+    /// variant fields keep their declaration order.
+    pub fn canonical() -> SerializerConfig {
+        SerializerConfig {
+            compact_ints: true,
+            optimize_containers: true,
+            always_count_containers: true,
+            sort_keys: true,
+            length_policy: Some(smallest_length_marker),
+            ..Default::default()
+        }
+    }
+}
+
+/// How `u64` values, which no UBJSON integer marker covers, are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum U64Policy {
+    /// Always as a high-precision number (`H`).
+    #[default]
+    Number,
+    /// As a signed integer when the value fits into `i64`, otherwise as a
+    /// high-precision number.
+    Integer,
+}
+
+/// Builds a [`SerializerConfig`] step by step, so that services can share a
+/// wire profile.
+///
+/// ```
+/// use serde_ub_json::{smallest_length_marker, SerializerBuilder, U64Policy};
+///
+/// let builder = SerializerBuilder::new()
+///     .length_policy(smallest_length_marker)
+///     .optimize_containers(true)
+///     .u64_policy(U64Policy::Integer);
+/// let bytes = builder.to_bytes(&vec![1u64, 2]).unwrap();
+/// assert_eq!(&bytes[..5], b"[$L#i");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SerializerBuilder {
+    config: SerializerConfig,
+}
+
+impl SerializerBuilder {
+    pub fn new() -> SerializerBuilder {
+        SerializerBuilder::default()
+    }
+
+    pub fn length_policy(mut self, policy: fn(usize) -> Marker) -> Self {
+        self.config.length_policy = Some(policy);
+        self
+    }
+
+    pub fn optimize_containers(mut self, optimize: bool) -> Self {
+        self.config.optimize_containers = optimize;
+        self
+    }
+
+    pub fn always_count_containers(mut self, always: bool) -> Self {
+        self.config.always_count_containers = always;
+        self
+    }
+
+    pub fn tuples_as_objects(mut self, as_objects: bool) -> Self {
+        self.config.tuples_as_objects = as_objects;
+        self
+    }
+
+    pub fn struct_as_array(mut self, as_array: bool) -> Self {
+        self.config.struct_as_array = as_array;
+        self
+    }
+
+    pub fn compact_ints(mut self, compact: bool) -> Self {
+        self.config.compact_ints = compact;
+        self
+    }
+
+    pub fn compact_floats(mut self, compact: bool) -> Self {
+        self.config.compact_floats = compact;
+        self
+    }
+
+    pub fn sort_keys(mut self, sort: bool) -> Self {
+        self.config.sort_keys = sort;
+        self
+    }
+
+    pub fn u64_policy(mut self, policy: U64Policy) -> Self {
+        self.config.u64_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> SerializerConfig {
+        self.config
+    }
+
+    pub fn serializer<F>(&self, formatter: F) -> Serializer<F>
+        where
+            F: Formatter,
+    {
+        Serializer::with_config(formatter, self.config.clone())
+    }
+
+    pub fn to_bytes<T>(&self, value: &T) -> Result<Vec<u8>>
+        where
+            T: Serialize,
+    {
+        to_bytes_with_config(value, &self.config)
+    }
+
+    pub fn to_bytes_in<T>(&self, value: &T, bytes: &mut Vec<u8>) -> Result<()>
+        where
+            T: Serialize,
+    {
+        to_bytes_in_with_config(value, bytes, &self.config)
+    }
+
+    pub fn to_writer<W, T>(&self, writer: W, value: &T) -> Result<()>
+        where
+            W: Write,
+            T: Serialize,
+    {
+        to_writer_with_config(writer, value, &self.config)
+    }
+}
+
+impl From<SerializerConfig> for SerializerBuilder {
+    fn from(config: SerializerConfig) -> SerializerBuilder {
+        SerializerBuilder { config }
+    }
+}
+
+/// Length policy that picks the smallest signed marker that fits the length.
+pub fn smallest_length_marker(len: usize) -> Marker {
+    if len <= i8::MAX as usize {
+        Marker::I8
+    } else if len <= i16::MAX as usize {
+        Marker::I16
+    } else if len <= i32::MAX as usize {
+        Marker::I32
+    } else {
+        Marker::I64
+    }
+}
+
+/// Length policy that writes lengths up to 255 with `U`, the minimal encoding
+/// for short strings and small blobs, and larger ones with the smallest signed
+/// marker that fits.
+pub fn byte_length_marker(len: usize) -> Marker {
+    if len <= u8::MAX as usize {
+        Marker::U8
+    } else {
+        smallest_length_marker(len)
+    }
+}
+
 pub struct Serializer<F> {
     formatter: F,
+    config: SerializerConfig,
+    // next string is the digits of a high-precision number
+    number: bool,
+    // next array or object is written in the strongly typed form if it can be
+    typed: bool,
+    // location of the value being written, when tracked; left in place on error
+    path: Vec<PathSegment>,
+    last_key: Option<String>,
+    // number of containers currently open
+    depth: usize,
+    // reused by `collect_str` to format values before their length is known
+    scratch: String,
+}
+
+impl<W> Serializer<SimpleFormatter<W>>
+    where
+        W: Write,
+{
+    pub fn get_ref(&self) -> &W {
+        self.formatter.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.formatter.get_mut()
+    }
+
+    /// Unwraps the serializer, returning the writer it was created with.
+    pub fn into_inner(self) -> W {
+        self.formatter.into_inner()
+    }
 }
 
 impl<F> Serializer<F>
@@ -29,7 +431,250 @@ impl<F> Serializer<F>
         F: Formatter,
 {
     pub fn new(formatter: F) -> Self {
-        Self { formatter }
+        Self::with_config(formatter, SerializerConfig::default())
+    }
+
+    pub fn with_config(formatter: F, config: SerializerConfig) -> Self {
+        Self {
+            formatter,
+            config,
+            number: false,
+            typed: false,
+            path: Vec::new(),
+            last_key: None,
+            depth: 0,
+            scratch: String::new(),
+        }
+    }
+
+    pub fn formatter(&self) -> &F {
+        &self.formatter
+    }
+
+    pub fn formatter_mut(&mut self) -> &mut F {
+        &mut self.formatter
+    }
+
+    pub fn into_formatter(self) -> F {
+        self.formatter
+    }
+
+    /// Clears the state left behind by a previous, possibly failed, value so
+    /// the serializer can be driven again. The formatter's output is kept.
+    pub fn reset(&mut self) {
+        self.formatter.set_mode(FormatterMode::Value);
+        self.number = false;
+        self.typed = false;
+        self.path.clear();
+        self.last_key = None;
+        self.depth = 0;
+    }
+
+    /// Writes a no-op (`N`) marker, e.g. as a keep-alive between top-level
+    /// values of a stream. Readers skip it.
+    pub fn write_noop(&mut self) -> Result<()> {
+        self.formatter.mark(Marker::NoOp)?;
+        Ok(())
+    }
+
+    fn enter(&mut self, segment: PathSegment) {
+        if self.config.track_path {
+            self.path.push(segment);
+        }
+    }
+
+    // entering the value of the last written key
+    fn enter_value(&mut self) {
+        if self.config.track_path {
+            let key = self.last_key.take().unwrap_or_default();
+            self.path.push(PathSegment::Key(key));
+        }
+    }
+
+    fn leave(&mut self) {
+        if self.config.track_path {
+            self.path.pop();
+        }
+    }
+
+    // opening a container
+    fn descend(&mut self) -> Result<()> {
+        self.depth += 1;
+        match self.config.max_depth {
+            Some(max_depth) if self.depth > max_depth => Err(Error::DepthLimitExceeded { max_depth }),
+            _ => Ok(()),
+        }
+    }
+
+    // closing a container
+    fn ascend(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    // records the formatter calls of a single value or key as the next item
+    // of `recording`, for containers that are buffered
+    fn record<T>(&mut self, value: &T, mode: FormatterMode, recording: &mut Recording) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+    {
+        let start = recording.calls.len();
+        let mut formatter = Recorder { recording: &mut *recording, mode };
+        formatter.set_mode(mode);
+
+        let mut serializer = Serializer::with_config(formatter, std::mem::take(&mut self.config));
+        serializer.path = std::mem::take(&mut self.path);
+        serializer.depth = self.depth;
+        serializer.scratch = std::mem::take(&mut self.scratch);
+        let result = value.serialize(&mut serializer);
+        self.config = serializer.config;
+        self.path = serializer.path;
+        self.last_key = serializer.last_key;
+        self.scratch = serializer.scratch;
+
+        recording.items.push(start..recording.calls.len());
+        result
+    }
+
+    // writes the calls of a recorded item
+    fn replay(&mut self, recording: &Recording, calls: &[Call]) -> Result<()> {
+        for call in calls {
+            match *call {
+                Call::Mode(mode) => self.formatter.set_mode(mode),
+                Call::Raw(start, end) => self.formatter.raw(&recording.bytes[start..end])?,
+                Call::Bool(v) => self.formatter.bool(v)?,
+                Call::U8(v) => self.formatter.u8(v)?,
+                Call::U16(v) => self.formatter.u16(v)?,
+                Call::U32(v) => self.formatter.u32(v)?,
+                Call::I8(v) => self.formatter.i8(v)?,
+                Call::I16(v) => self.formatter.i16(v)?,
+                Call::I32(v) => self.formatter.i32(v)?,
+                Call::I64(v) => self.formatter.i64(v)?,
+                Call::F32(v) => self.formatter.f32(v)?,
+                Call::F64(v) => self.formatter.f64(v)?,
+                Call::Mark(marker) => self.formatter.mark(marker)?,
+                Call::Len(v) => self.formatter.len(v)?,
+            }
+        }
+        Ok(())
+    }
+
+    // writes a recorded element of a strongly typed container, without the
+    // marker the container header already holds
+    fn replay_payload(&mut self, recording: &Recording, calls: &[Call]) -> Result<()> {
+        let first = calls.iter().position(|call| !matches!(call, Call::Mode(_))).unwrap_or(calls.len());
+        self.replay(recording, &calls[..first])?;
+        match calls.get(first) {
+            Some(Call::Mark(_)) => self.replay(recording, &calls[first + 1..]),
+            Some(call) => {
+                let (bytes, len) = call.simple_bytes();
+                self.formatter.raw(&bytes[1..len])?;
+                self.replay(recording, &calls[first + 1..])
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn write_len(&mut self, len: usize) -> Result<()> {
+        let policy = match self.config.length_policy {
+            Some(policy) => policy,
+            None => {
+                self.formatter.len(len)?;
+                return Ok(());
+            }
+        };
+
+        let invalid = |_| Error::InvalidLength;
+        match policy(len) {
+            Marker::I8 => self.formatter.i8(i8::try_from(len).map_err(invalid)?)?,
+            Marker::U8 => self.formatter.u8(u8::try_from(len).map_err(invalid)?)?,
+            Marker::I16 => self.formatter.i16(i16::try_from(len).map_err(invalid)?)?,
+            Marker::I32 => self.formatter.i32(i32::try_from(len).map_err(invalid)?)?,
+            Marker::I64 => self.formatter.i64(i64::try_from(len).map_err(invalid)?)?,
+            _ => return Err(Error::InvalidLength),
+        }
+        Ok(())
+    }
+
+    fn write_number(&mut self, digits: &str, found: &'static str) -> Result<()> {
+        if self.formatter.get_mode().is_key() {
+            return self.write_key_text(digits, found);
+        }
+
+        let bytes = digits.as_bytes();
+
+        self.formatter.mark(Marker::Number)?;
+        self.write_len(bytes.len())?;
+        self.formatter.raw(bytes)?;
+
+        Ok(())
+    }
+
+    // positional objects are written as arrays of their values
+    fn start_object(
+        &mut self,
+        len: Option<usize>,
+        positional: bool,
+        buffer: bool,
+    ) -> Result<ObjectSerializer<'_, F>> {
+        if self.formatter.get_mode().is_key() {
+            return Err(Error::InvalidKey { found: "map" });
+        }
+        self.descend()?;
+
+        let typed = std::mem::take(&mut self.typed);
+        if buffer
+            || typed
+            || self.config.optimize_containers
+            || (self.config.sort_keys && !positional)
+            || (self.config.always_count_containers && len.is_none())
+        {
+            // header depends on the values, written once they are all known
+            let entries = Recording::with_capacity(2 * len.unwrap_or(0));
+            return Ok(ObjectSerializer { len, count: 0, positional, entries: Some(entries), typed, ser: self });
+        }
+
+        self.formatter.mark(if positional { Marker::ArrayStart } else { Marker::ObjectStart })?;
+
+        if let Some(len) = len {
+            self.formatter.mark(Marker::Length)?;
+            self.write_len(len)?;
+        }
+
+        Ok(ObjectSerializer { len, count: 0, positional, entries: None, typed, ser: self })
+    }
+
+    // primitive map key, written as its text with `coerce_int_keys`
+    fn write_key_text(&mut self, v: impl Display, found: &'static str) -> Result<()> {
+        if !self.config.coerce_int_keys {
+            return Err(Error::InvalidKey { found });
+        }
+        serde::Serializer::serialize_str(self, &v.to_string())
+    }
+
+    // `U` where it fits, otherwise the narrowest signed marker, or `H` beyond `i64`
+    fn write_unsigned(&mut self, v: u64) -> Result<()> {
+        if let Ok(v) = u8::try_from(v) {
+            self.formatter.u8(v)?;
+            return Ok(());
+        }
+
+        match i64::try_from(v) {
+            Ok(v) => self.write_signed(v),
+            Err(_) => self.write_number(&v.to_string(), "u64"),
+        }
+    }
+
+    fn write_signed(&mut self, v: i64) -> Result<()> {
+        if let Ok(v) = i8::try_from(v) {
+            self.formatter.i8(v)?;
+        } else if let Ok(v) = i16::try_from(v) {
+            self.formatter.i16(v)?;
+        } else if let Ok(v) = i32::try_from(v) {
+            self.formatter.i32(v)?;
+        } else {
+            self.formatter.i64(v)?;
+        }
+        Ok(())
     }
 }
 
@@ -49,7 +694,7 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "bool");
         }
 
         self.formatter.bool(v)?;
@@ -58,7 +703,7 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "i8");
         }
 
         self.formatter.i8(v)?;
@@ -67,7 +712,11 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "i16");
+        }
+
+        if self.config.compact_ints {
+            return self.write_signed(v.into());
         }
 
         self.formatter.i16(v)?;
@@ -76,7 +725,11 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "i32");
+        }
+
+        if self.config.compact_ints {
+            return self.write_signed(v.into());
         }
 
         self.formatter.i32(v)?;
@@ -85,7 +738,11 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "i64");
+        }
+
+        if self.config.compact_ints {
+            return self.write_signed(v);
         }
 
         self.formatter.i64(v)?;
@@ -94,7 +751,7 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "u8");
         }
 
         self.formatter.u8(v)?;
@@ -103,7 +760,11 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "u16");
+        }
+
+        if self.config.compact_ints {
+            return self.write_unsigned(v.into());
         }
 
         self.formatter.u16(v)?;
@@ -112,7 +773,11 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "u32");
+        }
+
+        if self.config.compact_ints {
+            return self.write_unsigned(v.into());
         }
 
         self.formatter.u32(v)?;
@@ -120,46 +785,82 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+        if self.config.compact_ints && self.formatter.get_mode().is_value() {
+            return self.write_unsigned(v);
         }
 
-        self.formatter.mark(Marker::Number)?;
+        if let (U64Policy::Integer, Ok(v)) = (self.config.u64_policy, i64::try_from(v)) {
+            return self.serialize_i64(v);
+        }
 
-        let s = v.to_string();
-        let bytes = s.as_bytes();
-        let len = bytes.len();
+        self.write_number(&v.to_string(), "u64")
+    }
 
-        self.formatter.len(len)?;
-        self.formatter.raw(&bytes)?;
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        if self.formatter.get_mode().is_key() {
+            return self.write_key_text(v, "i128");
+        }
 
-        Ok(())
+        self.write_number(&v.to_string(), "i128")
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        if self.formatter.get_mode().is_key() {
+            return self.write_key_text(v, "u128");
+        }
+
+        self.write_number(&v.to_string(), "u128")
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "f32");
         }
 
-        self.formatter.f32(v)?;
+        if self.config.always_f64 {
+            self.formatter.f64(v.into())?;
+        } else {
+            self.formatter.f32(v)?;
+        }
         Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return self.write_key_text(v, "f64");
         }
 
-        self.formatter.f64(v)?;
+        let narrow = v as f32;
+        if self.config.compact_floats && !self.config.always_f64 && f64::from(narrow).to_bits() == v.to_bits() {
+            self.formatter.f32(narrow)?;
+        } else {
+            self.formatter.f64(v)?;
+        }
         Ok(())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
+        // `C` holds a single byte, other chars are written as strings
+        if v.is_ascii() && self.formatter.get_mode().is_value() {
+            self.formatter.mark(Marker::Char)?;
+            self.formatter.raw(&[v as u8])?;
+            return Ok(());
+        }
+
         let s = v.to_string();
         self.serialize_str(s.as_str())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
+        if self.number {
+            self.number = false;
+            return self.write_number(v, "number");
+        }
+
+        if self.config.track_path && self.formatter.get_mode().is_key() {
+            self.last_key = Some(v.to_string());
+        }
+
         if self.formatter.get_mode().is_value() {
             self.formatter.mark(Marker::String)?;
         }
@@ -167,41 +868,54 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
         let bytes = v.as_bytes();
         let len = bytes.len();
 
-        self.formatter.len(len)?;
-        self.formatter.raw(&bytes)?;
+        self.write_len(len)?;
+        self.formatter.raw(bytes)?;
 
         Ok(())
     }
 
+    fn collect_str<T>(self, value: &T) -> Result<Self::Ok>
+        where
+            T: ?Sized + Display,
+    {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        let result = match write!(scratch, "{}", value) {
+            Ok(()) => self.serialize_str(&scratch),
+            Err(_) => Err(serde::ser::Error::custom("Display implementation returned an error")),
+        };
+        self.scratch = scratch;
+        result
+    }
+
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "bytes" });
         }
 
+        // strongly typed array lets the payload be written (and borrowed) as is
         self.formatter.mark(Marker::ArrayStart)?;
+        self.formatter.mark(Marker::OfType)?;
+        self.formatter.mark(Marker::U8)?;
         self.formatter.mark(Marker::Length)?;
-        self.formatter.len(v.len())?;
-
-        for b in v {
-            self.formatter.mark(Marker::U8)?;
-            self.formatter.raw(&b.to_be_bytes())?;
-        }
+        self.write_len(v.len())?;
+        self.formatter.raw(v)?;
 
         Ok(())
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "none" });
         }
 
         self.formatter.mark(Marker::Null)?;
         Ok(())
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
         value.serialize(self)?;
         Ok(())
@@ -209,14 +923,23 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
 
     fn serialize_unit(self) -> Result<Self::Ok> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "unit" });
         }
 
-        self.formatter.mark(Marker::Null)?;
+        if self.config.unit_as_noop {
+            self.formatter.mark(Marker::NoOp)?;
+        } else {
+            self.formatter.mark(Marker::Null)?;
+        }
         Ok(())
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok> {
+        if name == NOOP_TOKEN && self.formatter.get_mode().is_value() {
+            self.formatter.mark(Marker::NoOp)?;
+            return Ok(());
+        }
+
         self.serialize_unit()
     }
 
@@ -229,15 +952,19 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
         self.serialize_str(variant)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok>
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
-        value.serialize(self)?;
-        Ok(())
+        self.number = name == NUMBER_TOKEN;
+        self.typed = name == TYPED_ARRAY_TOKEN || name == UNIFORM_TOKEN;
+        let result = value.serialize(&mut *self);
+        self.number = false;
+        self.typed = false;
+        result
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -245,40 +972,66 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
         value: &T,
     ) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "newtype variant" });
         }
+        self.descend()?;
 
         self.formatter.mark(Marker::ObjectStart)?;
         self.formatter.mark(Marker::Length)?;
-        self.formatter.len(1)?;
+        self.write_len(1)?;
 
         self.formatter.set_mode(FormatterMode::Key);
         variant.serialize(&mut *self)?;
 
         self.formatter.set_mode(FormatterMode::Value);
-        value.serialize(&mut *self)
+        self.enter_value();
+        value.serialize(&mut *self)?;
+        self.leave();
+        self.ascend();
+        Ok(())
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "sequence" });
+        }
+        self.descend()?;
+
+        let typed = std::mem::take(&mut self.typed);
+        if typed || self.config.optimize_containers || (self.config.always_count_containers && len.is_none()) {
+            // header depends on the elements, written once they are all known
+            let elements = Some(Recording::with_capacity(len.unwrap_or(0)));
+            return Ok(Self::SerializeSeq { len, index: None, elements, count: 0, typed, ser: self });
         }
 
         self.formatter.mark(Marker::ArrayStart)?;
 
         if let Some(len) = len {
             self.formatter.mark(Marker::Length)?;
-            self.formatter.len(len)?;
+            self.write_len(len)?;
         }
 
-        Ok(Self::SerializeSeq { len, ser: self })
+        Ok(Self::SerializeSeq { len, index: None, elements: None, count: 0, typed, ser: self })
     }
 
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
-        self.serialize_seq(Some(len))
+        if !self.config.tuples_as_objects {
+            return self.serialize_seq(Some(len));
+        }
+
+        if self.formatter.get_mode().is_key() {
+            return Err(Error::InvalidKey { found: "tuple" });
+        }
+        self.descend()?;
+
+        self.formatter.mark(Marker::ObjectStart)?;
+        self.formatter.mark(Marker::Length)?;
+        self.write_len(len)?;
+
+        Ok(Self::SerializeTuple { len: Some(len), index: Some(0), elements: None, count: 0, typed: false, ser: self })
     }
 
     fn serialize_tuple_struct(
@@ -286,7 +1039,7 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
         _name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        self.serialize_seq(Some(len))
+        self.serialize_tuple(len)
     }
 
     fn serialize_tuple_variant(
@@ -297,41 +1050,36 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
         len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "tuple variant" });
         }
+        self.descend()?;
 
         self.formatter.mark(Marker::ObjectStart)?;
         self.formatter.mark(Marker::Length)?;
-        self.formatter.len(1)?;
+        self.write_len(1)?;
 
         self.formatter.set_mode(FormatterMode::Key);
         variant.serialize(&mut *self)?;
 
         self.formatter.set_mode(FormatterMode::Value);
+        self.enter_value();
         self.formatter.mark(Marker::ArrayStart)?;
         self.formatter.mark(Marker::Length)?;
-        self.formatter.len(len)?;
+        self.write_len(len)?;
 
-        Ok(Self::SerializeTupleVariant { ser: self })
+        Ok(Self::SerializeTupleVariant { count: 0, ser: self })
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
-        if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
-        }
-
-        self.formatter.mark(Marker::ObjectStart)?;
-
-        if let Some(len) = len {
-            self.formatter.mark(Marker::Length)?;
-            self.formatter.len(len)?;
-        }
-
-        Ok(Self::SerializeMap { len, ser: self })
+        // a known map length is exact, and checked against the entries in `end`
+        self.start_object(len, false, false)
     }
 
     fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
-        self.serialize_map(Some(len))
+        let positional = self.config.struct_as_array;
+        // fields left out change the count, which is only known at the end
+        let skip_none = self.config.skip_none_fields && !positional;
+        self.start_object(Some(len), positional, skip_none)
     }
 
     fn serialize_struct_variant(
@@ -342,30 +1090,287 @@ impl<'a, F> serde::ser::Serializer for &'a mut Serializer<F>
         len: usize,
     ) -> Result<Self::SerializeStructVariant> {
         if self.formatter.get_mode().is_key() {
-            return Err(Error::InvalidKey);
+            return Err(Error::InvalidKey { found: "struct variant" });
         }
+        self.descend()?;
 
         self.formatter.mark(Marker::ObjectStart)?;
         self.formatter.mark(Marker::Length)?;
-        self.formatter.len(1)?;
+        self.write_len(1)?;
 
         self.formatter.set_mode(FormatterMode::Key);
         variant.serialize(&mut *self)?;
 
         self.formatter.set_mode(FormatterMode::Value);
+        self.enter_value();
         self.formatter.mark(Marker::ObjectStart)?;
         self.formatter.mark(Marker::Length)?;
-        self.formatter.len(len)?;
+        self.write_len(len)?;
 
-        Ok(Self::SerializeStructVariant { ser: self })
+        Ok(Self::SerializeStructVariant { count: 0, ser: self })
     }
 }
 
 pub struct ArraySerializer<'a, F> {
     len: Option<usize>,
+    // next positional key when a tuple is written as an object
+    index: Option<usize>,
+    // recorded elements when the array is optimized
+    elements: Option<Recording>,
+    // number of elements written so far
+    count: usize,
+    // typed form requested by `Value::TypedArray`
+    typed: bool,
     ser: &'a mut Serializer<F>,
 }
 
+impl<'a, F> ArraySerializer<'a, F>
+    where
+        F: Formatter,
+{
+    fn serialize_positional<T>(&mut self, index: usize, value: &T) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+    {
+        self.ser.formatter.set_mode(FormatterMode::Key);
+        index.to_string().serialize(&mut *self.ser)?;
+
+        self.ser.formatter.set_mode(FormatterMode::Value);
+        self.ser.enter(PathSegment::Index(index));
+        value.serialize(&mut *self.ser)?;
+        self.ser.leave();
+
+        self.index = Some(index + 1);
+        Ok(())
+    }
+
+    fn buffer_element<T>(&mut self, value: &T) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+    {
+        if let Some(mut elements) = self.elements.take() {
+            let result = self.ser.record(value, FormatterMode::Value, &mut elements);
+            self.elements = Some(elements);
+            result?;
+        }
+        Ok(())
+    }
+
+    // writes buffered elements, dropping their markers when optimizing and all share one scalar type
+    fn write_buffered(&mut self, elements: Recording) -> Result<()> {
+        let marker = match self.ser.config.optimize_containers || self.typed {
+            true => elements.common_scalar_marker(elements.items.iter()),
+            false => None,
+        };
+
+        self.ser.formatter.mark(Marker::ArrayStart)?;
+
+        match marker {
+            Some(marker) => {
+                self.ser.formatter.mark(Marker::OfType)?;
+                self.ser.formatter.mark(marker)?;
+                self.ser.formatter.mark(Marker::Length)?;
+                self.ser.write_len(elements.items.len())?;
+                for element in &elements.items {
+                    self.ser.replay_payload(&elements, &elements.calls[element.clone()])?;
+                }
+            }
+            None => {
+                self.ser.formatter.mark(Marker::Length)?;
+                self.ser.write_len(elements.items.len())?;
+                for element in &elements.items {
+                    self.ser.replay(&elements, &elements.calls[element.clone()])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// A formatter call made while a buffered element was encoded, replayed on
+// the serializer's formatter once the container header is written.
+#[derive(Clone, Copy, Debug)]
+enum Call {
+    Mode(FormatterMode),
+    // range of `Recording::bytes`
+    Raw(usize, usize),
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Mark(Marker),
+    Len(usize),
+}
+
+impl Call {
+    // bytes `SimpleFormatter` writes for the call, and their number
+    fn simple_bytes(&self) -> ([u8; 9], usize) {
+        let mut bytes = [0; 9];
+        let mut rest = &mut bytes[..];
+        let mut formatter = SimpleFormatter::new(&mut rest);
+        // nine bytes hold any single marker and scalar
+        let _ = match *self {
+            Call::Bool(v) => formatter.bool(v),
+            Call::U8(v) => formatter.u8(v),
+            Call::U16(v) => formatter.u16(v),
+            Call::U32(v) => formatter.u32(v),
+            Call::I8(v) => formatter.i8(v),
+            Call::I16(v) => formatter.i16(v),
+            Call::I32(v) => formatter.i32(v),
+            Call::I64(v) => formatter.i64(v),
+            Call::F32(v) => formatter.f32(v),
+            Call::F64(v) => formatter.f64(v),
+            Call::Mark(marker) => formatter.mark(marker),
+            Call::Mode(_) | Call::Raw(..) | Call::Len(_) => Ok(()),
+        };
+        let len = 9 - rest.len();
+        (bytes, len)
+    }
+}
+
+// Formatter calls of the buffered elements of one container, with the bytes
+// passed to `raw` kept in a single buffer.
+#[derive(Debug, Default)]
+struct Recording {
+    calls: Vec<Call>,
+    bytes: Vec<u8>,
+    // calls of each element, or of each key and value of an object
+    items: Vec<Range<usize>>,
+}
+
+impl Recording {
+    fn with_capacity(items: usize) -> Recording {
+        Recording {
+            items: Vec::with_capacity(items),
+            ..Default::default()
+        }
+    }
+
+    // first call of an item that writes something
+    fn first_call(&self, item: &Range<usize>) -> Option<&Call> {
+        self.calls[item.clone()].iter().find(|call| !matches!(call, Call::Mode(_)))
+    }
+
+    // marker the item starts with when written by `SimpleFormatter`
+    fn marker(&self, item: &Range<usize>) -> Option<u8> {
+        let (bytes, len) = self.first_call(item)?.simple_bytes();
+        (len > 0).then_some(bytes[0])
+    }
+
+    fn common_scalar_marker<'b, I>(&self, items: I) -> Option<Marker>
+        where
+            I: IntoIterator<Item = &'b Range<usize>>,
+    {
+        let mut items = items.into_iter();
+        let first = self.marker(items.next()?)?;
+        if items.any(|item| self.marker(item) != Some(first)) {
+            return None;
+        }
+
+        Marker::try_from(first).ok().filter(|marker| marker.is_scalar())
+    }
+
+    // string data of a recorded key
+    fn key_text(&self, item: &Range<usize>) -> &[u8] {
+        self.calls[item.clone()].iter()
+            .find_map(|call| match *call {
+                Call::Raw(start, end) => Some(&self.bytes[start..end]),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    fn is_null(&self, item: &Range<usize>) -> bool {
+        let mut calls = self.calls[item.clone()].iter().filter(|call| !matches!(call, Call::Mode(_)));
+        matches!((calls.next(), calls.next()), (Some(Call::Mark(Marker::Null)), None))
+    }
+}
+
+// Records the calls of a buffered element.
+struct Recorder<'r> {
+    recording: &'r mut Recording,
+    mode: FormatterMode,
+}
+
+impl<'r> Recorder<'r> {
+    fn push(&mut self, call: Call) -> std::io::Result<()> {
+        self.recording.calls.push(call);
+        Ok(())
+    }
+}
+
+impl<'r> Formatter for Recorder<'r> {
+    fn set_mode(&mut self, mode: FormatterMode) {
+        self.mode = mode;
+        self.recording.calls.push(Call::Mode(mode));
+    }
+
+    fn get_mode(&mut self) -> FormatterMode {
+        self.mode
+    }
+
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        let start = self.recording.bytes.len();
+        self.recording.bytes.extend_from_slice(v);
+        self.push(Call::Raw(start, self.recording.bytes.len()))
+    }
+
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        self.push(Call::Bool(v))
+    }
+
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        self.push(Call::U8(v))
+    }
+
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        self.push(Call::U16(v))
+    }
+
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        self.push(Call::U32(v))
+    }
+
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        self.push(Call::I8(v))
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        self.push(Call::I16(v))
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        self.push(Call::I32(v))
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        self.push(Call::I64(v))
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        self.push(Call::F32(v))
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        self.push(Call::F64(v))
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        self.push(Call::Mark(marker))
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        self.push(Call::Len(v))
+    }
+}
+
 impl<'a, F> SerializeSeq for ArraySerializer<'a, F>
     where
         F: Formatter,
@@ -373,15 +1378,33 @@ impl<'a, F> SerializeSeq for ArraySerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<Self::Ok>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
-        value.serialize(&mut *self.ser)?;
+        self.ser.enter(PathSegment::Index(self.count));
+        if self.elements.is_some() {
+            self.buffer_element(value)?;
+        } else {
+            if let Some(heartbeat) = self.ser.config.noop_heartbeat {
+                if self.len.is_none() && self.count > 0 && heartbeat() {
+                    self.ser.write_noop()?;
+                }
+            }
+            value.serialize(&mut *self.ser)?;
+        }
+        self.ser.leave();
+
+        self.count += 1;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok> {
+    fn end(mut self) -> Result<Self::Ok> {
+        self.ser.ascend();
+        if let Some(elements) = self.elements.take() {
+            return self.write_buffered(elements);
+        }
+
         if self.len.is_none() {
             self.ser.formatter.mark(Marker::ArrayEnd)?;
         }
@@ -396,15 +1419,23 @@ impl<'a, F> SerializeTuple for ArraySerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, _value: &T) -> Result<Self::Ok>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
-        Ok(())
+        if let Some(index) = self.index {
+            return self.serialize_positional(index, value);
+        }
+        SerializeSeq::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(())
+        // tuples written as objects have a counted header and no trailer
+        if self.index.is_some() {
+            self.ser.ascend();
+            return Ok(());
+        }
+        SerializeSeq::end(self)
     }
 }
 
@@ -415,23 +1446,82 @@ impl<'a, F> SerializeTupleStruct for ArraySerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, _value: &T) -> Result<Self::Ok>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
-        Ok(())
+        if let Some(index) = self.index {
+            return self.serialize_positional(index, value);
+        }
+        SerializeSeq::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        Ok(())
+        // tuples written as objects have a counted header and no trailer
+        if self.index.is_some() {
+            self.ser.ascend();
+            return Ok(());
+        }
+        SerializeSeq::end(self)
     }
 }
 
 pub struct ObjectSerializer<'a, F> {
     len: Option<usize>,
+    // number of entries written so far, checked against a counted header
+    count: usize,
+    // struct written as an array of its values
+    positional: bool,
+    // recorded keys and values when the object is optimized
+    entries: Option<Recording>,
+    // typed form requested by `Uniform`
+    typed: bool,
     ser: &'a mut Serializer<F>,
 }
 
+impl<'a, F> ObjectSerializer<'a, F>
+    where
+        F: Formatter,
+{
+    // writes buffered entries, dropping value markers when optimizing and all share one scalar type
+    fn write_buffered(&mut self, recording: Recording) -> Result<()> {
+        let mut entries = recording.items.chunks_exact(2).map(|item| (&item[0], &item[1])).collect::<Vec<_>>();
+        if self.ser.config.sort_keys && !self.positional {
+            entries.sort_by(|(a, _), (b, _)| recording.key_text(a).cmp(recording.key_text(b)));
+        }
+
+        let marker = match self.ser.config.optimize_containers || self.typed {
+            true => recording.common_scalar_marker(entries.iter().map(|(_, value)| *value)),
+            false => None,
+        };
+
+        self.ser.formatter.mark(if self.positional { Marker::ArrayStart } else { Marker::ObjectStart })?;
+
+        match marker {
+            Some(marker) => {
+                self.ser.formatter.mark(Marker::OfType)?;
+                self.ser.formatter.mark(marker)?;
+                self.ser.formatter.mark(Marker::Length)?;
+                self.ser.write_len(entries.len())?;
+                for (key, value) in &entries {
+                    self.ser.replay(&recording, &recording.calls[(*key).clone()])?;
+                    self.ser.replay_payload(&recording, &recording.calls[(*value).clone()])?;
+                }
+            }
+            None => {
+                self.ser.formatter.mark(Marker::Length)?;
+                self.ser.write_len(entries.len())?;
+                for (key, value) in &entries {
+                    self.ser.replay(&recording, &recording.calls[(*key).clone()])?;
+                    self.ser.replay(&recording, &recording.calls[(*value).clone()])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl<'a, F> SerializeMap for ObjectSerializer<'a, F>
     where
         F: Formatter,
@@ -439,29 +1529,57 @@ impl<'a, F> SerializeMap for ObjectSerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> std::result::Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> std::result::Result<(), Self::Error>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
+        if let Some(mut entries) = self.entries.take() {
+            let result = self.ser.record(key, FormatterMode::Key, &mut entries);
+            self.entries = Some(entries);
+            return result;
+        }
+
         self.ser.formatter.set_mode(FormatterMode::Key);
         key.serialize(&mut *self.ser)?;
         Ok(())
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> std::result::Result<(), Self::Error>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
+        if let Some(mut entries) = self.entries.take() {
+            self.ser.enter_value();
+            let result = self.ser.record(value, FormatterMode::Value, &mut entries);
+            self.entries = Some(entries);
+            result?;
+            self.ser.leave();
+            return Ok(());
+        }
+
         self.ser.formatter.set_mode(FormatterMode::Value);
+        self.ser.enter_value();
         value.serialize(&mut *self.ser)?;
+        self.ser.leave();
+        self.count += 1;
         Ok(())
     }
 
-    fn end(self) -> std::result::Result<Self::Ok, Self::Error> {
-        if self.len.is_none() {
-            self.ser.formatter.mark(Marker::ObjectEnd)?;
+    fn end(mut self) -> std::result::Result<Self::Ok, Self::Error> {
+        self.ser.ascend();
+        if let Some(entries) = self.entries.take() {
+            return self.write_buffered(entries);
+        }
+
+        match self.len {
+            // the header is already written and must not claim more or fewer entries
+            Some(len) if len != self.count => Err(Error::InvalidLength),
+            Some(_) => Ok(()),
+            None => {
+                self.ser.formatter.mark(if self.positional { Marker::ArrayEnd } else { Marker::ObjectEnd })?;
+                Ok(())
+            }
         }
-        Ok(())
     }
 }
 
@@ -472,25 +1590,44 @@ impl<'a, F> SerializeStruct for ObjectSerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
+        if self.positional {
+            // keys are implied by the position of the value
+            if self.ser.config.track_path {
+                self.ser.last_key = Some(key.to_string());
+            }
+            if let Some(entries) = &mut self.entries {
+                let end = entries.calls.len();
+                entries.items.push(end..end);
+            }
+            return self.serialize_value(value);
+        }
+
         self.serialize_key(key)?;
         self.serialize_value(value)?;
 
+        if self.ser.config.skip_none_fields {
+            if let Some(entries) = &mut self.entries {
+                if entries.items.last().is_some_and(|value| entries.is_null(value)) {
+                    entries.items.truncate(entries.items.len() - 2);
+                }
+            }
+        }
+
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
-        if self.len.is_none() {
-            self.ser.formatter.mark(Marker::ObjectEnd)?;
-        }
-        Ok(())
+        SerializeMap::end(self)
     }
 }
 
 pub struct VariantSerializer<'a, F> {
+    // number of tuple variant fields written so far
+    count: usize,
     ser: &'a mut Serializer<F>,
 }
 
@@ -501,14 +1638,21 @@ impl<'a, F> SerializeTupleVariant for VariantSerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<Self::Ok>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.enter(PathSegment::Index(self.count));
+        value.serialize(&mut *self.ser)?;
+        self.ser.leave();
+
+        self.count += 1;
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
+        self.ser.leave();
+        self.ser.ascend();
         Ok(())
     }
 }
@@ -520,24 +1664,41 @@ impl<'a, F> SerializeStructVariant for VariantSerializer<'a, F>
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok>
         where
-            T: Serialize,
+            T: ?Sized + Serialize,
     {
         self.ser.formatter.set_mode(FormatterMode::Key);
         key.serialize(&mut *self.ser)?;
 
         self.ser.formatter.set_mode(FormatterMode::Value);
+        self.ser.enter_value();
         value.serialize(&mut *self.ser)?;
+        self.ser.leave();
 
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
+        self.ser.leave();
+        self.ser.ascend();
         Ok(())
     }
 }
 
+/// Writes the pieces of the encoding chosen by [`Serializer`].
+///
+/// Every scalar method writes its marker followed by the big-endian payload;
+/// `u16` and `u32` may pick a smaller marker that holds the value. `mark`
+/// writes a marker alone, `raw` writes bytes as given (string contents and
+/// payloads of strongly typed elements), and `len` writes a container or
+/// string length together with its integer marker, see
+/// [`write_smallest_len`].
+///
+/// The mode is state of the serializer kept by the formatter: it is
+/// [`FormatterMode::Key`] while an object key is written, which the
+/// serializer uses to leave out the key's string marker. Formatters store it
+/// as set; wrappers forward it to the formatter they wrap.
 pub trait Formatter {
     fn set_mode(&mut self, mode: FormatterMode);
     fn get_mode(&mut self) -> FormatterMode;
@@ -561,26 +1722,59 @@ pub trait Formatter {
     fn mark(&mut self, marker: Marker) -> std::io::Result<()>;
 
     fn len(&mut self, v: usize) -> std::io::Result<()>;
+
+    /// Number of bytes written so far, for formatters that produce output and
+    /// keep count. Wrappers such as [`StatsFormatter`] read it to measure
+    /// what the formatter they wrap wrote.
+    fn bytes_written(&self) -> Option<usize> {
+        None
+    }
 }
 
-pub struct SimpleFormatter<'a, W> {
-    writer: &'a mut W,
+pub struct SimpleFormatter<W> {
+    writer: CountingWriter<W>,
     mode: FormatterMode,
+    compact_lengths: bool,
 }
 
-impl<'a, W> SimpleFormatter<'a, W>
+impl<W> SimpleFormatter<W>
     where
         W: Write,
 {
-    pub fn new(writer: &'a mut W) -> SimpleFormatter<'a, W> {
+    pub fn new(writer: W) -> SimpleFormatter<W> {
         SimpleFormatter {
-            writer,
+            writer: CountingWriter::new(writer),
             mode: FormatterMode::Value,
+            compact_lengths: false,
+        }
+    }
+
+    /// Like [`new`](SimpleFormatter::new), but writes every length with the
+    /// smallest signed marker that fits (`i`, `I`, `l` or `L`) instead of
+    /// always `L`.
+    pub fn compact(writer: W) -> SimpleFormatter<W> {
+        SimpleFormatter {
+            compact_lengths: true,
+            ..SimpleFormatter::new(writer)
         }
     }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer.inner
+    }
+
+    /// Unwraps the formatter, returning the writer. Nothing is buffered, but
+    /// the writer itself may still need flushing.
+    pub fn into_inner(self) -> W {
+        self.writer.inner
+    }
 }
 
-impl<'a, W> Formatter for SimpleFormatter<'a, W>
+impl<W> Formatter for SimpleFormatter<W>
     where
         W: Write,
 {
@@ -605,12 +1799,26 @@ impl<'a, W> Formatter for SimpleFormatter<'a, W>
         self.writer.write_all(&v.to_be_bytes())
     }
 
+    // smallest marker that holds the value: `U`, `I` or `l`
     fn u16(&mut self, v: u16) -> std::io::Result<()> {
-        self.i32(v as i32)
+        match u8::try_from(v) {
+            Ok(v) => self.u8(v),
+            Err(_) => match i16::try_from(v) {
+                Ok(v) => self.i16(v),
+                Err(_) => self.i32(v.into()),
+            },
+        }
     }
 
+    // smallest marker that holds the value: `U`, `I`, `l` or `L`
     fn u32(&mut self, v: u32) -> std::io::Result<()> {
-        self.i64(v as i64)
+        match u16::try_from(v) {
+            Ok(v) => self.u16(v),
+            Err(_) => match i32::try_from(v) {
+                Ok(v) => self.i32(v),
+                Err(_) => self.i64(v.into()),
+            },
+        }
     }
 
     fn i8(&mut self, v: i8) -> std::io::Result<()> {
@@ -648,301 +1856,2166 @@ impl<'a, W> Formatter for SimpleFormatter<'a, W>
     }
 
     fn len(&mut self, v: usize) -> std::io::Result<()> {
-        self.i64(v as i64)
+        if !self.compact_lengths {
+            return self.i64(v as i64);
+        }
+
+        write_smallest_len(self, v)
     }
-}
 
-#[derive(Copy, Clone)]
-pub enum FormatterMode {
-    Key,
-    Value,
+    fn bytes_written(&self) -> Option<usize> {
+        Some(self.writer.count)
+    }
 }
 
-impl FormatterMode {
-    pub fn is_key(&self) -> bool {
-        match self {
-            FormatterMode::Key => true,
-            FormatterMode::Value => false,
-        }
-    }
-    pub fn is_value(&self) -> bool {
-        !self.is_key()
+/// Writes a length with the smallest signed marker that holds it (`i`, `I`,
+/// `l` or `L`), for use in [`Formatter::len`] implementations.
+pub fn write_smallest_len<F>(formatter: &mut F, len: usize) -> std::io::Result<()>
+    where
+        F: Formatter + ?Sized,
+{
+    match smallest_length_marker(len) {
+        Marker::I8 => formatter.i8(len as i8),
+        Marker::I16 => formatter.i16(len as i16),
+        Marker::I32 => formatter.i32(len as i32),
+        _ => formatter.i64(len as i64),
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-    use std::mem::*;
+impl<F> Formatter for &mut F
+    where
+        F: Formatter + ?Sized,
+{
+    fn set_mode(&mut self, mode: FormatterMode) {
+        (**self).set_mode(mode)
+    }
 
-    use super::*;
+    fn get_mode(&mut self) -> FormatterMode {
+        (**self).get_mode()
+    }
 
-    #[derive(Serialize)]
-    struct SimpleStruct {
-        field1: i32,
-        field2: String,
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        (**self).raw(v)
     }
 
-    #[test]
-    fn serializing_true_produces_1_byte_big_t_value() {
-        let value = true;
-        let out = to_bytes(&value).unwrap();
-        assert_eq!(out, vec![b'T']);
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        (**self).bool(v)
     }
 
-    #[test]
-    fn serializing_false_produces_1_byte_big_f_value() {
-        let value = false;
-        let out = to_bytes(&value).unwrap();
-        assert_eq!(out, vec![b'F']);
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        (**self).u8(v)
     }
 
-    #[test]
-    fn serializing_u8_produces_2_byte_big_u_value() {
-        let value = 255u8;
-        let out = to_bytes(&value).unwrap();
-        assert_eq!(out, vec![b'U', value.to_be()]);
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        (**self).u16(v)
     }
 
-    #[test]
-    fn serializing_i8_produces_2_byte_small_i_value() {
-        let value = 127i8;
-        let out = to_bytes(&value).unwrap();
-        assert_eq!(
-            out,
-            vec![b'i', unsafe { transmute::<i8, u8>(value.to_be()) }]
-        );
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        (**self).u32(v)
     }
 
-    #[test]
-    fn serializing_i16_produces_3_byte_big_i_value() {
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        (**self).i8(v)
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        (**self).i16(v)
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        (**self).i32(v)
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        (**self).i64(v)
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        (**self).f32(v)
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        (**self).f64(v)
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        (**self).mark(marker)
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        (**self).len(v)
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        (**self).bytes_written()
+    }
+}
+
+/// Feeds the encoded bytes to a [`Hasher`] instead of writing them out.
+pub struct HashingFormatter<H> {
+    writer: CountingWriter<HashWriter<H>>,
+    mode: FormatterMode,
+}
+
+impl<H> HashingFormatter<H>
+    where
+        H: Hasher,
+{
+    pub fn new(hasher: H) -> HashingFormatter<H> {
+        HashingFormatter {
+            writer: CountingWriter::new(HashWriter(hasher)),
+            mode: FormatterMode::Value,
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.writer.inner.0.finish()
+    }
+
+    pub fn into_inner(self) -> H {
+        self.writer.inner.0
+    }
+
+    // encodes exactly like `SimpleFormatter`
+    fn simple(&mut self) -> SimpleFormatter<&mut CountingWriter<HashWriter<H>>> {
+        SimpleFormatter::new(&mut self.writer)
+    }
+}
+
+impl<H> Formatter for HashingFormatter<H>
+    where
+        H: Hasher,
+{
+    fn set_mode(&mut self, mode: FormatterMode) {
+        self.mode = mode;
+    }
+
+    fn get_mode(&mut self) -> FormatterMode {
+        self.mode
+    }
+
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        self.simple().raw(v)
+    }
+
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        self.simple().bool(v)
+    }
+
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        self.simple().u8(v)
+    }
+
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        self.simple().u16(v)
+    }
+
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        self.simple().u32(v)
+    }
+
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        self.simple().i8(v)
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        self.simple().i16(v)
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        self.simple().i32(v)
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        self.simple().i64(v)
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        self.simple().f32(v)
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        self.simple().f64(v)
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        self.simple().mark(marker)
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        self.simple().len(v)
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        Some(self.writer.count)
+    }
+}
+
+struct HashWriter<H>(H);
+
+impl<H> Write for HashWriter<H>
+    where
+        H: Hasher,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Passes writes on and counts the bytes the writer took.
+struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> CountingWriter<W> {
+        CountingWriter { inner, count: 0 }
+    }
+}
+
+impl<W> Write for CountingWriter<W>
+    where
+        W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.inner.write_all(buf)?;
+        self.count += buf.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// bytes collected before they are passed on to the writer
+const BATCH_LEN: usize = 8 * 1024;
+
+// Collects small writes, and passes writes of at least `BATCH_LEN` bytes on
+// together with the collected ones in a vectored write, without copying them.
+struct BatchWriter<W> {
+    inner: W,
+    batch: Vec<u8>,
+}
+
+impl<W> BatchWriter<W>
+    where
+        W: Write,
+{
+    fn new(inner: W) -> BatchWriter<W> {
+        BatchWriter {
+            inner,
+            batch: Vec::with_capacity(BATCH_LEN),
+        }
+    }
+
+    fn write_batch(&mut self) -> std::io::Result<()> {
+        let result = self.inner.write_all(&self.batch);
+        self.batch.clear();
+        result
+    }
+
+    fn write_large(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        // bytes of the batch and of `buf` written so far
+        let mut batched = 0;
+        let mut written = 0;
+        while written < buf.len() {
+            let result = if batched < self.batch.len() {
+                let slices = [IoSlice::new(&self.batch[batched..]), IoSlice::new(&buf[written..])];
+                self.inner.write_vectored(&slices)
+            } else {
+                self.inner.write(&buf[written..])
+            };
+            let error: std::io::Error = match result {
+                Ok(0) => std::io::ErrorKind::WriteZero.into(),
+                Ok(n) => {
+                    let from_batch = n.min(self.batch.len() - batched);
+                    batched += from_batch;
+                    written += n - from_batch;
+                    continue;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => e,
+            };
+            // keep what the writer has not taken yet
+            self.batch.drain(..batched);
+            return Err(error);
+        }
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+impl<W> Write for BatchWriter<W>
+    where
+        W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() >= BATCH_LEN {
+            self.write_large(buf)?;
+        } else {
+            if self.batch.len() + buf.len() > BATCH_LEN {
+                self.write_batch()?;
+            }
+            self.batch.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_batch()?;
+        self.inner.flush()
+    }
+}
+
+/// Counts the bytes `SimpleFormatter` would write, discarding them.
+pub struct CountingFormatter {
+    inner: SimpleFormatter<std::io::Sink>,
+}
+
+impl CountingFormatter {
+    pub fn new() -> CountingFormatter {
+        CountingFormatter {
+            inner: SimpleFormatter::new(std::io::sink()),
+        }
+    }
+
+    /// Counts like [`SimpleFormatter::compact`].
+    pub fn compact() -> CountingFormatter {
+        CountingFormatter {
+            inner: SimpleFormatter::compact(std::io::sink()),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.writer.count
+    }
+}
+
+impl Default for CountingFormatter {
+    fn default() -> CountingFormatter {
+        CountingFormatter::new()
+    }
+}
+
+impl Formatter for CountingFormatter {
+    fn set_mode(&mut self, mode: FormatterMode) {
+        self.inner.set_mode(mode)
+    }
+
+    fn get_mode(&mut self) -> FormatterMode {
+        self.inner.get_mode()
+    }
+
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        self.inner.raw(v)
+    }
+
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        self.inner.bool(v)
+    }
+
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        self.inner.u8(v)
+    }
+
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        self.inner.u16(v)
+    }
+
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        self.inner.u32(v)
+    }
+
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        self.inner.i8(v)
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        self.inner.i16(v)
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        self.inner.i32(v)
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        self.inner.i64(v)
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        self.inner.f32(v)
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        self.inner.f64(v)
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        self.inner.mark(marker)
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        self.inner.len(v)
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        self.inner.bytes_written()
+    }
+}
+
+/// Forwards every call to two formatters, e.g. to collect diagnostics
+/// alongside the real output.
+pub struct TeeFormatter<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> TeeFormatter<A, B>
+    where
+        A: Formatter,
+        B: Formatter,
+{
+    pub fn new(first: A, second: B) -> TeeFormatter<A, B> {
+        TeeFormatter { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A, B> Formatter for TeeFormatter<A, B>
+    where
+        A: Formatter,
+        B: Formatter,
+{
+    fn set_mode(&mut self, mode: FormatterMode) {
+        self.first.set_mode(mode);
+        self.second.set_mode(mode);
+    }
+
+    fn get_mode(&mut self) -> FormatterMode {
+        self.first.get_mode()
+    }
+
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        self.first.raw(v)?;
+        self.second.raw(v)
+    }
+
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        self.first.bool(v)?;
+        self.second.bool(v)
+    }
+
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        self.first.u8(v)?;
+        self.second.u8(v)
+    }
+
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        self.first.u16(v)?;
+        self.second.u16(v)
+    }
+
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        self.first.u32(v)?;
+        self.second.u32(v)
+    }
+
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        self.first.i8(v)?;
+        self.second.i8(v)
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        self.first.i16(v)?;
+        self.second.i16(v)
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        self.first.i32(v)?;
+        self.second.i32(v)
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        self.first.i64(v)?;
+        self.second.i64(v)
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        self.first.f32(v)?;
+        self.second.f32(v)
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        self.first.f64(v)?;
+        self.second.f64(v)
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        self.first.mark(marker)?;
+        self.second.mark(marker)
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        self.first.len(v)?;
+        self.second.len(v)
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        self.first.bytes_written()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormatterStats {
+    /// Number of markers written, including the type markers of scalar values.
+    pub markers: usize,
+    /// Number of arrays and objects started.
+    pub containers: usize,
+    /// Number of bytes the wrapped formatter wrote, as reported by its
+    /// [`Formatter::bytes_written`]; zero if it does not keep count.
+    pub bytes: usize,
+}
+
+/// Counts what passes through to the wrapped formatter.
+pub struct StatsFormatter<F> {
+    inner: F,
+    // bytes the wrapped formatter had written before
+    start: usize,
+    stats: FormatterStats,
+}
+
+impl<F> StatsFormatter<F>
+    where
+        F: Formatter,
+{
+    pub fn new(inner: F) -> StatsFormatter<F> {
+        StatsFormatter {
+            start: inner.bytes_written().unwrap_or(0),
+            inner,
+            stats: FormatterStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> &FormatterStats {
+        &self.stats
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn record(&mut self, result: std::io::Result<()>) -> std::io::Result<()> {
+        if let Some(written) = self.inner.bytes_written() {
+            self.stats.bytes = written - self.start;
+        }
+        result
+    }
+
+    fn record_marker(&mut self, result: std::io::Result<()>) -> std::io::Result<()> {
+        self.stats.markers += 1;
+        self.record(result)
+    }
+}
+
+impl<F> Formatter for StatsFormatter<F>
+    where
+        F: Formatter,
+{
+    fn set_mode(&mut self, mode: FormatterMode) {
+        self.inner.set_mode(mode)
+    }
+
+    fn get_mode(&mut self) -> FormatterMode {
+        self.inner.get_mode()
+    }
+
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        let result = self.inner.raw(v);
+        self.record(result)
+    }
+
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        let result = self.inner.bool(v);
+        self.record_marker(result)
+    }
+
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        let result = self.inner.u8(v);
+        self.record_marker(result)
+    }
+
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        let result = self.inner.u16(v);
+        self.record_marker(result)
+    }
+
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        let result = self.inner.u32(v);
+        self.record_marker(result)
+    }
+
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        let result = self.inner.i8(v);
+        self.record_marker(result)
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        let result = self.inner.i16(v);
+        self.record_marker(result)
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        let result = self.inner.i32(v);
+        self.record_marker(result)
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        let result = self.inner.i64(v);
+        self.record_marker(result)
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        let result = self.inner.f32(v);
+        self.record_marker(result)
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        let result = self.inner.f64(v);
+        self.record_marker(result)
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        if matches!(marker, Marker::ArrayStart | Marker::ObjectStart) {
+            self.stats.containers += 1;
+        }
+        let result = self.inner.mark(marker);
+        self.record_marker(result)
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        let result = self.inner.len(v);
+        self.record_marker(result)
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        self.inner.bytes_written()
+    }
+}
+
+/// What the serializer is currently writing, see [`Formatter`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FormatterMode {
+    /// An object key; strings are written without their `S` marker and
+    /// non-string keys are rejected.
+    Key,
+    /// Any other value.
+    Value,
+}
+
+impl FormatterMode {
+    pub fn is_key(&self) -> bool {
+        match self {
+            FormatterMode::Key => true,
+            FormatterMode::Value => false,
+        }
+    }
+    pub fn is_value(&self) -> bool {
+        !self.is_key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::mem::*;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+    struct SimpleStruct {
+        field1: i32,
+        field2: String,
+    }
+
+    struct ByteSlice<'a>(&'a [u8]);
+
+    impl<'a> Serialize for ByteSlice<'a> {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+        {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    // sequence that does not report its length up front
+    struct UnsizedSeq<'a>(&'a [i32]);
+
+    impl<'a> Serialize for UnsizedSeq<'a> {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+        {
+            let mut seq = serializer.serialize_seq(None)?;
+            for value in self.0 {
+                seq.serialize_element(value)?;
+            }
+            seq.end()
+        }
+    }
+
+    // map that does not report its length up front
+    struct UnsizedMap<'a>(&'a [(&'a str, Option<i32>)]);
+
+    impl<'a> Serialize for UnsizedMap<'a> {
+        fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+        {
+            let mut map = serializer.serialize_map(None)?;
+            for (key, value) in self.0 {
+                map.serialize_entry(key, value)?;
+            }
+            map.end()
+        }
+    }
+
+    #[test]
+    fn serializing_true_produces_1_byte_big_t_value() {
+        let value = true;
+        let out = to_bytes(&value).unwrap();
+        assert_eq!(out, vec![b'T']);
+    }
+
+    #[test]
+    fn serializing_false_produces_1_byte_big_f_value() {
+        let value = false;
+        let out = to_bytes(&value).unwrap();
+        assert_eq!(out, vec![b'F']);
+    }
+
+    #[test]
+    fn serializing_u8_produces_2_byte_big_u_value() {
+        let value = 255u8;
+        let out = to_bytes(&value).unwrap();
+        assert_eq!(out, vec![b'U', value.to_be()]);
+    }
+
+    #[test]
+    #[allow(unnecessary_transmutes)]
+    fn serializing_i8_produces_2_byte_small_i_value() {
+        let value = 127i8;
+        let out = to_bytes(&value).unwrap();
+        assert_eq!(
+            out,
+            vec![b'i', unsafe { transmute::<i8, u8>(value.to_be()) }]
+        );
+    }
+
+    #[test]
+    fn serializing_i16_produces_3_byte_big_i_value() {
         let value = 32767i16;
         let out = to_bytes(&value).unwrap();
 
-        assert_eq!(out.len(), 3);
-        assert_eq!(out[0], b'I');
-        assert_eq!(out[1..], value.to_be_bytes());
+        assert_eq!(out.len(), 3);
+        assert_eq!(out[0], b'I');
+        assert_eq!(out[1..], value.to_be_bytes());
+    }
+
+    #[test]
+    fn serializing_i32_produces_5_byte_small_l_value() {
+        let value = 2147483647i32;
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[0], b'l');
+        assert_eq!(out[1..], value.to_be_bytes());
+    }
+
+    #[test]
+    fn serializing_i64_produces_9_byte_big_l_value() {
+        let value = 9_223_372_036_854_775_807i64;
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 9);
+        assert_eq!(out[0], b'L');
+        assert_eq!(out[1..], value.to_be_bytes());
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn serializing_f32_produces_5_byte_small_d_value() {
+        let value = 3.14f32;
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[0], b'd');
+        assert_eq!(out[1..], value.to_be_bytes());
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn serializing_f64_produces_9_byte_big_d_value() {
+        let value = 3.14f64;
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 9);
+        assert_eq!(out[0], b'D');
+        assert_eq!(out[1..], value.to_be_bytes());
+    }
+
+    #[test]
+    fn serializing_str_produces_big_l_string_value() {
+        let str = (0..127).map(|_| 'X').collect::<String>();
+        let value = str.as_str();
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 1 + 8 + 1 + 127); // S + L + (size) + 127
+        assert_eq!(out[0], b'S');
+        assert_eq!(out[1], b'L');
+        assert_eq!(out[2..10], 127i64.to_be_bytes());
+        assert_eq!(&out[10..], value.as_bytes());
+    }
+
+    // #[test]
+    // fn serializing_str_of_length_127_produces_small_i_string_value() {
+    //     let str = (0..127).map(|n| 'X').collect::<String>();
+    //     let value = str.as_str();
+    //     let out = to_bytes(&value).unwrap();
+    //
+    //     assert_eq!(out.len(), 1 + 1 + 1 + 127); // S + i + (size) + 127
+    //     assert_eq!(out[0], b'S');
+    //     assert_eq!(out[1], b'i');
+    //     assert_eq!(out[2..3], 127i8.to_be_bytes());
+    //     assert_eq!(&out[3..], value.as_bytes());
+    // }
+    //
+    // #[test]
+    // fn serializing_str_of_length_32767_produces_big_i_string_value() {
+    //     let str = (0..32767).map(|n| 'X').collect::<String>();
+    //     let value = str.as_str();
+    //     let out = to_bytes(&value).unwrap();
+    //
+    //     assert_eq!(out.len(), 1 + 1 + 2 + 32767); // S + i + (size) + 32767
+    //     assert_eq!(out[0], b'S');
+    //     assert_eq!(out[1], b'I');
+    //     assert_eq!(out[2..4], 32767i16.to_be_bytes());
+    //     assert_eq!(&out[4..], value.as_bytes());
+    // }
+
+    #[test]
+    fn serializing_none_produces_big_z_string_value() {
+        let value: Option<i32> = None;
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out, vec![b'Z']);
+    }
+
+    #[test]
+    fn serializing_unit_produces_big_n_string_value() {
+        let value = ();
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out, vec![b'Z']);
+    }
+
+    #[test]
+    fn serializing_vec_of_bytes_produces_array_value() {
+        let value = b"test".to_vec();
+        let out = to_bytes(&value).unwrap();
+
+        let len = (value.len() as i64).to_be_bytes();
+
+        assert_eq!(b"[#L", &out[..3]);
+        assert_eq!(&len, &out[3..11]);
+        assert_eq!(&out[11..], b"UtUeUsUt");
+    }
+
+    #[test]
+    fn serializing_vec_of_strings_produces_array_value() {
+        let value = vec!["one", "two"];
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 37);
+
+        let len = (value.len() as i64).to_be_bytes();
+        let mut span = vec![b'[', b'#', b'L'];
+        span.extend_from_slice(&len);
+        assert_eq!(out[..11], span);
+
+        let len = (value[0].len() as i64).to_be_bytes();
+        let mut span = vec![b'S', b'L'];
+        span.extend_from_slice(&len);
+        span.extend_from_slice(value[0].as_bytes());
+        assert_eq!(out[11..24], span);
+
+        let len = (value[1].len() as i64).to_be_bytes();
+        let mut span = vec![b'S', b'L'];
+        span.extend_from_slice(&len);
+        span.extend_from_slice(value[1].as_bytes());
+        assert_eq!(out[24..37], span);
+    }
+
+    #[test]
+    fn serializing_map_of_strings_to_i32_produces_object_value() {
+        let value = HashMap::from([("key1", 1i32), ("key2", 2i32)]);
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 47);
+
+        let len = (value.len() as u64).to_be_bytes();
+        let mut span = vec![b'{', b'#', b'L'];
+        span.extend_from_slice(&len);
+        assert_eq!(out[..11], span);
+
+        let entries = value.iter().collect::<Vec<_>>();
+
+        // 1st entry
+        let len = (entries[0].0.len() as i64).to_be_bytes();
+        let mut span = vec![b'L'];
+        span.extend_from_slice(&len);
+        span.extend_from_slice(entries[0].0.as_bytes());
+        assert_eq!(out[11..24], span);
+
+        assert_eq!(out[24], b'l');
+        assert_eq!(out[25..29], entries[0].1.to_be_bytes());
+
+        // 2nd entry
+        let len = (entries[1].0.len() as i64).to_be_bytes();
+        let mut span = vec![b'L'];
+        span.extend_from_slice(&len);
+        span.extend_from_slice(entries[1].0.as_bytes());
+        assert_eq!(out[29..42], span);
+
+        assert_eq!(out[42], b'l');
+        assert_eq!(out[43..47], entries[1].1.to_be_bytes());
+    }
+
+    #[test]
+    fn serializing_map_of_not_string_to_any_produces_error() {
+        let value = HashMap::from([(true, "val1"), (false, "val2")]);
+        let result = to_bytes(&value);
+        match result {
+            Err(e) => assert!(matches!(e, Error::InvalidKey { found: "bool" })),
+            _ => panic!("Expected error"),
+        }
+
+        let value = HashMap::from([(0, "val1"), (1, "val2")]);
+        let result = to_bytes(&value);
+        match result {
+            Err(e) => assert!(matches!(e, Error::InvalidKey { found: "i32" })),
+            _ => panic!("Expected error"),
+        }
+
+        let value = HashMap::from([(0u64, "val1")]);
+        let result = to_bytes(&value);
+        match result {
+            Err(e) => {
+                assert!(matches!(e, Error::InvalidKey { found: "u64" }));
+                assert_eq!(e.to_string(), "invalid key: expected string, found u64");
+            }
+            _ => panic!("Expected error"),
+        }
+    }
+
+    #[test]
+    fn serializing_simple_struct_produces_object_value() {
+        let value = SimpleStruct {
+            field1: 1,
+            field2: "val".to_string(),
+        };
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(out.len(), 59);
+
+        let len = 2i64.to_be_bytes();
+        let mut span = vec![b'{', b'#', b'L'];
+        span.extend_from_slice(&len);
+        assert_eq!(out[..11], span);
+
+        // 1st field
+        let len = ("field1".len() as i64).to_be_bytes();
+        let mut span = vec![b'L'];
+        span.extend_from_slice(&len);
+        span.extend_from_slice("field1".as_bytes());
+        assert_eq!(out[11..26], span);
+
+        assert_eq!(out[26], b'l');
+        assert_eq!(out[27..31], 1i32.to_be_bytes());
+
+        // 2nd field
+        let len = ("field2".len() as i64).to_be_bytes();
+        let mut span = vec![b'L'];
+        span.extend_from_slice(&len);
+        span.extend_from_slice("field2".as_bytes());
+        assert_eq!(out[31..46], span);
+
+        assert_eq!(out[46], b'S');
+        assert_eq!(out[47], b'L');
+        assert_eq!(out[48..56], 3i64.to_be_bytes());
+        assert_eq!(&out[56..], b"val");
+    }
+
+    #[test]
+    fn serializing_tuple_with_tuples_as_objects_produces_object_value() {
+        let config = SerializerConfig {
+            tuples_as_objects: true,
+            ..Default::default()
+        };
+        let value = (7i32, "seven".to_string());
+        let out = to_bytes_with_config(&value, &config).unwrap();
+
+        let mut span = vec![b'{', b'#', b'L'];
+        span.extend_from_slice(&2i64.to_be_bytes());
+        span.push(b'L');
+        span.extend_from_slice(&1i64.to_be_bytes());
+        span.push(b'0');
+        assert_eq!(out[..21], span);
+
+        let decoded = crate::from_bytes::<'_, (i32, String)>(&out).unwrap();
+        assert_eq!(decoded, value);
+
+        let error = crate::from_bytes::<'_, (i32, i32)>(b"{i\x011i\x01i\x010i\x02}").unwrap_err();
+        assert_eq!(error.to_string(), "expected positional key \"0\", found \"1\"");
+    }
+
+    #[test]
+    fn serializing_through_tee_of_simple_and_stats_formatters_produces_bytes_and_stats() {
+        let value = SimpleStruct {
+            field1: 1,
+            field2: "val".to_string(),
+        };
+
+        let mut out = Vec::new();
+        let mut sink = std::io::sink();
+        let mut stats = StatsFormatter::new(SimpleFormatter::new(&mut sink));
+
+        let formatter = TeeFormatter::new(SimpleFormatter::new(&mut out), &mut stats);
+        let mut serializer = Serializer::new(formatter);
+        value.serialize(&mut serializer).unwrap();
+
+        assert_eq!(out, to_bytes(&value).unwrap());
+        assert_eq!(stats.stats(), &FormatterStats {
+            markers: 8, // { # L L l L S L
+            containers: 1,
+            bytes: 59,
+        });
+    }
+
+    #[test]
+    fn serializing_through_stats_formatter_counts_bytes_of_wrapped_formatter() {
+        let mut out = Vec::new();
+        let mut formatter = StatsFormatter::new(SimpleFormatter::compact(&mut out));
+        "hello".serialize(&mut Serializer::new(&mut formatter)).unwrap();
+
+        assert_eq!(formatter.stats(), &FormatterStats {
+            markers: 2, // S i
+            containers: 0,
+            bytes: 8,
+        });
+        assert_eq!(out, b"Si\x05hello");
+
+        let hashing = HashingFormatter::new(std::collections::hash_map::DefaultHasher::new());
+        let mut formatter = StatsFormatter::new(hashing);
+        "hello".serialize(&mut Serializer::new(&mut formatter)).unwrap();
+        assert_eq!(formatter.stats().bytes, 15); // S L <8 bytes> hello
+    }
+
+    #[test]
+    fn serializing_bytes_produces_strongly_typed_array_value() {
+        let value = ByteSlice(b"test");
+        let out = to_bytes(&value).unwrap();
+
+        assert_eq!(b"[$U#L", &out[..5]);
+        assert_eq!(&4i64.to_be_bytes(), &out[5..13]);
+        assert_eq!(&out[13..], b"test");
+
+        let decoded = crate::from_bytes::<'_, &[u8]>(&out).unwrap();
+        assert_eq!(decoded, b"test");
+    }
+
+    #[test]
+    fn serializing_nested_bytes_produces_borrowable_typed_arrays() {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert("blob", ByteSlice(b"\x00\xffdata"));
+
+        for optimize_containers in [false, true] {
+            let config = SerializerConfig {
+                optimize_containers,
+                ..Default::default()
+            };
+            let out = to_bytes_with_config(&map, &config).unwrap();
+            let start = out.windows(4).position(|w| w == b"[$U#").unwrap();
+            assert_eq!(&out[out.len() - 6..], b"\x00\xffdata");
+            assert!(start > 0);
+
+            let decoded = crate::from_bytes::<'_, HashMap<&str, &[u8]>>(&out).unwrap();
+            assert_eq!(decoded["blob"], b"\x00\xffdata");
+        }
+    }
+
+    #[test]
+    fn serializing_borrowed_cow_produces_same_value_as_owned() {
+        let owned = "text".to_string();
+        let borrowed: Cow<str> = Cow::Borrowed("text");
+        assert_eq!(to_bytes(&borrowed).unwrap(), to_bytes(&owned).unwrap());
+        assert_eq!(to_bytes(&"text").unwrap(), to_bytes(&owned).unwrap());
+
+        let owned = b"bytes".to_vec();
+        let borrowed: Cow<[u8]> = Cow::Borrowed(b"bytes");
+        assert_eq!(to_bytes(&borrowed).unwrap(), to_bytes(&owned).unwrap());
+        assert_eq!(to_bytes(&&owned[..]).unwrap(), to_bytes(&owned).unwrap());
+
+        // written with `serialize_bytes` as a strongly typed array
+        #[serde_with::serde_as]
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Blob<'a> {
+            #[serde_as(as = "serde_with::Bytes")]
+            #[serde(borrow)]
+            data: Cow<'a, [u8]>,
+        }
+
+        let mut expected = b"[$U#L".to_vec();
+        expected.extend_from_slice(&5i64.to_be_bytes());
+        expected.extend_from_slice(b"bytes");
+        for data in [Cow::Borrowed(&b"bytes"[..]), Cow::Owned(b"bytes".to_vec())] {
+            let out = to_bytes(&Blob { data }).unwrap();
+            assert!(out.ends_with(&expected));
+
+            let decoded = crate::from_bytes::<Blob>(&out).unwrap();
+            assert!(matches!(decoded.data, Cow::Borrowed(b"bytes")));
+        }
+    }
+
+    #[test]
+    fn serializing_vec_of_bool_produces_true_false_values() {
+        let mut expected = b"[#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        expected.extend_from_slice(b"TF");
+        assert_eq!(to_bytes(&vec![true, false]).unwrap(), expected);
+
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+
+        let out = to_bytes_with_config(&vec![true, true], &config).unwrap();
+        let mut expected = b"[$T#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        assert_eq!(out, expected);
+        assert_eq!(crate::from_bytes::<Vec<bool>>(&out).unwrap(), vec![true, true]);
+
+        let out = to_bytes_with_config(&vec![true, false], &config).unwrap();
+        assert_eq!(&out[..2], b"[#");
+    }
+
+    #[test]
+    fn serializing_homogeneous_vecs_with_optimize_containers_produces_typed_arrays() {
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+
+        let ints: Vec<i32> = (0..100).collect();
+        let out = to_bytes_with_config(&ints, &config).unwrap();
+        assert_eq!(&out[..4], b"[$l#");
+        assert_eq!(out.len(), 5 + 8 + 100 * 4);
+        assert_eq!(to_bytes(&ints).unwrap().len(), 3 + 8 + 100 * 5);
+        assert_eq!(crate::from_bytes::<Vec<i32>>(&out).unwrap(), ints);
+
+        let floats = vec![0.5f64, -1.0, 1e300];
+        let out = to_bytes_with_config(&floats, &config).unwrap();
+        assert_eq!(&out[..4], b"[$D#");
+        assert_eq!(out.len(), 5 + 8 + 3 * 8);
+        assert_eq!(crate::from_bytes::<Vec<f64>>(&out).unwrap(), floats);
+
+        // mixed widths stay untyped
+        let out = to_bytes_with_config(&vec![crate::Value::I8(1), crate::Value::I32(2)], &config).unwrap();
+        assert_eq!(&out[..3], b"[#L");
+    }
+
+    #[test]
+    fn serializing_vec_of_structs_with_optimize_containers_produces_counted_array_of_objects() {
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+
+        let value = vec![
+            SimpleStruct { field1: 1, field2: "a".to_string() },
+            SimpleStruct { field1: 2, field2: "b".to_string() },
+        ];
+        let out = to_bytes_with_config(&value, &config).unwrap();
+
+        // objects cannot be an element type, so only the count is written
+        let mut expected = b"[#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        assert_eq!(&out[..expected.len()], &expected[..]);
+        assert_eq!(out[expected.len()], b'{');
+
+        let decoded = crate::from_bytes::<Vec<SimpleStruct>>(&out).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn serializing_sets_produces_arrays_that_round_trip() {
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+
+        let value = BTreeSet::from([3i32, 1, 2]);
+        for config in [SerializerConfig::default(), config.clone()] {
+            let out = to_bytes_with_config(&value, &config).unwrap();
+            assert_eq!(out[0], b'[');
+            let decoded = crate::from_bytes::<BTreeSet<i32>>(&out).unwrap();
+            assert_eq!(decoded.into_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+        }
+
+        let value = HashSet::from([10i32, 20, 30]);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..3], b"[$l");
+        assert_eq!(crate::from_bytes::<HashSet<i32>>(&out).unwrap(), value);
+
+        let out = to_bytes(&value).unwrap();
+        assert_eq!(crate::from_bytes::<HashSet<i32>>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn serializing_small_i16_with_compact_ints_produces_i8_value() {
+        let config = SerializerConfig {
+            compact_ints: true,
+            ..Default::default()
+        };
+
+        let out = to_bytes_with_config(&5i16, &config).unwrap();
+        assert_eq!(out, b"i\x05");
+        assert_eq!(crate::from_bytes::<i16>(&out).unwrap(), 5);
+
+        let out = to_bytes_with_config(&-300i16, &config).unwrap();
+        assert_eq!(out, b"I\xfe\xd4");
+        assert_eq!(crate::from_bytes::<i16>(&out).unwrap(), -300);
+
+        assert_eq!(to_bytes(&5i16).unwrap(), b"I\x00\x05");
+    }
+
+    #[test]
+    fn serializing_map_with_sort_keys_produces_entries_ordered_by_key() {
+        let config = SerializerConfig {
+            sort_keys: true,
+            length_policy: Some(smallest_length_marker),
+            ..Default::default()
+        };
+
+        let value = UnsizedMap(&[("b", Some(1)), ("aa", Some(2)), ("a", None)]);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(out, b"{#i\x03i\x01aZi\x02aal\x00\x00\x00\x02i\x01bl\x00\x00\x00\x01");
+    }
+
+    #[test]
+    fn serializing_canonical_produces_identical_output_for_equal_values() {
+        let entries = (0..32).map(|i| (format!("key{}", i), vec![i, -i]));
+
+        let first = to_bytes_canonical(&entries.clone().collect::<HashMap<_, _>>()).unwrap();
+        for _ in 0..8 {
+            let value = entries.clone().rev().collect::<HashMap<_, _>>();
+            assert_eq!(to_bytes_canonical(&value).unwrap(), first);
+        }
+
+        // arrays of small integers are typed and compact
+        let out = to_bytes_canonical(&vec![1i32, 2, 3]).unwrap();
+        assert_eq!(out, b"[$i#i\x03\x01\x02\x03");
+
+        // so are lengths
+        let out = to_bytes_canonical(&HashMap::from([("k", "v")])).unwrap();
+        assert_eq!(out, b"{$S#i\x01i\x01ki\x01v");
+    }
+
+    #[test]
+    fn serializing_struct_with_skipped_field_produces_count_of_written_entries() {
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Optional {
+            a: i8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            b: Option<i8>,
+            c: i8,
+        }
+
+        let value = Optional { a: 1, b: None, c: 3 };
+        let optimized = SerializerConfig { optimize_containers: true, ..Default::default() };
+        for config in [SerializerConfig::default(), optimized] {
+            let out = to_bytes_with_config(&value, &config).unwrap();
+            let count = out.iter().position(|&b| b == b'#').unwrap() + 1;
+            assert_eq!(crate::parse_len(&out[count..]).unwrap().0, 2);
+            assert_eq!(crate::from_bytes::<Optional>(&out).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn serializing_map_with_fewer_entries_than_declared_produces_invalid_length() {
+        struct Lying;
+
+        impl Serialize for Lying {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+                where
+                    S: serde::Serializer,
+            {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("a", &1)?;
+                map.end()
+            }
+        }
+
+        assert!(matches!(to_bytes(&Lying), Err(Error::InvalidLength)));
+
+        // buffered objects write the number of entries they received
+        let config = SerializerConfig { optimize_containers: true, ..Default::default() };
+        let out = to_bytes_with_config(&Lying, &config).unwrap();
+        let decoded = crate::from_bytes::<HashMap<String, i32>>(&out).unwrap();
+        assert_eq!(decoded, HashMap::from([("a".to_string(), 1)]));
+    }
+
+    #[test]
+    fn serializing_struct_with_struct_as_array_produces_array_of_field_values() {
+        let value = SimpleStruct { field1: 7, field2: "ok".to_string() };
+        let de_config = crate::DeserializerConfig { struct_from_array: true, ..Default::default() };
+
+        let config = SerializerConfig {
+            struct_as_array: true,
+            length_policy: Some(smallest_length_marker),
+            ..Default::default()
+        };
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(out, b"[#i\x02l\x00\x00\x00\x07Si\x02ok");
+        assert_eq!(crate::from_bytes_with_config::<SimpleStruct>(&out, &de_config).unwrap(), value);
+
+        let config = SerializerConfig { optimize_containers: true, ..config };
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(out, b"[#i\x02l\x00\x00\x00\x07Si\x02ok");
+
+        // maps keep their keys
+        let map = HashMap::from([("a", 1)]);
+        let out = to_bytes_with_config(&map, &config).unwrap();
+        assert_eq!(out[0], b'{');
+    }
+
+    #[test]
+    fn serializing_ints_with_compact_ints_produces_smallest_markers() {
+        let config = SerializerConfig {
+            compact_ints: true,
+            ..Default::default()
+        };
+
+        assert_eq!(to_bytes_with_config(&42i64, &config).unwrap(), b"i\x2a");
+        assert_eq!(to_bytes_with_config(&-42i32, &config).unwrap(), b"i\xd6");
+        assert_eq!(to_bytes_with_config(&42u64, &config).unwrap(), b"U\x2a");
+        assert_eq!(to_bytes_with_config(&200u32, &config).unwrap(), b"U\xc8");
+        assert_eq!(to_bytes_with_config(&1000u16, &config).unwrap(), b"I\x03\xe8");
+        assert_eq!(to_bytes_with_config(&70_000u64, &config).unwrap(), b"l\0\x01\x11\x70");
+        assert_eq!(&to_bytes_with_config(&u64::MAX, &config).unwrap()[..1], b"H");
+
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Counters {
+            hits: u64,
+            misses: u32,
+            delta: i64,
+        }
+        let value = Counters { hits: 3, misses: 70_000, delta: -1 };
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert!(out.len() < to_bytes(&value).unwrap().len());
+        assert_eq!(crate::from_bytes::<Counters>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn serializing_struct_with_byte_length_policy_produces_u8_lengths() {
+        let config = SerializerConfig {
+            always_count_containers: true,
+            length_policy: Some(byte_length_marker),
+            ..Default::default()
+        };
+
+        let value = SimpleStruct { field1: 1, field2: "xy".to_string() };
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(out, b"{#U\x02U\x06field1l\0\0\0\x01U\x06field2SU\x02xy");
+        assert_eq!(crate::from_bytes::<SimpleStruct>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn serializing_small_byte_vec_with_byte_length_policy_produces_u8_counted_typed_array() {
+        let config = SerializerConfig {
+            optimize_containers: true,
+            length_policy: Some(byte_length_marker),
+            ..Default::default()
+        };
+
+        let value: Vec<u8> = (1..=10).collect();
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..5], b"[$U#U");
+        assert_eq!(out[5], 10);
+        assert_eq!(&out[6..], &value[..]);
+        assert_eq!(crate::from_bytes::<Vec<u8>>(&out).unwrap(), value);
+
+        // serialize_bytes writes the same encoding and can be borrowed back
+        let out_bytes = to_bytes_with_config(&ByteSlice(&value), &config).unwrap();
+        assert_eq!(out_bytes, out);
+
+        let value = vec![7u8; 255];
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..6], b"[$U#U\xff");
+
+        let value = vec![7u8; 256];
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..7], b"[$U#I\x01\x00");
+    }
+
+    #[test]
+    fn serializing_unsized_map_with_optimize_containers_produces_counted_object_value() {
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+
+        let value = UnsizedMap(&[("a", Some(1)), ("b", Some(2))]);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+
+        let mut expected = b"{$l#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        for (key, v) in [(b'a', 1i32), (b'b', 2i32)] {
+            expected.push(b'L');
+            expected.extend_from_slice(&1i64.to_be_bytes());
+            expected.push(key);
+            expected.extend_from_slice(&v.to_be_bytes());
+        }
+        assert_eq!(out, expected);
+
+        let decoded = crate::from_bytes::<HashMap<String, i32>>(&out).unwrap();
+        assert_eq!(decoded, HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]));
+
+        // values of different types keep their markers
+        let value = UnsizedMap(&[("a", Some(1)), ("b", None)]);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..3], b"{#L");
+
+        let decoded = crate::from_bytes::<HashMap<String, Option<i32>>>(&out).unwrap();
+        assert_eq!(decoded, HashMap::from([("a".to_string(), Some(1)), ("b".to_string(), None)]));
+
+        let out = to_bytes_with_config(&UnsizedMap(&[]), &config).unwrap();
+        let decoded = crate::from_bytes::<HashMap<String, i32>>(&out).unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn serializing_unsized_seq_with_always_count_containers_produces_counted_array_value() {
+        let value = UnsizedSeq(&[1, 2]);
+        let out = to_bytes(&value).unwrap();
+        assert_eq!(out[0], b'[');
+        assert_eq!(out[out.len() - 1], b']');
+
+        let config = SerializerConfig {
+            always_count_containers: true,
+            ..Default::default()
+        };
+        let out = to_bytes_with_config(&value, &config).unwrap();
+
+        let mut expected = b"[#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        for v in [1i32, 2] {
+            expected.push(b'l');
+            expected.extend_from_slice(&v.to_be_bytes());
+        }
+        assert_eq!(out, expected);
+        assert_eq!(crate::from_bytes::<Vec<i32>>(&out).unwrap(), vec![1, 2]);
+
+        let value = UnsizedMap(&[("a", Some(1))]);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..3], b"{#L");
+        let decoded = crate::from_bytes::<HashMap<String, i32>>(&out).unwrap();
+        assert_eq!(decoded, HashMap::from([("a".to_string(), 1)]));
+    }
+
+    #[test]
+    fn serializing_with_i32_length_policy_produces_small_l_lengths() {
+        let config = SerializerConfig {
+            length_policy: Some(|_| Marker::I32),
+            ..Default::default()
+        };
+        let value = vec!["ab"];
+        let out = to_bytes_with_config(&value, &config).unwrap();
+
+        let mut expected = b"[#l".to_vec();
+        expected.extend_from_slice(&1i32.to_be_bytes());
+        expected.extend_from_slice(b"Sl");
+        expected.extend_from_slice(&2i32.to_be_bytes());
+        expected.extend_from_slice(b"ab");
+        assert_eq!(out, expected);
+        assert_eq!(crate::from_bytes::<Vec<String>>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn serializing_with_smallest_length_policy_produces_smallest_lengths() {
+        let config = SerializerConfig {
+            length_policy: Some(smallest_length_marker),
+            ..Default::default()
+        };
+
+        let out = to_bytes_with_config(&"ab", &config).unwrap();
+        assert_eq!(out, b"Si\x02ab");
+
+        let value = "x".repeat(300);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..4], b"SI\x01\x2c");
+        assert_eq!(crate::from_bytes::<String>(&out).unwrap(), value);
+
+        let config = SerializerConfig {
+            length_policy: Some(|_| Marker::U8),
+            ..Default::default()
+        };
+        let value = "x".repeat(200);
+        let out = to_bytes_with_config(&value, &config).unwrap();
+        assert_eq!(&out[..3], b"SU\xc8");
+        assert_eq!(crate::from_bytes::<String>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn serializing_with_too_narrow_length_policy_produces_error() {
+        let config = SerializerConfig {
+            length_policy: Some(|_| Marker::I8),
+            ..Default::default()
+        };
+        let value = "x".repeat(200);
+        let result = to_bytes_with_config(&value, &config);
+        assert!(matches!(result, Err(Error::InvalidLength)));
+    }
+
+    #[test]
+    fn serializing_failing_nested_field_with_track_path_produces_error_with_path() {
+        struct Failing;
+
+        impl Serialize for Failing {
+            fn serialize<S>(&self, _serializer: S) -> std::result::Result<S::Ok, S::Error>
+                where
+                    S: serde::Serializer,
+            {
+                Err(serde::ser::Error::custom("custom error"))
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Item {
+            id: i32,
+            name: Failing,
+        }
+
+        #[derive(Serialize)]
+        struct Order {
+            items: Vec<Item>,
+        }
+
+        let value = Order {
+            items: vec![Item { id: 1, name: Failing }],
+        };
+
+        let result = to_bytes(&value);
+        assert_eq!(result.unwrap_err().to_string(), "custom error");
+
+        for optimize_containers in [false, true] {
+            let config = SerializerConfig {
+                track_path: true,
+                optimize_containers,
+                ..Default::default()
+            };
+            let result = to_bytes_with_config(&value, &config);
+            assert_eq!(result.unwrap_err().to_string(), "custom error at .items[0].name");
+        }
     }
 
     #[test]
-    fn serializing_i32_produces_5_byte_small_l_value() {
-        let value = 2147483647i32;
-        let out = to_bytes(&value).unwrap();
-
-        assert_eq!(out.len(), 5);
-        assert_eq!(out[0], b'l');
-        assert_eq!(out[1..], value.to_be_bytes());
+    fn serializing_unit_with_unit_as_noop_produces_noop() {
+        let config = SerializerConfig {
+            unit_as_noop: true,
+            ..Default::default()
+        };
+        assert_eq!(to_bytes_with_config(&(), &config).unwrap(), b"N");
+        assert_eq!(to_bytes(&()).unwrap(), b"Z");
+        assert_eq!(to_bytes_with_config(&None::<()>, &config).unwrap(), b"Z");
+        assert_eq!(crate::from_bytes::<()>(b"N").unwrap(), ());
     }
 
     #[test]
-    fn serializing_i64_produces_9_byte_big_l_value() {
-        let value = 9_223_372_036_854_775_807i64;
-        let out = to_bytes(&value).unwrap();
+    fn serializing_f32_with_always_f64_produces_f64() {
+        let config = SerializerConfig {
+            always_f64: true,
+            ..Default::default()
+        };
 
-        assert_eq!(out.len(), 9);
-        assert_eq!(out[0], b'L');
-        assert_eq!(out[1..], value.to_be_bytes());
+        let mut expected = vec![b'D'];
+        expected.extend_from_slice(&1.5f64.to_be_bytes());
+        assert_eq!(to_bytes_with_config(&1.5f32, &config).unwrap(), expected);
+        assert_eq!(to_bytes(&1.5f32).unwrap()[0], b'd');
+
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..config
+        };
+        let out = to_bytes_with_config(&vec![0.25f32, 2.0], &config).unwrap();
+        assert_eq!(&out[..3], b"[$D");
+        assert_eq!(crate::from_bytes::<Vec<f64>>(&out).unwrap(), vec![0.25, 2.0]);
     }
 
     #[test]
-    fn serializing_f32_produces_5_byte_small_d_value() {
-        let value = 3.14f32;
-        let out = to_bytes(&value).unwrap();
+    fn serializing_f32_fields_with_always_f64_round_trips() {
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Point {
+            x: f32,
+            y: f32,
+        }
 
-        assert_eq!(out.len(), 5);
-        assert_eq!(out[0], b'd');
-        assert_eq!(out[1..], value.to_be_bytes());
+        let config = SerializerConfig {
+            always_f64: true,
+            ..Default::default()
+        };
+        let point = Point { x: 0.1, y: -2.5 };
+        let out = to_bytes_with_config(&point, &config).unwrap();
+        assert!(!out.contains(&b'd'));
+        assert_eq!(crate::from_bytes::<Point>(&out).unwrap(), point);
     }
 
     #[test]
-    fn serializing_f64_produces_9_byte_big_d_value() {
-        let value = 3.14f64;
-        let out = to_bytes(&value).unwrap();
+    fn serializing_f64_with_compact_floats_produces_f32_when_exact() {
+        let config = SerializerConfig {
+            compact_floats: true,
+            ..Default::default()
+        };
 
-        assert_eq!(out.len(), 9);
-        assert_eq!(out[0], b'D');
-        assert_eq!(out[1..], value.to_be_bytes());
+        let mut expected = vec![b'd'];
+        expected.extend_from_slice(&1024.0f32.to_be_bytes());
+        assert_eq!(to_bytes_with_config(&1024.0f64, &config).unwrap(), expected);
+        assert_eq!(to_bytes_with_config(&0.1f64, &config).unwrap()[0], b'D');
+        assert_eq!(to_bytes_with_config(&f64::NEG_INFINITY, &config).unwrap()[0], b'd');
+
+        let values = vec![0.5, 16777217.0, -0.0, 1e300];
+        let out = to_bytes_with_config(&values, &config).unwrap();
+        let back: Vec<f64> = crate::from_bytes(&out).unwrap();
+        assert_eq!(back, values);
+        assert!(back[2].is_sign_negative());
     }
 
     #[test]
-    fn serializing_str_produces_big_l_string_value() {
-        let str = (0..127).map(|_| 'X').collect::<String>();
-        let value = str.as_str();
-        let out = to_bytes(&value).unwrap();
+    fn serializing_display_value_produces_string() {
+        use std::collections::BTreeMap;
+        use std::fmt;
 
-        assert_eq!(out.len(), 1 + 8 + 1 + 127); // S + L + (size) + 127
-        assert_eq!(out[0], b'S');
-        assert_eq!(out[1], b'L');
-        assert_eq!(out[2..10], 127i64.to_be_bytes());
-        assert_eq!(&out[10..], value.as_bytes());
+        struct Version(u8, u8);
+
+        impl fmt::Display for Version {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}.{}", self.0, self.1)
+            }
+        }
+
+        impl Serialize for Version {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        let versions = vec![Version(1, 2), Version(10, 0)];
+        assert_eq!(to_bytes(&versions).unwrap(), to_bytes(&vec!["1.2", "10.0"]).unwrap());
+
+        let mut writer = crate::ObjectWriter::new(Vec::new()).unwrap();
+        writer.entry(&Version(3, 4), &true).unwrap();
+        let map: BTreeMap<String, bool> = crate::from_bytes(&writer.finish().unwrap()).unwrap();
+        assert!(map["3.4"]);
     }
 
-    // #[test]
-    // fn serializing_str_of_length_127_produces_small_i_string_value() {
-    //     let str = (0..127).map(|n| 'X').collect::<String>();
-    //     let value = str.as_str();
-    //     let out = to_bytes(&value).unwrap();
-    //
-    //     assert_eq!(out.len(), 1 + 1 + 1 + 127); // S + i + (size) + 127
-    //     assert_eq!(out[0], b'S');
-    //     assert_eq!(out[1], b'i');
-    //     assert_eq!(out[2..3], 127i8.to_be_bytes());
-    //     assert_eq!(&out[3..], value.as_bytes());
-    // }
-    //
-    // #[test]
-    // fn serializing_str_of_length_32767_produces_big_i_string_value() {
-    //     let str = (0..32767).map(|n| 'X').collect::<String>();
-    //     let value = str.as_str();
-    //     let out = to_bytes(&value).unwrap();
-    //
-    //     assert_eq!(out.len(), 1 + 1 + 2 + 32767); // S + i + (size) + 32767
-    //     assert_eq!(out[0], b'S');
-    //     assert_eq!(out[1], b'I');
-    //     assert_eq!(out[2..4], 32767i16.to_be_bytes());
-    //     assert_eq!(&out[4..], value.as_bytes());
-    // }
+    // records the calls made to it, taking at most `max` bytes per call
+    struct CallLog {
+        out: Vec<u8>,
+        calls: usize,
+        max: usize,
+    }
+
+    impl CallLog {
+        fn new(max: usize) -> CallLog {
+            CallLog { out: Vec::new(), calls: 0, max }
+        }
+    }
+
+    impl Write for CallLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> std::io::Result<usize> {
+            self.calls += 1;
+            let mut left = self.max;
+            for buf in bufs {
+                let n = buf.len().min(left);
+                self.out.extend_from_slice(&buf[..n]);
+                left -= n;
+            }
+            Ok(self.max - left)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Blob(Vec<u8>);
+
+    impl Serialize for Blob {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
 
     #[test]
-    fn serializing_none_produces_big_z_string_value() {
-        let value: Option<i32> = None;
-        let out = to_bytes(&value).unwrap();
+    fn serializing_to_writer_writes_large_payload_with_header_at_once() {
+        let value = ("attachment", Blob(vec![7; 1 << 20]));
+        let mut log = CallLog::new(usize::MAX);
+        to_writer(&mut log, &value).unwrap();
+        assert_eq!(log.out, to_bytes(&value).unwrap());
+        assert_eq!(log.calls, 1);
+    }
 
-        assert_eq!(out, vec![b'Z']);
+    #[test]
+    fn serializing_to_writer_batches_small_writes() {
+        let value: Vec<u32> = (0..10_000).collect();
+        let mut log = CallLog::new(usize::MAX);
+        to_writer(&mut log, &value).unwrap();
+        assert_eq!(log.out, to_bytes(&value).unwrap());
+        assert!(log.calls <= log.out.len() / BATCH_LEN + 1);
     }
 
     #[test]
-    fn serializing_unit_produces_big_n_string_value() {
-        let value = ();
-        let out = to_bytes(&value).unwrap();
+    fn serializing_to_writer_handles_partial_writes() {
+        let value = (vec!["x".repeat(BATCH_LEN * 2)], Blob((0..=255).cycle().take(BATCH_LEN * 3).collect()));
+        let mut log = CallLog::new(7);
+        to_writer(&mut log, &value).unwrap();
+        assert_eq!(log.out, to_bytes(&value).unwrap());
+    }
 
-        assert_eq!(out, vec![b'Z']);
+    #[test]
+    fn serializing_failing_display_value_produces_error() {
+        use std::fmt;
+
+        struct Broken;
+
+        impl fmt::Display for Broken {
+            fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+                Err(fmt::Error)
+            }
+        }
+
+        impl Serialize for Broken {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        assert!(matches!(to_bytes(&Broken), Err(Error::Custom(_))));
     }
 
     #[test]
-    fn serializing_vec_of_bytes_produces_array_value() {
-        let value = b"test".to_vec();
-        let out = to_bytes(&value).unwrap();
+    fn serializing_i128_keys_with_coerce_int_keys_round_trips() {
+        use std::collections::BTreeMap;
+        use crate::{from_bytes_with_config, DeserializerConfig};
+
+        let map = BTreeMap::from([
+            (i128::MIN, "min".to_string()),
+            (-1, "minus one".to_string()),
+            (i128::MAX, "max".to_string()),
+        ]);
+        assert!(matches!(to_bytes(&map), Err(Error::InvalidKey { found: "i128" })));
+
+        let config = SerializerConfig {
+            coerce_int_keys: true,
+            ..Default::default()
+        };
+        let out = to_bytes_with_config(&map, &config).unwrap();
+        let keys = crate::from_bytes::<BTreeMap<String, String>>(&out).unwrap();
+        assert!(keys.contains_key("-170141183460469231731687303715884105728"));
 
-        let len = (value.len() as i64).to_be_bytes();
+        let config = DeserializerConfig {
+            coerce_int_keys: true,
+            ..Default::default()
+        };
+        let value: BTreeMap<i128, String> = from_bytes_with_config(&out, &config).unwrap();
+        assert_eq!(value, map);
 
-        assert_eq!(b"[#L", &out[..3]);
-        assert_eq!(&len, &out[3..11]);
-        assert_eq!(&out[11..], b"UtUeUsUt");
+        let value: Result<BTreeMap<u8, String>> = from_bytes_with_config(&out, &config);
+        assert!(value.is_err());
     }
 
     #[test]
-    fn serializing_vec_of_strings_produces_array_value() {
-        let value = vec!["one", "two"];
-        let out = to_bytes(&value).unwrap();
+    fn hashing_equal_values_produces_equal_content_ids() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashMap;
+
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for i in 0..32 {
+            a.insert(format!("key{}", i), i);
+            b.insert(format!("key{}", 31 - i), 31 - i);
+        }
 
-        assert_eq!(out.len(), 37);
+        let id = content_id::<_, DefaultHasher>(&a).unwrap();
+        assert_eq!(id, content_id::<_, DefaultHasher>(&b).unwrap());
 
-        let len = (value.len() as i64).to_be_bytes();
-        let mut span = vec![b'[', b'#', b'L'];
-        span.extend_from_slice(&len);
-        assert_eq!(out[..11], span);
+        // same as hashing the canonical bytes in one go
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&to_bytes_canonical(&a).unwrap());
+        assert_eq!(id, hasher.finish());
 
-        let len = (value[0].len() as i64).to_be_bytes();
-        let mut span = vec![b'S', b'L'];
-        span.extend_from_slice(&len);
-        span.extend_from_slice(value[0].as_bytes());
-        assert_eq!(out[11..24], span);
+        b.insert("key0".to_string(), 100);
+        assert_ne!(id, content_id::<_, DefaultHasher>(&b).unwrap());
+        assert_ne!(content_id::<_, DefaultHasher>(&1u8).unwrap(), content_id::<_, DefaultHasher>(&2u8).unwrap());
+    }
 
-        let len = (value[1].len() as i64).to_be_bytes();
-        let mut span = vec![b'S', b'L'];
-        span.extend_from_slice(&len);
-        span.extend_from_slice(value[1].as_bytes());
-        assert_eq!(out[24..37], span);
+    #[test]
+    fn serializing_with_builder_config_produces_profile_output() {
+        let builder = SerializerBuilder::new()
+            .length_policy(smallest_length_marker)
+            .always_count_containers(true)
+            .u64_policy(U64Policy::Integer);
+
+        let out = builder.to_bytes(&vec![5u64, u64::MAX]).unwrap();
+        let mut expected = b"[#i\x02L".to_vec();
+        expected.extend_from_slice(&5i64.to_be_bytes());
+        expected.extend_from_slice(b"Hi\x1418446744073709551615");
+        assert_eq!(out, expected);
+
+        let config = builder.clone().build();
+        assert_eq!(to_bytes_with_config(&vec![5u64, u64::MAX], &config).unwrap(), expected);
+
+        // default policy keeps every u64 a high-precision number
+        assert_eq!(&to_bytes(&5u64).unwrap()[..], b"HL\0\0\0\0\0\0\0\x015");
     }
 
     #[test]
-    fn serializing_map_of_strings_to_i32_produces_object_value() {
-        let value = HashMap::from([("key1", 1i32), ("key2", 2i32)]);
-        let out = to_bytes(&value).unwrap();
+    fn serializing_with_compact_formatter_produces_minimal_lengths() {
+        let mut out = Vec::new();
+        let mut serializer = Serializer::new(SimpleFormatter::compact(&mut out));
+        vec!["abc"].serialize(&mut serializer).unwrap();
+        assert_eq!(out, b"[#i\x01Si\x03abc");
 
-        assert_eq!(out.len(), 47);
+        let mut out = Vec::new();
+        let mut serializer = Serializer::new(SimpleFormatter::compact(&mut out));
+        vec![0u8; 200].serialize(&mut serializer).unwrap();
+        assert_eq!(&out[..5], b"[#I\x00\xc8");
+        assert_eq!(crate::from_bytes::<Vec<u8>>(&out).unwrap(), vec![0; 200]);
+    }
 
-        let len = (value.len() as u64).to_be_bytes();
-        let mut span = vec![b'{', b'#', b'L'];
-        span.extend_from_slice(&len);
-        assert_eq!(out[..11], span);
+    #[test]
+    fn serializing_unsigned_ints_produces_smallest_markers() {
+        assert_eq!(to_bytes(&200u16).unwrap(), b"U\xc8");
+        assert_eq!(to_bytes(&300u16).unwrap(), b"I\x01\x2c");
+        assert_eq!(to_bytes(&40_000u16).unwrap(), b"l\0\0\x9c\x40");
+        assert_eq!(to_bytes(&7u32).unwrap(), b"U\x07");
+        assert_eq!(to_bytes(&70_000u32).unwrap(), b"l\0\x01\x11\x70");
+        assert_eq!(to_bytes(&u32::MAX).unwrap(), b"L\0\0\0\0\xff\xff\xff\xff");
+
+        for v in [0u16, 255, 256, 32767, 32768, u16::MAX] {
+            assert_eq!(crate::from_bytes::<u16>(&to_bytes(&v).unwrap()).unwrap(), v);
+        }
+        for v in [0u32, 255, 32768, i32::MAX as u32, u32::MAX] {
+            assert_eq!(crate::from_bytes::<u32>(&to_bytes(&v).unwrap()).unwrap(), v);
+        }
 
-        let entries = value.iter().collect::<Vec<_>>();
+        let mut out = Vec::new();
+        let mut formatter = StatsFormatter::new(SimpleFormatter::new(&mut out));
+        vec![1u16, 40_000].serialize(&mut Serializer::new(&mut formatter)).unwrap();
+        let bytes = formatter.stats().bytes;
+        assert_eq!(bytes, out.len());
+    }
 
-        // 1st entry
-        let len = (entries[0].0.len() as i64).to_be_bytes();
-        let mut span = vec![b'L'];
-        span.extend_from_slice(&len);
-        span.extend_from_slice(entries[0].0.as_bytes());
-        assert_eq!(out[11..24], span);
+    #[test]
+    fn serializing_ascii_char_produces_char_marker() {
+        assert_eq!(to_bytes(&'a').unwrap(), b"Ca");
+        assert_eq!(crate::from_bytes::<char>(b"Ca").unwrap(), 'a');
 
-        assert_eq!(out[24], b'l');
-        assert_eq!(out[25..29], entries[0].1.to_be_bytes());
+        // non-ASCII chars do not fit into one byte
+        let out = to_bytes(&'é').unwrap();
+        assert_eq!(out, "SL\0\0\0\0\0\0\0\x02é".as_bytes());
+        assert_eq!(crate::from_bytes::<char>(&out).unwrap(), 'é');
 
-        // 2nd entry
-        let len = (entries[1].0.len() as i64).to_be_bytes();
-        let mut span = vec![b'L'];
-        span.extend_from_slice(&len);
-        span.extend_from_slice(entries[1].0.as_bytes());
-        assert_eq!(out[29..42], span);
+        // keys stay strings
+        let map = HashMap::from([('k', 1u8)]);
+        let out = to_bytes(&map).unwrap();
+        assert_eq!(&out[out.len() - 12..], b"L\0\0\0\0\0\0\0\x01kU\x01");
+        assert_eq!(crate::from_bytes::<HashMap<char, u8>>(&out).unwrap(), map);
+    }
 
-        assert_eq!(out[42], b'l');
-        assert_eq!(out[43..47], entries[1].1.to_be_bytes());
+    #[test]
+    fn serializing_none_fields_with_skip_none_fields_produces_no_entries() {
+        #[derive(Debug, Default, PartialEq, Serialize, serde::Deserialize)]
+        struct Sparse {
+            id: u8,
+            #[serde(default)]
+            name: Option<String>,
+            #[serde(default)]
+            tags: Option<Vec<u8>>,
+        }
+
+        let value = Sparse { id: 1, ..Default::default() };
+        assert_eq!(&to_bytes(&value).unwrap()[..3], b"{#L");
+
+        for optimize_containers in [false, true] {
+            let config = SerializerConfig {
+                skip_none_fields: true,
+                optimize_containers,
+                ..Default::default()
+            };
+
+            let keeping = SerializerConfig {
+                skip_none_fields: false,
+                ..config.clone()
+            };
+
+            let out = to_bytes_with_config(&value, &config).unwrap();
+            let kept = to_bytes_with_config(&value, &keeping).unwrap();
+            if optimize_containers {
+                // the remaining entry makes the object typed
+                assert_eq!(&out[..4], b"{$U#");
+            } else {
+                assert_eq!(out.len(), kept.len() - 2 * (1 + 8 + 4 + 1));
+            }
+            assert_eq!(crate::from_bytes::<Sparse>(&out).unwrap(), value);
+
+            let value = Sparse { id: 2, name: Some("n".to_string()), tags: None };
+            let out = to_bytes_with_config(&value, &config).unwrap();
+            let kept = to_bytes_with_config(&value, &keeping).unwrap();
+            assert_eq!(out.len(), kept.len() - (1 + 8 + 4 + 1));
+            assert_eq!(crate::from_bytes::<Sparse>(&out).unwrap(), value);
+        }
     }
 
     #[test]
-    fn serializing_map_of_not_string_to_any_produces_error() {
-        let value = HashMap::from([(true, "val1"), (false, "val2")]);
-        let result = to_bytes(&value);
-        match result {
-            Err(e) => assert!(matches!(e, Error::InvalidKey)),
-            _ => panic!("Expected error"),
+    fn serializing_tuples_produces_arrays_of_elements() {
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Point(i8, i8);
+
+        let out = to_bytes(&(1i8, "a", true)).unwrap();
+        let mut expected = b"[#L".to_vec();
+        expected.extend_from_slice(&3i64.to_be_bytes());
+        expected.extend_from_slice(b"i\x01SL\0\0\0\0\0\0\0\x01aT");
+        assert_eq!(out, expected);
+        assert_eq!(crate::from_bytes::<(i8, String, bool)>(&out).unwrap(), (1, "a".to_string(), true));
+
+        for config in [SerializerConfig::default(), SerializerConfig::canonical()] {
+            let value = vec![Point(1, -1), Point(2, -2)];
+            let out = to_bytes_with_config(&value, &config).unwrap();
+            assert_eq!(crate::from_bytes::<Vec<Point>>(&out).unwrap(), value);
+
+            let value = ((1u8, 2u8), [3i16, 4, 5]);
+            let out = to_bytes_with_config(&value, &config).unwrap();
+            assert_eq!(crate::from_bytes::<((u8, u8), [i16; 3])>(&out).unwrap(), value);
         }
 
-        let value = HashMap::from([(0, "val1"), (1, "val2")]);
-        let result = to_bytes(&value);
-        match result {
-            Err(e) => assert!(matches!(e, Error::InvalidKey)),
-            _ => panic!("Expected error"),
+        let out = to_bytes_with_config(&Point(3, 4), &SerializerConfig::canonical()).unwrap();
+        assert_eq!(out, b"[$i#i\x02\x03\x04");
+    }
+
+    #[test]
+    fn serializing_into_owned_writer_gives_writer_back() {
+        let mut ser = Serializer::new(SimpleFormatter::new(std::io::Cursor::new(Vec::new())));
+        7u8.serialize(&mut ser).unwrap();
+        assert_eq!(ser.get_ref().position(), 2);
+        ser.get_mut().flush().unwrap();
+        'a'.serialize(&mut ser).unwrap();
+        assert_eq!(ser.into_inner().into_inner(), b"U\x07C\x61");
+    }
+
+    #[test]
+    fn serializing_into_existing_buffer_appends() {
+        let mut bytes = b"Z".to_vec();
+        to_bytes_in(&1u8, &mut bytes).unwrap();
+        to_bytes_in(&vec![true], &mut bytes).unwrap();
+        let mut expected = b"ZU\x01[#L".to_vec();
+        expected.extend_from_slice(&1i64.to_be_bytes());
+        expected.push(b'T');
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn serializing_after_reset_reuses_serializer() {
+        let config = SerializerConfig {
+            track_path: true,
+            ..Default::default()
+        };
+        struct Failing;
+        impl Serialize for Failing {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> std::result::Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("failing"))
+            }
         }
+
+        let mut ser = Serializer::with_config(SimpleFormatter::new(Vec::new()), config);
+        assert!(vec![Failing].serialize(&mut ser).is_err());
+        assert!(!ser.path.is_empty());
+
+        ser.reset();
+        ser.get_mut().clear();
+        assert!(ser.path.is_empty());
+        7u8.serialize(&mut ser).unwrap();
+        assert_eq!(ser.into_inner(), b"U\x07");
     }
 
     #[test]
-    fn serializing_simple_struct_produces_object_value() {
-        let value = SimpleStruct {
-            field1: 1,
-            field2: "val".to_string(),
+    fn serializing_unsized_array_with_heartbeat_produces_noops() {
+        struct Unsized(Vec<u8>);
+        impl Serialize for Unsized {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().filter(|_| true))
+            }
+        }
+
+        let config = SerializerConfig {
+            noop_heartbeat: Some(|| true),
+            ..Default::default()
         };
-        let out = to_bytes(&value).unwrap();
+        let out = to_bytes_with_config(&Unsized(vec![1, 2, 3]), &config).unwrap();
+        assert_eq!(out, b"[U\x01NU\x02NU\x03]");
+        assert_eq!(crate::from_bytes::<Vec<u8>>(&out).unwrap(), vec![1, 2, 3]);
 
-        assert_eq!(out.len(), 59);
+        // counted arrays are left alone
+        let out = to_bytes_with_config(&vec![1u8, 2], &config).unwrap();
+        assert!(!out.contains(&b'N'));
+    }
 
-        let len = 2i64.to_be_bytes();
-        let mut span = vec![b'{', b'#', b'L'];
-        span.extend_from_slice(&len);
-        assert_eq!(out[..11], span);
+    #[test]
+    fn serializing_noop_between_values_produces_readable_stream() {
+        let mut ser = Serializer::new(SimpleFormatter::new(Vec::new()));
+        1u8.serialize(&mut ser).unwrap();
+        ser.write_noop().unwrap();
+        ser.write_noop().unwrap();
+        2u8.serialize(&mut ser).unwrap();
+        let out = ser.into_inner();
+        assert_eq!(out, b"U\x01NNU\x02");
 
-        // 1st field
-        let len = ("field1".len() as i64).to_be_bytes();
-        let mut span = vec![b'L'];
-        span.extend_from_slice(&len);
-        span.extend_from_slice("field1".as_bytes());
-        assert_eq!(out[11..26], span);
+        let values: Vec<u8> = crate::Deserializer::new(&out).into_iter().collect::<Result<_>>().unwrap();
+        assert_eq!(values, vec![1, 2]);
+    }
 
-        assert_eq!(out[26], b'l');
-        assert_eq!(out[27..31], 1i32.to_be_bytes());
+    #[test]
+    fn serializing_past_max_depth_produces_error() {
+        use crate::value::Value;
 
-        // 2nd field
-        let len = ("field2".len() as i64).to_be_bytes();
-        let mut span = vec![b'L'];
-        span.extend_from_slice(&len);
-        span.extend_from_slice("field2".as_bytes());
-        assert_eq!(out[31..46], span);
+        fn nested(depth: usize) -> Value {
+            (0..depth).fold(Value::I8(1), |inner, _| Value::Array(vec![inner]))
+        }
 
-        assert_eq!(out[46], b'S');
-        assert_eq!(out[47], b'L');
-        assert_eq!(out[48..56], 3i64.to_be_bytes());
-        assert_eq!(&out[56..], b"val");
+        for optimize_containers in [false, true] {
+            let config = SerializerConfig {
+                max_depth: Some(4),
+                optimize_containers,
+                ..Default::default()
+            };
+            assert!(to_bytes_with_config(&nested(4), &config).is_ok());
+            assert!(matches!(
+                to_bytes_with_config(&nested(5), &config),
+                Err(Error::DepthLimitExceeded { max_depth: 4 })
+            ));
+            // siblings do not add up
+            assert!(to_bytes_with_config(&vec![nested(3), nested(3)], &config).is_ok());
+        }
+        assert!(to_bytes(&nested(64)).is_ok());
+    }
+
+    #[test]
+    fn sizing_value_matches_encoded_length() {
+        let mut map = HashMap::new();
+        map.insert("key".to_string(), vec![Some(1.5f32), None]);
+        let value = (SimpleStruct { field1: 7, field2: "text".to_string() }, map, 'x', 300u16);
+
+        for config in [SerializerConfig::default(), SerializerConfig::canonical()] {
+            let size = serialized_size_with_config(&value, &config).unwrap();
+            assert_eq!(size, to_bytes_with_config(&value, &config).unwrap().len());
+        }
+        assert_eq!(serialized_size(&1u8).unwrap(), 2);
+    }
+
+    #[test]
+    fn serializing_primitive_keys_with_coerce_int_keys_round_trips() {
+        use std::collections::BTreeMap;
+        use crate::{from_bytes_with_config, DeserializerConfig};
+
+        let ser_config = SerializerConfig {
+            coerce_int_keys: true,
+            ..Default::default()
+        };
+        let de_config = DeserializerConfig {
+            coerce_int_keys: true,
+            ..Default::default()
+        };
+
+        let map = HashMap::from([(u64::MAX, 1u8), (0, 2)]);
+        assert!(matches!(to_bytes(&map), Err(Error::InvalidKey { found: "u64" })));
+        let out = to_bytes_with_config(&map, &ser_config).unwrap();
+        let value: HashMap<u64, u8> = from_bytes_with_config(&out, &de_config).unwrap();
+        assert_eq!(value, map);
+
+        let map = BTreeMap::from([(false, 1u8), (true, 2)]);
+        let out = to_bytes_with_config(&map, &ser_config).unwrap();
+        let keys = crate::from_bytes::<BTreeMap<String, u8>>(&out).unwrap();
+        assert_eq!(keys.keys().collect::<Vec<_>>(), ["false", "true"]);
+        let value: BTreeMap<bool, u8> = from_bytes_with_config(&out, &de_config).unwrap();
+        assert_eq!(value, map);
+
+        struct FloatKeys;
+        impl Serialize for FloatKeys {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_map([(1.5f64, 1u8), (-0.25, 2)])
+            }
+        }
+        let out = to_bytes_with_config(&FloatKeys, &ser_config).unwrap();
+        let keys = crate::from_bytes::<BTreeMap<String, u8>>(&out).unwrap();
+        assert_eq!(keys, BTreeMap::from([("1.5".to_string(), 1), ("-0.25".to_string(), 2)]));
+    }
+
+    #[test]
+    fn serializing_uniform_struct_produces_typed_object() {
+        #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+        struct Point {
+            x: i8,
+            y: i8,
+        }
+
+        let out = to_bytes(&Uniform(Point { x: 1, y: -1 })).unwrap();
+        let mut expected = b"{$i#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        expected.extend_from_slice(b"L\0\0\0\0\0\0\0\x01x\x01L\0\0\0\0\0\0\0\x01y\xff");
+        assert_eq!(out, expected);
+        assert_eq!(crate::from_bytes::<Uniform<Point>>(&out).unwrap(), Uniform(Point { x: 1, y: -1 }));
+
+        // mixed values and nested containers keep the plain form
+        let out = to_bytes(&Uniform(SimpleStruct { field1: 1, field2: "a".to_string() })).unwrap();
+        assert_eq!(&out[..3], b"{#L");
+        let out = to_bytes(&vec![Uniform(vec![1u8, 2]), Uniform(vec![3])]).unwrap();
+        assert_eq!(&out[..3], b"[#L");
+        assert_eq!(&out[11..14], b"[$U");
+        assert_eq!(crate::from_bytes::<Vec<Vec<u8>>>(&out).unwrap(), vec![vec![1, 2], vec![3]]);
+
+        let map = std::collections::BTreeMap::from([("a", 1.5f64), ("b", 2.5)]);
+        let out = to_bytes_with_config(&Uniform(&map), &SerializerConfig::canonical()).unwrap();
+        assert_eq!(&out[..6], b"{$D#i\x02");
     }
 }
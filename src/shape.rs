@@ -0,0 +1,231 @@
+use crate::de::{is_empty_payload, Deserializer};
+use crate::value::Marker;
+use crate::Result;
+
+/// Expected structure of a document, checked by [`matches_shape`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// Any single value.
+    Any,
+    Null,
+    Bool,
+    /// Any integer marker (`i`, `U`, `I`, `l`, `L`).
+    Int,
+    /// `d` or `D`.
+    Float,
+    /// A string or a char.
+    Str,
+    /// The inner shape, or null.
+    Nullable(Box<Shape>),
+    /// An array whose elements all match the inner shape.
+    Array(Box<Shape>),
+    /// An object containing at least the listed fields; other fields are skipped.
+    Object(Vec<(String, Shape)>),
+}
+
+/// Checks the first value in `bytes` against `shape` without deserializing it.
+///
+/// Returns `Ok(false)` as soon as the document diverges from the shape and an
+/// error only when the input itself is malformed.
+pub fn matches_shape(bytes: &[u8], shape: &Shape) -> Result<bool> {
+    let mut de = Deserializer::new(bytes);
+    let marker = de.read_marker()?;
+    Matcher {
+        de,
+        skipped: Vec::new(),
+    }
+    .matches(shape, marker)
+}
+
+struct Matcher<'de> {
+    de: Deserializer<'de>,
+    skipped: Vec<u8>,
+}
+
+impl<'de> Matcher<'de> {
+    fn matches(&mut self, shape: &Shape, marker: Marker) -> Result<bool> {
+        match (shape, marker) {
+            (Shape::Nullable(_), Marker::Null) => Ok(true),
+            (Shape::Nullable(inner), _) => self.matches(inner, marker),
+            (Shape::Array(elem), Marker::ArrayStart) => self.matches_array(elem),
+            (Shape::Object(fields), Marker::ObjectStart) => self.matches_object(fields),
+            (Shape::Any, _) => self.skip(marker),
+            (Shape::Null, Marker::Null) | (Shape::Bool, Marker::True | Marker::False) => Ok(true),
+            (Shape::Int, m) if m.is_integer() => self.skip(m),
+            (Shape::Float, m) if m.is_float() => self.skip(m),
+            (Shape::Str, Marker::String | Marker::Char) => self.skip(marker),
+            _ => Ok(false),
+        }
+    }
+
+    fn matches_array(&mut self, elem: &Shape) -> Result<bool> {
+        let (len, of_type) = self.de.read_container_header()?;
+        // every element is the type marker alone, so one of them tells
+        if let Some(marker) = of_type.filter(|&marker| is_empty_payload(marker)) {
+            return match len {
+                Some(0) => Ok(true),
+                _ => self.matches(elem, marker),
+            };
+        }
+        let mut index = 0;
+        while let Some(marker) = self.next_element(len, of_type, index, Marker::ArrayEnd)? {
+            if !self.matches(elem, marker)? {
+                return Ok(false);
+            }
+            index += 1;
+        }
+        Ok(true)
+    }
+
+    fn matches_object(&mut self, fields: &[(String, Shape)]) -> Result<bool> {
+        let (len, of_type) = self.de.read_container_header()?;
+        let mut seen = vec![false; fields.len()];
+        let mut index = 0;
+        loop {
+            if len.is_none() && self.de.peek_marker()? == Marker::ObjectEnd {
+                self.de.read_marker()?;
+                break;
+            }
+            if len == Some(index) {
+                break;
+            }
+            let field = {
+                let key = self.de.read_str()?;
+                fields.iter().position(|(name, _)| *name == *key)
+            };
+            let marker = match of_type {
+                Some(marker) => marker,
+                None => self.de.read_marker()?,
+            };
+            match field {
+                Some(i) => {
+                    if !self.matches(&fields[i].1, marker)? {
+                        return Ok(false);
+                    }
+                    seen[i] = true;
+                }
+                None => {
+                    self.skip(marker)?;
+                }
+            }
+            index += 1;
+        }
+        Ok(seen.into_iter().all(|s| s))
+    }
+
+    // marker of the next element, or None once the container is exhausted
+    fn next_element(
+        &mut self,
+        len: Option<usize>,
+        of_type: Option<Marker>,
+        index: usize,
+        end: Marker,
+    ) -> Result<Option<Marker>> {
+        if let Some(len) = len {
+            if index == len {
+                return Ok(None);
+            }
+            return match of_type {
+                Some(marker) => Ok(Some(marker)),
+                None => self.de.read_marker().map(Some),
+            };
+        }
+        loop {
+            let marker = self.de.read_marker()?;
+            match marker {
+                m if m == end => return Ok(None),
+                Marker::NoOp => continue,
+                m => return Ok(Some(m)),
+            }
+        }
+    }
+
+    fn skip(&mut self, marker: Marker) -> Result<bool> {
+        self.skipped.clear();
+        self.de.read_raw_payload(marker, &mut self.skipped)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_bytes;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct User {
+        id: u32,
+        name: String,
+        tags: Vec<String>,
+        email: Option<String>,
+    }
+
+    fn user_shape() -> Shape {
+        Shape::Object(vec![
+            ("id".to_string(), Shape::Int),
+            ("name".to_string(), Shape::Str),
+            ("tags".to_string(), Shape::Array(Box::new(Shape::Str))),
+            ("email".to_string(), Shape::Nullable(Box::new(Shape::Str))),
+        ])
+    }
+
+    #[test]
+    fn matching_document_against_its_shape_produces_true() {
+        let user = User {
+            id: 7,
+            name: "ann".to_string(),
+            tags: vec!["a".to_string(), "b".to_string()],
+            email: None,
+        };
+        let bytes = to_bytes(&user).unwrap();
+        assert!(matches_shape(&bytes, &user_shape()).unwrap());
+    }
+
+    #[test]
+    fn matching_document_with_wrong_field_type_produces_false() {
+        let shape = Shape::Object(vec![("id".to_string(), Shape::Int)]);
+        assert!(!matches_shape(b"{U\x02idSU\x05seven}", &shape).unwrap());
+    }
+
+    #[test]
+    fn matching_document_with_missing_field_produces_false() {
+        let shape = Shape::Object(vec![
+            ("id".to_string(), Shape::Int),
+            ("name".to_string(), Shape::Str),
+        ]);
+        assert!(!matches_shape(b"{U\x02idU\x07}", &shape).unwrap());
+    }
+
+    #[test]
+    fn matching_document_skips_unknown_fields() {
+        let shape = Shape::Object(vec![("id".to_string(), Shape::Int)]);
+        let bytes = b"{U\x05extra[U\x01U\x02]U\x02idU\x07}";
+        assert!(matches_shape(bytes, &shape).unwrap());
+    }
+
+    #[test]
+    fn matching_typed_array_checks_element_type() {
+        let ints = b"[$U#U\x03\x01\x02\x03";
+        assert!(matches_shape(ints, &Shape::Array(Box::new(Shape::Int))).unwrap());
+        assert!(!matches_shape(ints, &Shape::Array(Box::new(Shape::Str))).unwrap());
+    }
+
+    #[test]
+    fn matching_typed_array_without_payloads_checks_type_once() {
+        let nulls = b"[$Z#l\x00\x0f\x42\x40";
+        assert!(matches_shape(nulls, &Shape::Array(Box::new(Shape::Null))).unwrap());
+        assert!(!matches_shape(nulls, &Shape::Array(Box::new(Shape::Bool))).unwrap());
+        assert!(matches_shape(b"[$T#i\x00", &Shape::Array(Box::new(Shape::Int))).unwrap());
+
+        // beyond the cap on such counts
+        let huge = b"[$Z#L\x00\x00\x01\x00\x00\x00\x00\x00";
+        assert!(matches_shape(huge, &Shape::Array(Box::new(Shape::Null))).is_err());
+    }
+
+    #[test]
+    fn matching_malformed_document_produces_error() {
+        let shape = Shape::Array(Box::new(Shape::Int));
+        assert!(matches_shape(b"[U\x01", &shape).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Reads and writes values over [`tokio::io::AsyncRead`] and
+//! [`tokio::io::AsyncWrite`], such as sockets, without blocking a runtime
+//! worker.
+//!
+//! ```ignore
+//! let mut reader = serde_ub_json::tokio::AsyncReader::new(read_half);
+//! while let Some(request) = reader.read_value::<Request>().await? {
+//!     serde_ub_json::tokio::to_writer_async(&mut write_half, &handle(request)).await?;
+//! }
+//! ```
+use std::pin::Pin;
+
+use ::tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::incremental::{read_value, IncrementalDecoder};
+use crate::ser::{to_bytes_in_with_config, SerializerConfig};
+use crate::{Error, Result};
+
+// bytes asked from the reader at a time
+const CHUNK_LEN: usize = 8 * 1024;
+
+/// Serializes a value and writes it to `writer`.
+///
+/// Serde serializes synchronously, so the value is encoded into memory first
+/// and then written as one buffer. The writer is not flushed.
+pub async fn to_writer_async<W, T>(writer: &mut W, value: &T) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+        T: ?Sized + Serialize,
+{
+    to_writer_async_with_config(writer, value, &SerializerConfig::default()).await
+}
+
+pub async fn to_writer_async_with_config<W, T>(writer: &mut W, value: &T, config: &SerializerConfig) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+        T: ?Sized + Serialize,
+{
+    let mut bytes = Vec::new();
+    to_bytes_in_with_config(value, &mut bytes, config)?;
+    writer.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Reads one value from `reader`.
+///
+/// Bytes the reader delivers past the end of the value are dropped; use an
+/// [`AsyncReader`] to read a stream of values.
+pub async fn from_reader_async<R, T>(reader: &mut R) -> Result<T>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+        T: DeserializeOwned,
+{
+    AsyncReader::new(reader).read_value().await?.ok_or(Error::Eof)
+}
+
+/// Reads a stream of values from an [`AsyncRead`], decoding each as soon as
+/// its last byte has arrived, however the input is split across reads.
+pub struct AsyncReader<R> {
+    reader: R,
+    decoder: IncrementalDecoder,
+    chunk: Vec<u8>,
+}
+
+impl<R> AsyncReader<R>
+    where
+        R: AsyncRead + Unpin,
+{
+    pub fn new(reader: R) -> AsyncReader<R> {
+        AsyncReader::with_decoder(reader, IncrementalDecoder::new())
+    }
+
+    /// Uses `decoder`, e.g. one with a value size limit, for the input.
+    pub fn with_decoder(reader: R, decoder: IncrementalDecoder) -> AsyncReader<R> {
+        AsyncReader {
+            reader,
+            decoder,
+            chunk: vec![0; CHUNK_LEN],
+        }
+    }
+
+    /// Reads the next value, or `None` once the input ends between values.
+    /// Input ending inside a value is reported as `Error::UnexpectedEof`.
+    pub async fn read_value<T>(&mut self) -> Result<Option<T>>
+        where
+            T: DeserializeOwned,
+    {
+        let reader = &mut self.reader;
+        read_value(&mut self.decoder, &mut self.chunk, |cx, buf| {
+            let mut buf = ReadBuf::new(buf);
+            Pin::new(&mut *reader).poll_read(cx, &mut buf).map_ok(|()| buf.filled().len())
+        })
+        .await
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the reader; bytes read but not decoded yet are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_on::block_on;
+    use crate::{from_bytes, to_bytes};
+
+    #[test]
+    fn writing_value_async_produces_same_bytes() {
+        let value = (1u8, "two".to_string(), vec![3.0f64]);
+        let mut out = Vec::new();
+        block_on(to_writer_async(&mut out, &value)).unwrap();
+        assert_eq!(out, to_bytes(&value).unwrap());
+        assert_eq!(from_bytes::<(u8, String, Vec<f64>)>(&out).unwrap(), value);
+    }
+
+    #[test]
+    fn writing_values_async_appends_them() {
+        let config = SerializerConfig::canonical();
+        let mut out = Vec::new();
+        block_on(async {
+            to_writer_async_with_config(&mut out, &1u8, &config).await?;
+            to_writer_async_with_config(&mut out, &2u8, &config).await
+        })
+        .unwrap();
+        assert_eq!(out, b"U\x01U\x02");
+    }
+
+    #[test]
+    fn reading_value_async_produces_value() {
+        let mut input: &[u8] = b"SU\x03abcU\x01";
+        let value: String = block_on(from_reader_async(&mut input)).unwrap();
+        assert_eq!(value, "abc");
+    }
+
+    #[test]
+    fn reading_values_async_produces_stream() {
+        let mut bytes = to_bytes(&vec![1u8, 2]).unwrap();
+        bytes.extend_from_slice(b"NSU\x01x");
+        let mut reader = AsyncReader::new(&bytes[..]);
+        block_on(async {
+            assert_eq!(reader.read_value::<Vec<u8>>().await.unwrap(), Some(vec![1, 2]));
+            assert_eq!(reader.read_value::<String>().await.unwrap(), Some("x".to_string()));
+            assert_eq!(reader.read_value::<String>().await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn reading_truncated_value_async_produces_error() {
+        let mut reader = AsyncReader::new(&b"[U\x01"[..]);
+        let result = block_on(reader.read_value::<Vec<u8>>());
+        assert!(matches!(result, Err(Error::UnexpectedEof { .. })));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::de::from_bytes;
+use crate::ser::{to_bytes_with_config, SerializerConfig};
+use crate::value::Value;
+use crate::Result;
+
+/// Re-encodes a UBJSON document under `config`, e.g. to turn a verbose
+/// document into a compact one with `optimize_containers`.
+pub fn transcode(input: &[u8], config: &SerializerConfig) -> Result<Vec<u8>> {
+    let value: Value = from_bytes(input)?;
+    to_bytes_with_config(&value, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::to_bytes;
+
+    #[test]
+    fn transcoding_array_with_optimize_containers_produces_typed_array() {
+        let input = to_bytes(&vec![1i32, 2, 3]).unwrap();
+        assert_eq!(&input[..3], b"[#L");
+
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+        let out = transcode(&input, &config).unwrap();
+
+        let mut expected = b"[$l#L".to_vec();
+        expected.extend_from_slice(&3i64.to_be_bytes());
+        for v in [1i32, 2, 3] {
+            expected.extend_from_slice(&v.to_be_bytes());
+        }
+        assert_eq!(out, expected);
+        assert!(out.len() < input.len());
+
+        assert_eq!(from_bytes::<Vec<i32>>(&out).unwrap(), vec![1, 2, 3]);
+        assert_eq!(from_bytes::<Value>(&out).unwrap(), from_bytes::<Value>(&input).unwrap());
+    }
+
+    #[test]
+    fn transcoding_mixed_document_preserves_values() {
+        let mut input = b"{#i\x03".to_vec();
+        input.extend_from_slice(b"i\x01aSi\x03one");
+        input.extend_from_slice(b"i\x01bH\x69\x0512345");
+        input.extend_from_slice(b"i\x01c[Z");
+        input.extend_from_slice(b"Ti\x02]");
+
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+        let out = transcode(&input, &config).unwrap();
+
+        assert_eq!(from_bytes::<Value>(&out).unwrap(), Value::Object(vec![
+            ("a".to_string(), Value::String("one".to_string())),
+            ("b".to_string(), Value::Number("12345".to_string())),
+            ("c".to_string(), Value::Array(vec![Value::Null, Value::Bool(true), Value::I8(2)])),
+        ]));
+    }
+}
@@ -1,6 +1,26 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use crate::{Error, Result};
 
-#[derive(Clone, PartialEq)]
+// Newtype name under which `Value::Number` travels through serde, and the key
+// under which the deserializer hands high-precision numbers back.
+pub(crate) const NUMBER_TOKEN: &str = "$serde_ub_json::private::Number";
+
+// Unit struct name under which `Value::NoOp` travels through serde.
+pub(crate) const NOOP_TOKEN: &str = "$serde_ub_json::private::NoOp";
+
+// Newtype name under which `Value::TypedArray` asks for the strongly typed form.
+pub(crate) const TYPED_ARRAY_TOKEN: &str = "$serde_ub_json::private::TypedArray";
+
+// Newtype name under which `Uniform` asks for the strongly typed form.
+pub(crate) const UNIFORM_TOKEN: &str = "$serde_ub_json::private::Uniform";
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Null,
     NoOp,
@@ -16,10 +36,430 @@ pub enum Value {
     Char(char),
     String(String),
     Array(Vec<Value>),
+    /// Array written in the strongly typed form (`[$<type>#<count>`) with the
+    /// given element marker, see [`Value::typed_array`]. Written as a plain
+    /// array if its elements stop sharing that marker.
+    TypedArray(Marker, Vec<Value>),
     Object(Vec<(String, Value)>),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+impl Value {
+    /// Builds an array that is written in the strongly typed form, regardless
+    /// of `optimize_containers`.
+    ///
+    /// All elements must be scalars written with the same marker, so e.g.
+    /// `true` and `false` cannot be mixed. Fails with `Error::Expected` naming
+    /// the marker of the first element otherwise, and with
+    /// `Error::InvalidLength` for an empty array, whose type is unknown.
+    pub fn typed_array(values: Vec<Value>) -> Result<Value> {
+        let marker = match values.first() {
+            Some(value) => value.scalar_marker().ok_or(Error::InvalidMarker)?,
+            None => return Err(Error::InvalidLength),
+        };
+        if values.iter().any(|value| value.scalar_marker() != Some(marker)) {
+            return Err(Error::Expected(vec![marker]));
+        }
+        Ok(Value::TypedArray(marker, values))
+    }
+
+    // marker a scalar is written with
+    fn scalar_marker(&self) -> Option<Marker> {
+        let marker = match self {
+            Value::Null => Marker::Null,
+            Value::Bool(true) => Marker::True,
+            Value::Bool(false) => Marker::False,
+            Value::I8(_) => Marker::I8,
+            Value::U8(_) => Marker::U8,
+            Value::I16(_) => Marker::I16,
+            Value::I32(_) => Marker::I32,
+            Value::I64(_) => Marker::I64,
+            Value::F32(_) => Marker::F32,
+            Value::F64(_) => Marker::F64,
+            Value::Number(_) => Marker::Number,
+            Value::Char(_) => Marker::Char,
+            Value::String(_) => Marker::String,
+            Value::NoOp | Value::Array(_) | Value::TypedArray(..) | Value::Object(_) => return None,
+        };
+        Some(marker)
+    }
+
+    /// Applies `f` to this value and then to every nested value, depth-first.
+    ///
+    /// Containers are visited before their children, so a replacement made by
+    /// `f` is walked as well.
+    pub fn walk_mut<F>(&mut self, f: &mut F)
+        where
+            F: FnMut(&mut Value),
+    {
+        f(self);
+        match self {
+            Value::Array(values) | Value::TypedArray(_, values) => {
+                for value in values {
+                    value.walk_mut(f);
+                }
+            }
+            Value::Object(entries) => {
+                for (_, value) in entries {
+                    value.walk_mut(f);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns a copy in which every integer is stored in the narrowest variant
+    /// that holds it, e.g. `I64(5)` becomes `I8(5)`, so that it serializes
+    /// compactly.
+    pub fn deep_clone_compact(&self) -> Value {
+        let mut value = self.clone();
+        value.walk_mut(&mut |value| {
+            let n = match *value {
+                Value::U8(n) => n as i64,
+                Value::I16(n) => n as i64,
+                Value::I32(n) => n as i64,
+                Value::I64(n) => n,
+                _ => return,
+            };
+            *value = if let Ok(n) = i8::try_from(n) {
+                Value::I8(n)
+            } else if let Ok(n) = u8::try_from(n) {
+                Value::U8(n)
+            } else if let Ok(n) = i16::try_from(n) {
+                Value::I16(n)
+            } else if let Ok(n) = i32::try_from(n) {
+                Value::I32(n)
+            } else {
+                Value::I64(n)
+            };
+        });
+        value
+    }
+
+    /// Keeps only the array elements for which `f` returns `true`.
+    ///
+    /// Does nothing for objects and scalars.
+    pub fn retain<F>(&mut self, f: F)
+        where
+            F: FnMut(&Value) -> bool,
+    {
+        if let Value::Array(values) | Value::TypedArray(_, values) = self {
+            values.retain(f);
+        }
+    }
+
+    /// Keeps only the object entries for which `f` returns `true`.
+    ///
+    /// Does nothing for arrays and scalars.
+    pub fn retain_keys<F>(&mut self, mut f: F)
+        where
+            F: FnMut(&str, &Value) -> bool,
+    {
+        if let Value::Object(entries) = self {
+            entries.retain(|(key, value)| f(key, value));
+        }
+    }
+
+    /// Looks up a nested value by JSON Pointer (RFC 6901), e.g. `/a/0/b`.
+    ///
+    /// Returns `None` when any segment is missing.
+    pub fn pointer(&self, ptr: &str) -> Option<&Value> {
+        let mut target = self;
+        for segment in pointer_segments(ptr)? {
+            target = match target {
+                Value::Array(values) | Value::TypedArray(_, values) => values.get(parse_index(&segment)?)?,
+                Value::Object(entries) => entries.iter()
+                    .find(|(key, _)| *key == segment)
+                    .map(|(_, value)| value)?,
+                _ => return None,
+            };
+        }
+        Some(target)
+    }
+
+    /// Like [`pointer`](Value::pointer), but allows the value to be edited in place.
+    pub fn pointer_mut(&mut self, ptr: &str) -> Option<&mut Value> {
+        let mut target = self;
+        for segment in pointer_segments(ptr)? {
+            target = match target {
+                Value::Array(values) | Value::TypedArray(_, values) => values.get_mut(parse_index(&segment)?)?,
+                Value::Object(entries) => entries.iter_mut()
+                    .find(|(key, _)| *key == segment)
+                    .map(|(_, value)| value)?,
+                _ => return None,
+            };
+        }
+        Some(target)
+    }
+
+    /// Compares like `==`, except that numbers are equal when they have the same
+    /// value, whatever their variant, e.g. `Number("5")`, `I8(5)` and `F64(5.0)`.
+    pub fn numeric_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Array(a) | Value::TypedArray(_, a), Value::Array(b) | Value::TypedArray(_, b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.numeric_eq(b))
+            }
+            (Value::Object(a), Value::Object(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|((ka, a), (kb, b))| ka == kb && a.numeric_eq(b))
+            }
+            _ => match (self.as_numeric(), other.as_numeric()) {
+                (Some(a), Some(b)) => a.same_value(b),
+                (None, None) => self == other,
+                _ => false,
+            },
+        }
+    }
+
+    fn as_numeric(&self) -> Option<Numeric> {
+        let numeric = match *self {
+            Value::I8(n) => Numeric::Int(n as i128),
+            Value::U8(n) => Numeric::Int(n as i128),
+            Value::I16(n) => Numeric::Int(n as i128),
+            Value::I32(n) => Numeric::Int(n as i128),
+            Value::I64(n) => Numeric::Int(n as i128),
+            Value::F32(n) => Numeric::Float(n as f64),
+            Value::F64(n) => Numeric::Float(n),
+            Value::Number(ref digits) => match digits.parse::<i128>() {
+                Ok(n) => Numeric::Int(n),
+                Err(_) => Numeric::Float(digits.parse::<f64>().ok()?),
+            },
+            _ => return None,
+        };
+        Some(numeric)
+    }
+
+    /// Returns the decimal digits of a number without going through `f64`:
+    /// the stored string of `Number`, and the formatted value of integer and
+    /// finite float variants. Returns `None` for other values.
+    pub fn as_number_str(&self) -> Option<Cow<'_, str>> {
+        let digits = match *self {
+            Value::Number(ref digits) => return Some(Cow::Borrowed(digits)),
+            Value::I8(n) => n.to_string(),
+            Value::U8(n) => n.to_string(),
+            Value::I16(n) => n.to_string(),
+            Value::I32(n) => n.to_string(),
+            Value::I64(n) => n.to_string(),
+            Value::F32(n) if n.is_finite() => n.to_string(),
+            Value::F64(n) if n.is_finite() => n.to_string(),
+            _ => return None,
+        };
+        Some(Cow::Owned(digits))
+    }
+
+    /// Moves the entries out of an object, or returns `None` for other values.
+    pub fn into_object(self) -> Option<Vec<(String, Value)>> {
+        match self {
+            Value::Object(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Moves the elements out of an array, or returns `None` for other values.
+    pub fn into_array(self) -> Option<Vec<Value>> {
+        match self {
+            Value::Array(values) | Value::TypedArray(_, values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Numeric {
+    Int(i128),
+    Float(f64),
+}
+
+impl Numeric {
+    fn same_value(self, other: Numeric) -> bool {
+        match (self, other) {
+            (Numeric::Int(a), Numeric::Int(b)) => a == b,
+            (Numeric::Float(a), Numeric::Float(b)) => a == b,
+            (Numeric::Int(i), Numeric::Float(f)) | (Numeric::Float(f), Numeric::Int(i)) => {
+                f.fract() == 0.0 && f == i as f64 && f as i128 == i
+            }
+        }
+    }
+}
+
+// unescaped reference tokens, or None if the pointer is malformed
+fn pointer_segments(ptr: &str) -> Option<Vec<String>> {
+    if ptr.is_empty() {
+        return Some(Vec::new());
+    }
+
+    let rest = ptr.strip_prefix('/')?;
+    let segments = rest.split('/')
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    Some(segments)
+}
+
+// array indices are plain decimal numbers without leading zeros
+fn parse_index(segment: &str) -> Option<usize> {
+    if segment.len() > 1 && segment.starts_with('0') {
+        return None;
+    }
+    if !segment.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    segment.parse().ok()
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::NoOp => serializer.serialize_unit_struct(NOOP_TOKEN),
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::I8(v) => serializer.serialize_i8(*v),
+            Value::U8(v) => serializer.serialize_u8(*v),
+            Value::I16(v) => serializer.serialize_i16(*v),
+            Value::I32(v) => serializer.serialize_i32(*v),
+            Value::I64(v) => serializer.serialize_i64(*v),
+            Value::F32(v) => serializer.serialize_f32(*v),
+            Value::F64(v) => serializer.serialize_f64(*v),
+            Value::Number(v) => serializer.serialize_newtype_struct(NUMBER_TOKEN, v.as_str()),
+            Value::Char(v) => serializer.serialize_char(*v),
+            Value::String(v) => serializer.serialize_str(v),
+            Value::Array(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Value::TypedArray(_, values) => serializer.serialize_newtype_struct(TYPED_ARRAY_TOKEN, values),
+            Value::Object(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Value, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any UBJSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i8<E>(self, v: i8) -> std::result::Result<Value, E> {
+        Ok(Value::I8(v))
+    }
+
+    fn visit_i16<E>(self, v: i16) -> std::result::Result<Value, E> {
+        Ok(Value::I16(v))
+    }
+
+    fn visit_i32<E>(self, v: i32) -> std::result::Result<Value, E> {
+        Ok(Value::I32(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::I64(v))
+    }
+
+    fn visit_u8<E>(self, v: u8) -> std::result::Result<Value, E> {
+        Ok(Value::U8(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        match i64::try_from(v) {
+            Ok(v) => Ok(Value::I64(v)),
+            Err(_) => Ok(Value::Number(v.to_string())),
+        }
+    }
+
+    fn visit_f32<E>(self, v: f32) -> std::result::Result<Value, E> {
+        Ok(Value::F32(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::F64(v))
+    }
+
+    fn visit_char<E>(self, v: char) -> std::result::Result<Value, E> {
+        Ok(Value::Char(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Value, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Value, A::Error>
+        where
+            A: MapAccess<'de>,
+    {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key::<String>()? {
+            if entries.is_empty() && key == NUMBER_TOKEN {
+                return Ok(Value::Number(map.next_value()?));
+            }
+            if entries.is_empty() && key == TYPED_ARRAY_TOKEN {
+                let marker: char = map.next_value()?;
+                map.next_key::<String>()?;
+                let values: Vec<Value> = map.next_value()?;
+                let marker = Marker::try_from(marker as u8).map_err(serde::de::Error::custom)?;
+                return Ok(Value::TypedArray(marker, values));
+            }
+            entries.push((key, map.next_value()?));
+        }
+        Ok(Value::Object(entries))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Marker {
     Null = b'Z',
@@ -44,15 +484,45 @@ pub enum Marker {
     OfType = b'$',
 }
 
-impl Into<char> for Marker {
-    fn into(self) -> char {
-        self as u8 as char
+impl Marker {
+    /// `i`, `U`, `I`, `l` or `L`.
+    pub fn is_integer(self) -> bool {
+        matches!(self, Marker::I8 | Marker::U8 | Marker::I16 | Marker::I32 | Marker::I64)
+    }
+
+    /// `d` or `D`.
+    pub fn is_float(self) -> bool {
+        matches!(self, Marker::F32 | Marker::F64)
+    }
+
+    /// `[` or `{`.
+    pub fn is_container_start(self) -> bool {
+        matches!(self, Marker::ArrayStart | Marker::ObjectStart)
+    }
+
+    /// `]` or `}`.
+    pub fn is_container_end(self) -> bool {
+        matches!(self, Marker::ArrayEnd | Marker::ObjectEnd)
+    }
+
+    /// Marker of a complete value that is not a container.
+    pub fn is_scalar(self) -> bool {
+        self.is_integer() || self.is_float() || matches!(
+            self,
+            Marker::Null | Marker::True | Marker::False | Marker::Number | Marker::Char | Marker::String
+        )
+    }
+}
+
+impl From<Marker> for char {
+    fn from(marker: Marker) -> char {
+        marker as u8 as char
     }
 }
 
-impl Into<&[u8]> for Marker {
-    fn into(self) -> &'static [u8] {
-        match self {
+impl From<Marker> for &'static [u8] {
+    fn from(marker: Marker) -> &'static [u8] {
+        match marker {
             Marker::Null => b"Z",
             Marker::NoOp => b"N",
             Marker::True => b"T",
@@ -115,3 +585,284 @@ impl TryFrom<char> for Marker {
         Marker::try_from(value as u8)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compacting_value_narrows_integers_and_serializes_smaller() {
+        let value = Value::Object(vec![
+            ("small".to_string(), Value::I64(5)),
+            ("values".to_string(), Value::Array(vec![
+                Value::I64(-1),
+                Value::I64(200),
+                Value::I32(1000),
+                Value::I64(100_000),
+                Value::I64(i64::MAX),
+            ])),
+            ("name".to_string(), Value::String("x".to_string())),
+        ]);
+
+        let compact = value.deep_clone_compact();
+        assert_eq!(compact, Value::Object(vec![
+            ("small".to_string(), Value::I8(5)),
+            ("values".to_string(), Value::Array(vec![
+                Value::I8(-1),
+                Value::U8(200),
+                Value::I16(1000),
+                Value::I32(100_000),
+                Value::I64(i64::MAX),
+            ])),
+            ("name".to_string(), Value::String("x".to_string())),
+        ]));
+
+        let wide = crate::to_bytes(&value).unwrap();
+        let narrow = crate::to_bytes(&compact).unwrap();
+        assert!(narrow.len() < wide.len());
+    }
+
+    #[test]
+    fn converting_containers_into_inner_data_moves_entries_and_elements() {
+        let value = Value::Object(vec![("a".to_string(), Value::I8(1))]);
+        assert_eq!(value.clone().into_object(), Some(vec![("a".to_string(), Value::I8(1))]));
+        assert_eq!(value.into_array(), None);
+
+        let value = Value::Array(vec![Value::Null, Value::String("x".to_string())]);
+        assert_eq!(value.clone().into_array(), Some(vec![Value::Null, Value::String("x".to_string())]));
+        assert_eq!(value.into_object(), None);
+
+        assert_eq!(Value::I8(1).into_array(), None);
+        assert_eq!(Value::String("x".to_string()).into_object(), None);
+    }
+
+    #[test]
+    fn comparing_numerically_equal_values_of_different_variants_is_equal() {
+        let five = [
+            Value::Number("5".to_string()),
+            Value::I8(5),
+            Value::U8(5),
+            Value::I64(5),
+            Value::F32(5.0),
+            Value::F64(5.0),
+        ];
+        for a in &five {
+            for b in &five {
+                assert!(a.numeric_eq(b));
+            }
+        }
+
+        assert!(Value::Number("2.5".to_string()).numeric_eq(&Value::F64(2.5)));
+        assert!(Value::Number("12345678901234567890".to_string()).numeric_eq(&Value::Number("12345678901234567890".to_string())));
+
+        let a = Value::Object(vec![("a".to_string(), Value::Array(vec![Value::I8(1), Value::Null]))]);
+        let b = Value::Object(vec![("a".to_string(), Value::Array(vec![Value::F64(1.0), Value::Null]))]);
+        assert!(a.numeric_eq(&b));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn comparing_different_numbers_or_non_numbers_is_not_equal() {
+        assert!(!Value::I8(5).numeric_eq(&Value::F64(5.5)));
+        assert!(!Value::Number("6".to_string()).numeric_eq(&Value::I8(5)));
+        assert!(!Value::I8(5).numeric_eq(&Value::String("5".to_string())));
+        assert!(!Value::I64(i64::MAX).numeric_eq(&Value::F64(i64::MAX as f64)));
+
+        let a = Value::Array(vec![Value::I8(1)]);
+        let b = Value::Array(vec![Value::I8(1), Value::I8(2)]);
+        assert!(!a.numeric_eq(&b));
+    }
+
+    #[test]
+    fn building_typed_array_from_homogeneous_values_produces_typed_output() {
+        let value = Value::typed_array(vec![Value::I16(1), Value::I16(2)]).unwrap();
+        assert_eq!(value, Value::TypedArray(Marker::I16, vec![Value::I16(1), Value::I16(2)]));
+
+        let out = crate::to_bytes(&value).unwrap();
+        let mut expected = b"[$I#L".to_vec();
+        expected.extend_from_slice(&2i64.to_be_bytes());
+        expected.extend_from_slice(&[0, 1, 0, 2]);
+        assert_eq!(out, expected);
+
+        let decoded = crate::from_bytes::<Value>(&out).unwrap();
+        assert!(decoded.numeric_eq(&value));
+    }
+
+    #[test]
+    fn building_typed_array_from_mixed_values_produces_error() {
+        let result = Value::typed_array(vec![Value::I16(1), Value::I32(2)]);
+        assert!(matches!(result, Err(Error::Expected(markers)) if markers == vec![Marker::I16]));
+
+        let result = Value::typed_array(vec![Value::Bool(true), Value::Bool(false)]);
+        assert!(matches!(result, Err(Error::Expected(_))));
+
+        let result = Value::typed_array(vec![Value::Array(vec![])]);
+        assert!(matches!(result, Err(Error::InvalidMarker)));
+
+        assert!(matches!(Value::typed_array(vec![]), Err(Error::InvalidLength)));
+    }
+
+    #[test]
+    fn classifying_markers_puts_every_marker_in_its_group() {
+        let mut markers = 0;
+        for byte in 0..=u8::MAX {
+            let marker = match Marker::try_from(byte) {
+                Ok(marker) => marker,
+                Err(_) => continue,
+            };
+            markers += 1;
+
+            let groups = (
+                marker.is_integer(),
+                marker.is_float(),
+                marker.is_container_start(),
+                marker.is_container_end(),
+                marker.is_scalar(),
+            );
+            let expected = match byte {
+                b'i' | b'U' | b'I' | b'l' | b'L' => (true, false, false, false, true),
+                b'd' | b'D' => (false, true, false, false, true),
+                b'[' | b'{' => (false, false, true, false, false),
+                b']' | b'}' => (false, false, false, true, false),
+                b'Z' | b'T' | b'F' | b'H' | b'C' | b'S' => (false, false, false, false, true),
+                b'N' | b'#' | b'$' => (false, false, false, false, false),
+                _ => unreachable!(),
+            };
+            assert_eq!(groups, expected, "marker {}", char::from(marker));
+        }
+        assert_eq!(markers, 20);
+    }
+
+    #[test]
+    fn walking_value_visits_containers_and_mutates_string_leaves() {
+        let mut value = Value::Object(vec![
+            ("name".to_string(), Value::String("John".to_string())),
+            ("tags".to_string(), Value::Array(vec![
+                Value::String("a".to_string()),
+                Value::I8(1),
+                Value::Array(vec![Value::String("b".to_string())]),
+            ])),
+        ]);
+
+        let mut containers = 0;
+        value.walk_mut(&mut |v| match v {
+            Value::String(s) => *s = "***".to_string(),
+            Value::Array(_) | Value::Object(_) => containers += 1,
+            _ => {}
+        });
+
+        assert_eq!(containers, 3);
+        assert_eq!(value, Value::Object(vec![
+            ("name".to_string(), Value::String("***".to_string())),
+            ("tags".to_string(), Value::Array(vec![
+                Value::String("***".to_string()),
+                Value::I8(1),
+                Value::Array(vec![Value::String("***".to_string())]),
+            ])),
+        ]));
+    }
+
+    #[test]
+    fn editing_value_by_pointer_changes_nested_value() {
+        let mut value = Value::Object(vec![
+            ("a".to_string(), Value::Array(vec![
+                Value::Object(vec![("b".to_string(), Value::I8(1))]),
+            ])),
+        ]);
+
+        *value.pointer_mut("/a/0/b").unwrap() = Value::String("changed".to_string());
+
+        assert_eq!(value.pointer("/a/0/b"), Some(&Value::String("changed".to_string())));
+        assert_eq!(value, Value::Object(vec![
+            ("a".to_string(), Value::Array(vec![
+                Value::Object(vec![("b".to_string(), Value::String("changed".to_string()))]),
+            ])),
+        ]));
+        assert_eq!(value.pointer(""), Some(&value.clone()));
+    }
+
+    #[test]
+    fn looking_up_missing_pointer_produces_none() {
+        let mut value = Value::Object(vec![
+            ("a".to_string(), Value::Array(vec![Value::I8(1)])),
+            ("c/d".to_string(), Value::Null),
+        ]);
+
+        assert!(value.pointer_mut("/a/1").is_none());
+        assert!(value.pointer_mut("/a/x").is_none());
+        assert!(value.pointer_mut("/b").is_none());
+        assert!(value.pointer_mut("/a/0/b").is_none());
+        assert!(value.pointer_mut("a").is_none());
+        assert_eq!(value.pointer("/c~1d"), Some(&Value::Null));
+    }
+
+    #[test]
+    fn retaining_odd_elements_removes_even_elements_from_array() {
+        let mut value = Value::Array((1..=6).map(Value::I8).collect());
+        value.retain(|v| !matches!(v, Value::I8(n) if n % 2 == 0));
+        assert_eq!(value, Value::Array(vec![Value::I8(1), Value::I8(3), Value::I8(5)]));
+
+        let mut scalar = Value::I8(2);
+        scalar.retain(|_| false);
+        assert_eq!(scalar, Value::I8(2));
+    }
+
+    #[test]
+    fn retaining_keys_removes_matching_entries_from_object() {
+        let mut value = Value::Object(vec![
+            ("id".to_string(), Value::I8(1)),
+            ("_internal".to_string(), Value::Null),
+            ("name".to_string(), Value::String("John".to_string())),
+        ]);
+        value.retain_keys(|key, _| !key.starts_with('_'));
+        assert_eq!(value, Value::Object(vec![
+            ("id".to_string(), Value::I8(1)),
+            ("name".to_string(), Value::String("John".to_string())),
+        ]));
+
+        let mut array = Value::Array(vec![Value::Null]);
+        array.retain_keys(|_, _| false);
+        assert_eq!(array, Value::Array(vec![Value::Null]));
+    }
+
+    #[test]
+    fn deserializing_typed_array_with_preserve_typed_arrays_round_trips_bytes() {
+        use crate::{from_bytes_with_config, to_bytes, to_bytes_with_config, DeserializerConfig, SerializerConfig};
+
+        let config = SerializerConfig {
+            optimize_containers: true,
+            ..Default::default()
+        };
+        let bytes = to_bytes_with_config(&vec![vec![1i8, 2, 3], vec![]], &config).unwrap();
+        assert_eq!(&bytes[..5], b"[#L\0\0");
+
+        let preserving = DeserializerConfig {
+            preserve_typed_arrays: true,
+            ..Default::default()
+        };
+        let value: Value = from_bytes_with_config(&bytes, &preserving).unwrap();
+        assert_eq!(value, Value::Array(vec![
+            Value::TypedArray(Marker::I8, vec![Value::I8(1), Value::I8(2), Value::I8(3)]),
+            Value::Array(vec![]),
+        ]));
+        assert_eq!(to_bytes(&value).unwrap(), bytes);
+
+        // without the flag the element type is lost
+        let value: Value = crate::from_bytes(&bytes).unwrap();
+        assert!(matches!(&value.into_array().unwrap()[0], Value::Array(_)));
+    }
+
+    #[test]
+    fn extracting_number_str_produces_exact_digits() {
+        let digits = "12345678901234567890.0000000001";
+        let value = Value::Number(digits.to_string());
+        assert!(matches!(value.as_number_str(), Some(Cow::Borrowed(s)) if s == digits));
+
+        assert_eq!(Value::I64(i64::MIN).as_number_str().unwrap(), "-9223372036854775808");
+        assert_eq!(Value::U8(200).as_number_str().unwrap(), "200");
+        assert_eq!(Value::F64(0.1).as_number_str().unwrap(), "0.1");
+
+        assert_eq!(Value::F64(f64::NAN).as_number_str(), None);
+        assert_eq!(Value::String("1".to_string()).as_number_str(), None);
+    }
+}
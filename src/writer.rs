@@ -0,0 +1,233 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::ser::{Formatter, FormatterMode, Serializer, SerializerConfig, SimpleFormatter};
+use crate::value::Marker;
+use crate::Result;
+
+/// Writes an unsized array (`[` ... `]`) one element at a time, for output
+/// that is produced over a long time and never held in memory as a whole.
+///
+/// The array is only closed by [`finish`](ArrayWriter::finish); dropping the
+/// writer leaves it open. An element that fails to serialize may leave a
+/// partial encoding behind, so the output should be discarded then.
+pub struct ArrayWriter<W> {
+    ser: Serializer<SimpleFormatter<W>>,
+    len: usize,
+}
+
+impl<W> ArrayWriter<W>
+    where
+        W: Write,
+{
+    /// Writes the start marker.
+    pub fn new(writer: W) -> Result<ArrayWriter<W>> {
+        ArrayWriter::with_config(writer, SerializerConfig::default())
+    }
+
+    /// Like [`new`](ArrayWriter::new), serializing the elements under `config`.
+    pub fn with_config(writer: W, config: SerializerConfig) -> Result<ArrayWriter<W>> {
+        let mut ser = Serializer::with_config(SimpleFormatter::new(writer), config);
+        ser.formatter_mut().mark(Marker::ArrayStart)?;
+        Ok(ArrayWriter { ser, len: 0 })
+    }
+
+    pub fn push<T>(&mut self, value: &T) -> Result<()>
+        where
+            T: ?Sized + Serialize,
+    {
+        value.serialize(&mut self.ser)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Number of elements pushed so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.ser.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.ser.get_mut()
+    }
+
+    /// Writes the end marker, flushes the writer and returns it.
+    pub fn finish(mut self) -> Result<W> {
+        self.ser.formatter_mut().mark(Marker::ArrayEnd)?;
+        let mut writer = self.ser.into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+/// Writes an unsized object (`{` ... `}`) one entry at a time, the
+/// counterpart of [`ArrayWriter`] for key/value pairs known only at runtime.
+///
+/// Keys are not checked for duplicates. As with `ArrayWriter`, only
+/// [`finish`](ObjectWriter::finish) closes the object.
+pub struct ObjectWriter<W> {
+    ser: Serializer<SimpleFormatter<W>>,
+    len: usize,
+}
+
+impl<W> ObjectWriter<W>
+    where
+        W: Write,
+{
+    /// Writes the start marker.
+    pub fn new(writer: W) -> Result<ObjectWriter<W>> {
+        ObjectWriter::with_config(writer, SerializerConfig::default())
+    }
+
+    /// Like [`new`](ObjectWriter::new), serializing the entries under `config`.
+    pub fn with_config(writer: W, config: SerializerConfig) -> Result<ObjectWriter<W>> {
+        let mut ser = Serializer::with_config(SimpleFormatter::new(writer), config);
+        ser.formatter_mut().mark(Marker::ObjectStart)?;
+        Ok(ObjectWriter { ser, len: 0 })
+    }
+
+    /// Writes a key, which must serialize as a string (or, with
+    /// `coerce_int_keys`, a primitive), followed by its value.
+    pub fn entry<K, V>(&mut self, key: &K, value: &V) -> Result<()>
+        where
+            K: ?Sized + Serialize,
+            V: ?Sized + Serialize,
+    {
+        self.ser.formatter_mut().set_mode(FormatterMode::Key);
+        let result = key.serialize(&mut self.ser);
+        self.ser.formatter_mut().set_mode(FormatterMode::Value);
+        result?;
+
+        value.serialize(&mut self.ser)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Number of entries written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.ser.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.ser.get_mut()
+    }
+
+    /// Writes the end marker, flushes the writer and returns it.
+    pub fn finish(mut self) -> Result<W> {
+        self.ser.formatter_mut().mark(Marker::ObjectEnd)?;
+        let mut writer = self.ser.into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::{from_bytes, Error};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        level: u8,
+        message: String,
+    }
+
+    #[test]
+    fn pushing_elements_produces_unsized_array() {
+        let mut writer = ArrayWriter::new(Vec::new()).unwrap();
+        assert_eq!(writer.get_ref(), b"[");
+        writer.push(&1u8).unwrap();
+        writer.push("two").unwrap();
+        assert_eq!(writer.len(), 2);
+        let out = writer.finish().unwrap();
+        assert_eq!(out, b"[U\x01SL\0\0\0\0\0\0\0\x03two]");
+    }
+
+    #[test]
+    fn pushing_records_round_trips() {
+        let records: Vec<Record> = (0..3)
+            .map(|level| Record { level, message: format!("event {}", level) })
+            .collect();
+
+        let config = SerializerConfig::canonical();
+        let mut writer = ArrayWriter::with_config(Vec::new(), config).unwrap();
+        for record in &records {
+            writer.push(record).unwrap();
+        }
+        let out = writer.finish().unwrap();
+        assert_eq!(from_bytes::<Vec<Record>>(&out).unwrap(), records);
+    }
+
+    #[test]
+    fn finishing_empty_writer_produces_empty_array() {
+        let writer = ArrayWriter::new(Vec::new()).unwrap();
+        assert!(writer.is_empty());
+        let out = writer.finish().unwrap();
+        assert_eq!(out, b"[]");
+        assert!(from_bytes::<Vec<u8>>(&out).unwrap().is_empty());
+    }
+
+    #[test]
+    fn writing_entries_produces_unsized_object() {
+        let mut writer = ObjectWriter::new(Vec::new()).unwrap();
+        writer.entry("a", &1u8).unwrap();
+        writer.entry("b", &vec![true]).unwrap();
+        assert_eq!(writer.len(), 2);
+        let out = writer.finish().unwrap();
+
+        let mut expected = b"{L\0\0\0\0\0\0\0\x01aU\x01L\0\0\0\0\0\0\0\x01b[#L".to_vec();
+        expected.extend_from_slice(&1i64.to_be_bytes());
+        expected.extend_from_slice(b"T}");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn writing_entries_round_trips_as_map() {
+        let config = SerializerConfig::canonical();
+        let mut writer = ObjectWriter::with_config(Vec::new(), config).unwrap();
+        for level in 0..3u8 {
+            let record = Record { level, message: format!("event {}", level) };
+            writer.entry(&format!("r{}", level), &record).unwrap();
+        }
+        let out = writer.finish().unwrap();
+
+        let map: BTreeMap<String, Record> = from_bytes(&out).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["r2"].message, "event 2");
+    }
+
+    #[test]
+    fn writing_entry_with_non_string_key_produces_error() {
+        let mut writer = ObjectWriter::new(Vec::new()).unwrap();
+        assert!(matches!(writer.entry(&1u8, &1u8), Err(Error::InvalidKey { found: "u8" })));
+        assert!(writer.is_empty());
+
+        let config = SerializerConfig {
+            coerce_int_keys: true,
+            ..Default::default()
+        };
+        let mut writer = ObjectWriter::with_config(Vec::new(), config).unwrap();
+        writer.entry(&7u8, "seven").unwrap();
+        let map: BTreeMap<String, String> = from_bytes(&writer.finish().unwrap()).unwrap();
+        assert_eq!(map["7"], "seven");
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use serde_ub_json::{
+    from_bytes, to_bytes, write_smallest_len, CountingFormatter, Formatter, FormatterMode, Marker,
+    Serializer, SerializerConfig, SimpleFormatter,
+};
+
+/// Writes like `SimpleFormatter`, but lengths up to 255 as `U`.
+struct UnsignedLengths {
+    inner: SimpleFormatter<Vec<u8>>,
+}
+
+impl Formatter for UnsignedLengths {
+    fn set_mode(&mut self, mode: FormatterMode) {
+        self.inner.set_mode(mode)
+    }
+
+    fn get_mode(&mut self) -> FormatterMode {
+        self.inner.get_mode()
+    }
+
+    fn raw(&mut self, v: &[u8]) -> std::io::Result<()> {
+        self.inner.raw(v)
+    }
+
+    fn bool(&mut self, v: bool) -> std::io::Result<()> {
+        self.inner.bool(v)
+    }
+
+    fn u8(&mut self, v: u8) -> std::io::Result<()> {
+        self.inner.u8(v)
+    }
+
+    fn u16(&mut self, v: u16) -> std::io::Result<()> {
+        self.inner.u16(v)
+    }
+
+    fn u32(&mut self, v: u32) -> std::io::Result<()> {
+        self.inner.u32(v)
+    }
+
+    fn i8(&mut self, v: i8) -> std::io::Result<()> {
+        self.inner.i8(v)
+    }
+
+    fn i16(&mut self, v: i16) -> std::io::Result<()> {
+        self.inner.i16(v)
+    }
+
+    fn i32(&mut self, v: i32) -> std::io::Result<()> {
+        self.inner.i32(v)
+    }
+
+    fn i64(&mut self, v: i64) -> std::io::Result<()> {
+        self.inner.i64(v)
+    }
+
+    fn f32(&mut self, v: f32) -> std::io::Result<()> {
+        self.inner.f32(v)
+    }
+
+    fn f64(&mut self, v: f64) -> std::io::Result<()> {
+        self.inner.f64(v)
+    }
+
+    fn mark(&mut self, marker: Marker) -> std::io::Result<()> {
+        self.inner.mark(marker)
+    }
+
+    fn len(&mut self, v: usize) -> std::io::Result<()> {
+        match u8::try_from(v) {
+            Ok(v) => self.inner.u8(v),
+            Err(_) => write_smallest_len(&mut self.inner, v),
+        }
+    }
+}
+
+fn value() -> BTreeMap<String, Vec<u16>> {
+    let mut map = BTreeMap::new();
+    map.insert("short".to_string(), vec![1, 2, 3]);
+    map.insert("long".to_string(), (0..300).collect());
+    map
+}
+
+#[test]
+fn custom_formatter_output_reads_back() {
+    let mut serializer = Serializer::new(UnsignedLengths { inner: SimpleFormatter::new(Vec::new()) });
+    value().serialize(&mut serializer).unwrap();
+    let out = serializer.into_formatter().inner.into_inner();
+
+    assert!(out.starts_with(b"{#U\x02U\x04long[#I\x01\x2c"));
+    assert_eq!(from_bytes::<BTreeMap<String, Vec<u16>>>(&out).unwrap(), value());
+}
+
+#[test]
+fn custom_formatter_writes_buffered_elements() {
+    let config = SerializerConfig { optimize_containers: true, ..Default::default() };
+    let formatter = UnsignedLengths { inner: SimpleFormatter::new(Vec::new()) };
+    let mut serializer = Serializer::with_config(formatter, config);
+    value().serialize(&mut serializer).unwrap();
+    let out = serializer.into_formatter().inner.into_inner();
+
+    assert!(out.starts_with(b"{#U\x02U\x04long[#I\x01\x2cU\x00"));
+    assert!(out.ends_with(b"U\x05short[$U#U\x03\x01\x02\x03"));
+    assert_eq!(from_bytes::<BTreeMap<String, Vec<u16>>>(&out).unwrap(), value());
+}
+
+#[test]
+fn counting_formatter_counts_encoded_bytes() {
+    let mut serializer = Serializer::new(CountingFormatter::new());
+    value().serialize(&mut serializer).unwrap();
+    assert_eq!(serializer.into_formatter().count(), to_bytes(&value()).unwrap().len());
+}
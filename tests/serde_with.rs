@@ -0,0 +1,49 @@
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use serde_ub_json::{from_bytes, to_bytes, to_bytes_with_config, SerializerConfig};
+
+#[serde_as]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Settings {
+    #[serde_as(as = "DisplayFromStr")]
+    port: u16,
+    #[serde_as(as = "DisplayFromStr")]
+    host: Ipv4Addr,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    limits: Vec<u64>,
+}
+
+fn settings() -> Settings {
+    Settings {
+        port: 8080,
+        host: Ipv4Addr::new(127, 0, 0, 1),
+        limits: vec![1, 20, 300],
+    }
+}
+
+#[test]
+fn display_from_str_fields_round_trip_as_strings() {
+    let value = settings();
+    let out = to_bytes(&value).unwrap();
+
+    // numbers travel as their string form
+    assert!(out.windows(4).any(|w| w == b"8080"));
+    assert_eq!(from_bytes::<Settings>(&out).unwrap(), value);
+}
+
+#[test]
+fn display_from_str_fields_round_trip_in_typed_string_containers() {
+    let config = SerializerConfig {
+        optimize_containers: true,
+        ..Default::default()
+    };
+    let value = settings();
+    let out = to_bytes_with_config(&value, &config).unwrap();
+
+    // the limits are all strings, so the array is typed
+    assert!(out.windows(3).any(|w| w == b"[$S"));
+    assert_eq!(from_bytes::<Settings>(&out).unwrap(), value);
+}